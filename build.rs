@@ -1,9 +1,15 @@
+use sha2::{Digest, Sha256};
 use std::env;
 use std::error::Error;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+#[path = "src/arch.rs"]
+mod arch;
+
+use arch::Arch;
+
 const NODE_EXPORTER_VERSION: &str = "1.7.0";
 const WINDOWS_EXPORTER_VERSION: &str = "0.25.1";
 
@@ -14,9 +20,15 @@ fn main() -> Result<(), Box<dyn Error>> {
     println!("cargo:rerun-if-env-changed=NODE_EXPORTER_BUILD_URL");
     println!("cargo:rerun-if-env-changed=WINDOWS_EXPORTER_BUILD_FILE");
     println!("cargo:rerun-if-env-changed=WINDOWS_EXPORTER_BUILD_URL");
+    println!("cargo:rerun-if-env-changed=PROCESS_CPU_AGENT_BUILD_SHA256");
+    println!("cargo:rerun-if-env-changed=NODE_EXPORTER_BUILD_SHA256");
+    println!("cargo:rerun-if-env-changed=WINDOWS_EXPORTER_BUILD_SHA256");
 
     let out_dir = PathBuf::from(env::var("OUT_DIR")?);
-    let target = TargetInfo::from_triple(&env::var("TARGET")?);
+    let triple = env::var("TARGET")?;
+    let target = TargetInfo::from_triple(&triple);
+    // Lets the installer pick release artifacts for the exact target it was built for
+    println!("cargo:rustc-env=PROMETHEUS_AGENTS_TARGET={triple}");
 
     for artifact in artifacts_for(&target) {
         artifact.ensure(&target, &out_dir)?;
@@ -31,6 +43,13 @@ struct Artifact {
     output_name: &'static str,
     env_file: &'static str,
     env_url: &'static str,
+    env_sha256: &'static str,
+}
+
+/// Where an artifact is fetched from
+enum Source {
+    File(PathBuf),
+    Url(String),
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -43,27 +62,84 @@ enum ArtifactKind {
 impl Artifact {
     fn ensure(&self, target: &TargetInfo, out_dir: &Path) -> Result<(), Box<dyn Error>> {
         let dest = out_dir.join(self.output_name);
+        // CI passes unset repository variables as empty strings
+        let pinned = match env::var(self.env_sha256) {
+            Ok(value) if !value.trim().is_empty() => Some(normalize_sha256(&value)?),
+            _ => None,
+        };
+
         if dest.exists() {
-            return Ok(());
+            // Cached artifacts were verified when written; re-fetch one that
+            // no longer matches the pinned digest
+            match &pinned {
+                Some(digest) if self.verify(&dest, digest).is_err() => {}
+                _ => return Ok(()),
+            }
         }
 
-        if let Ok(path) = env::var(self.env_file) {
-            copy_to(Path::new(&path), &dest)?;
-            return Ok(());
+        let source = if let Ok(path) = env::var(self.env_file) {
+            Source::File(PathBuf::from(path))
+        } else if let Some(bundled) = self.bundle_path()? {
+            Source::File(bundled)
+        } else if let Ok(url) = env::var(self.env_url) {
+            Source::Url(url)
+        } else {
+            Source::Url(self.default_url(target)?)
+        };
+        // Known before fetching, so nothing unverified is ever left in OUT_DIR
+        let expected = match (pinned, &source) {
+            (Some(digest), _) => digest,
+            (None, Source::File(path)) => match sidecar_sha256(path)? {
+                Some(digest) => digest,
+                None => self.known_sha256(target)?,
+            },
+            (None, Source::Url(_)) => self.known_sha256(target)?,
+        };
+        match source {
+            Source::File(path) => copy_to(&path, &dest)?,
+            Source::Url(url) => download_to(&url, &dest)?,
         }
+        self.verify(&dest, &expected)
+    }
 
-        if let Some(bundled) = self.bundle_path()? {
-            copy_to(&bundled, &dest)?;
-            return Ok(());
-        }
+    /// Digest of the default release artifact for `target`, pinned below.
+    /// Exporter releases missing there are looked up in the upstream
+    /// `sha256sums.txt`. Copies of that artifact from a file are held to it
+    /// as well.
+    fn known_sha256(&self, target: &TargetInfo) -> Result<String, Box<dyn Error>> {
+        let url = self.default_url(target)?;
+        let known = match self.kind {
+            ArtifactKind::ProcessCpuAgent => pinned_sha256(PROCESS_CPU_AGENT_SHA256, &url),
+            ArtifactKind::NodeExporter => match pinned_sha256(NODE_EXPORTER_SHA256, &url) {
+                Some(digest) => Some(digest),
+                None => upstream_sha256(&url)?,
+            },
+            ArtifactKind::WindowsExporter => match pinned_sha256(WINDOWS_EXPORTER_SHA256, &url) {
+                Some(digest) => Some(digest),
+                None => upstream_sha256(&url)?,
+            },
+        };
+        known.ok_or_else(|| {
+            format!(
+                "No SHA-256 known for {}; set {} to the digest of the artifact \
+                 or put it in a .sha256 file next to a bundled one",
+                self.output_name, self.env_sha256
+            )
+            .into()
+        })
+    }
 
-        if let Ok(url) = env::var(self.env_url) {
-            download_to(&url, &dest)?;
-            return Ok(());
+    /// Check the artifact against its expected digest, deleting it on mismatch
+    fn verify(&self, dest: &Path, expected: &str) -> Result<(), Box<dyn Error>> {
+        let actual = format!("{:x}", Sha256::digest(fs::read(dest)?));
+        if actual != expected {
+            fs::remove_file(dest)?;
+            return Err(format!(
+                "Checksum mismatch for {}: expected {expected}, got {actual}",
+                self.output_name
+            )
+            .into());
         }
-
-        let url = self.default_url(target)?;
-        download_to(&url, &dest)?;
         Ok(())
     }
 
@@ -93,46 +169,32 @@ impl Artifact {
 struct TargetInfo {
     triple: String,
     os: TargetOs,
-    arch: TargetArch,
+    arch: Option<Arch>,
 }
 
 enum TargetOs {
     Linux,
+    MacOs,
     Windows,
     Other,
 }
 
-enum TargetArch {
-    X86_64,
-    X86,
-    Aarch64,
-    Other,
-}
-
 impl TargetInfo {
     fn from_triple(triple: &str) -> Self {
         let os = if triple.contains("windows") {
             TargetOs::Windows
         } else if triple.contains("linux") {
             TargetOs::Linux
+        } else if triple.contains("darwin") {
+            TargetOs::MacOs
         } else {
             TargetOs::Other
         };
 
-        let arch = if triple.contains("x86_64") || triple.contains("amd64") {
-            TargetArch::X86_64
-        } else if triple.contains("i686") || triple.contains("i586") || triple.contains("386") {
-            TargetArch::X86
-        } else if triple.contains("aarch64") || triple.contains("arm64") {
-            TargetArch::Aarch64
-        } else {
-            TargetArch::Other
-        };
-
         Self {
             triple: triple.to_string(),
             os,
-            arch,
+            arch: Arch::from_triple(triple),
         }
     }
 
@@ -140,9 +202,24 @@ impl TargetInfo {
         matches!(self.os, TargetOs::Linux)
     }
 
+    fn is_macos(&self) -> bool {
+        matches!(self.os, TargetOs::MacOs)
+    }
+
     fn is_windows(&self) -> bool {
         matches!(self.os, TargetOs::Windows)
     }
+
+    /// OS and architecture as they appear in release artifact names
+    fn release_platform(&self) -> Option<(&'static str, &'static str)> {
+        let arch = self.arch?;
+        match self.os {
+            TargetOs::Linux => Some(("linux", arch.node_exporter_name())),
+            TargetOs::MacOs => Some(("darwin", arch.darwin_name()?)),
+            TargetOs::Windows => Some(("windows", arch.node_exporter_name())),
+            TargetOs::Other => None,
+        }
+    }
 }
 
 fn artifacts_for(target: &TargetInfo) -> Vec<Artifact> {
@@ -151,14 +228,16 @@ fn artifacts_for(target: &TargetInfo) -> Vec<Artifact> {
         output_name: "process_cpu_agent.bin",
         env_file: "PROCESS_CPU_AGENT_BUILD_FILE",
         env_url: "PROCESS_CPU_AGENT_BUILD_URL",
+        env_sha256: "PROCESS_CPU_AGENT_BUILD_SHA256",
     }];
 
-    if target.is_linux() {
+    if target.is_linux() || target.is_macos() {
         list.push(Artifact {
             kind: ArtifactKind::NodeExporter,
             output_name: "node_exporter.tar.gz",
             env_file: "NODE_EXPORTER_BUILD_FILE",
             env_url: "NODE_EXPORTER_BUILD_URL",
+            env_sha256: "NODE_EXPORTER_BUILD_SHA256",
         });
     }
 
@@ -168,6 +247,7 @@ fn artifacts_for(target: &TargetInfo) -> Vec<Artifact> {
             output_name: "windows_exporter.msi",
             env_file: "WINDOWS_EXPORTER_BUILD_FILE",
             env_url: "WINDOWS_EXPORTER_BUILD_URL",
+            env_sha256: "WINDOWS_EXPORTER_BUILD_SHA256",
         });
     }
 
@@ -192,6 +272,12 @@ fn download_to(url: &str, dest: &Path) -> Result<(), Box<dyn Error>> {
     if let Some(parent) = dest.parent() {
         fs::create_dir_all(parent)?;
     }
+    let bytes = download_bytes(url)?;
+    fs::write(dest, &bytes)?;
+    Ok(())
+}
+
+fn download_bytes(url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
     let client = reqwest::blocking::Client::builder()
         .timeout(Duration::from_secs(120))
         .build()?;
@@ -199,24 +285,83 @@ fn download_to(url: &str, dest: &Path) -> Result<(), Box<dyn Error>> {
     if !response.status().is_success() {
         return Err(format!("Failed to download {url}: HTTP {}", response.status()).into());
     }
-    let bytes = response.bytes()?;
-    fs::write(dest, &bytes)?;
-    Ok(())
+    Ok(response.bytes()?.to_vec())
 }
 
-fn default_process_cpu_agent_url(target: &TargetInfo) -> Option<String> {
-    let os = match target.os {
-        TargetOs::Windows => "windows",
-        TargetOs::Linux => "linux",
-        TargetOs::Other => return None,
-    };
+/// Look up the digest of a default release artifact in the upstream
+/// `sha256sums.txt` published next to it
+fn upstream_sha256(url: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let (base, file_name) = url
+        .rsplit_once('/')
+        .ok_or_else(|| format!("Invalid artifact URL {url}"))?;
+    let sums_url = format!("{base}/sha256sums.txt");
+    let sums = String::from_utf8(download_bytes(&sums_url)?)?;
+
+    let digest = sums.lines().find_map(|line| {
+        let mut parts = line.split_whitespace();
+        let digest = parts.next()?;
+        let name = parts.next()?.trim_start_matches('*');
+        (name == file_name).then(|| digest.to_ascii_lowercase())
+    });
+    match digest {
+        Some(digest) => Ok(Some(digest)),
+        None => Err(format!("No checksum for {file_name} in {sums_url}").into()),
+    }
+}
 
-    let arch = match target.arch {
-        TargetArch::X86_64 => "amd64",
-        TargetArch::X86 => "386",
-        TargetArch::Aarch64 => "arm64",
-        TargetArch::Other => "amd64",
-    };
+fn normalize_sha256(value: &str) -> Result<String, Box<dyn Error>> {
+    let value = value.trim().to_ascii_lowercase();
+    if value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(value)
+    } else {
+        Err(format!("'{value}' is not a SHA-256 digest").into())
+    }
+}
+
+/// Digest in the `sha256sum` style file `{path}.sha256` next to a local
+/// artifact, if there is one
+fn sidecar_sha256(path: &Path) -> Result<Option<String>, Box<dyn Error>> {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".sha256");
+    let sidecar = PathBuf::from(sidecar);
+    if !sidecar.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(&sidecar)?;
+    let digest = content
+        .split_whitespace()
+        .next()
+        .ok_or_else(|| format!("{} is empty", sidecar.display()))?;
+    normalize_sha256(digest)
+        .map(Some)
+        .map_err(|e| format!("{}: {e}", sidecar.display()).into())
+}
+
+/// SHA-256 of the process-cpu-agent release artifacts by file name. The
+/// agent publishes no checksum file, so update these together with the
+/// release the default URL serves; targets missing here need
+/// PROCESS_CPU_AGENT_BUILD_SHA256 or a `lib/process-cpu-agent.sha256` next
+/// to a bundled binary.
+const PROCESS_CPU_AGENT_SHA256: &[(&str, &str)] = &[];
+
+/// SHA-256 of the node_exporter `NODE_EXPORTER_VERSION` release archives by
+/// file name, copied from its `sha256sums.txt` by
+/// `scripts/pin-artifact-digests.sh` so builds need no network to look them up
+const NODE_EXPORTER_SHA256: &[(&str, &str)] = &[];
+
+/// SHA-256 of the windows_exporter `WINDOWS_EXPORTER_VERSION` MSIs by file
+/// name, maintained like `NODE_EXPORTER_SHA256`
+const WINDOWS_EXPORTER_SHA256: &[(&str, &str)] = &[];
+
+fn pinned_sha256(pins: &[(&str, &str)], url: &str) -> Option<String> {
+    let file_name = url.rsplit('/').next()?;
+    pins.iter()
+        .find(|(name, _)| *name == file_name)
+        .map(|(_, digest)| digest.to_string())
+}
+
+fn default_process_cpu_agent_url(target: &TargetInfo) -> Option<String> {
+    let (os, arch) = target.release_platform()?;
 
     let suffix = if os == "windows" {
         format!("process-cpu-agent-{os}-{arch}.exe")
@@ -230,19 +375,14 @@ fn default_process_cpu_agent_url(target: &TargetInfo) -> Option<String> {
 }
 
 fn default_node_exporter_url(target: &TargetInfo) -> Option<String> {
-    if !target.is_linux() {
+    if !target.is_linux() && !target.is_macos() {
         return None;
     }
 
-    let arch = match target.arch {
-        TargetArch::X86_64 => "amd64",
-        TargetArch::X86 => "386",
-        TargetArch::Aarch64 => "arm64",
-        TargetArch::Other => "amd64",
-    };
+    let (os, arch) = target.release_platform()?;
 
     Some(format!(
-        "https://github.com/prometheus/node_exporter/releases/download/v{ver}/node_exporter-{ver}.linux-{arch}.tar.gz",
+        "https://github.com/prometheus/node_exporter/releases/download/v{ver}/node_exporter-{ver}.{os}-{arch}.tar.gz",
         ver = NODE_EXPORTER_VERSION
     ))
 }
@@ -252,10 +392,7 @@ fn default_windows_exporter_url(target: &TargetInfo) -> Option<String> {
         return None;
     }
 
-    let arch = match target.arch {
-        TargetArch::X86_64 => "amd64",
-        _ => "386",
-    };
+    let arch = target.arch?.windows_exporter_name()?;
 
     Some(format!(
         "https://github.com/prometheus-community/windows_exporter/releases/download/v{ver}/windows_exporter-{ver}-{arch}.msi",
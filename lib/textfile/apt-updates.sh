@@ -0,0 +1,25 @@
+#!/bin/sh
+# Pending apt upgrades, for node_exporter's textfile collector.
+# Usage: apt-updates.sh <textfile directory>
+set -eu
+dir="${1:?usage: $0 <textfile directory>}"
+out="$dir/apt_updates.prom"
+tmp="$out.$$"
+trap 'rm -f "$tmp"' EXIT
+
+# Simulate an upgrade against the cached package lists; nothing is installed
+pending=$(apt-get --just-print dist-upgrade 2>/dev/null | grep '^Inst ' || true)
+upgrades=$(printf '%s' "$pending" | grep -c '^Inst ' || true)
+security=$(printf '%s' "$pending" | grep -ci 'security' || true)
+
+cat >"$tmp" <<METRICS
+# HELP apt_upgrades_pending Packages apt would upgrade.
+# TYPE apt_upgrades_pending gauge
+apt_upgrades_pending $upgrades
+# HELP apt_security_upgrades_pending Pending upgrades from a security archive.
+# TYPE apt_security_upgrades_pending gauge
+apt_security_upgrades_pending $security
+METRICS
+chmod 644 "$tmp"
+# Renamed into place, so the collector never reads a partial file
+mv "$tmp" "$out"
@@ -0,0 +1,26 @@
+#!/bin/sh
+# Whether installed updates wait for a reboot, for node_exporter's textfile collector.
+# Usage: reboot-required.sh <textfile directory>
+set -eu
+dir="${1:?usage: $0 <textfile directory>}"
+out="$dir/reboot_required.prom"
+tmp="$out.$$"
+trap 'rm -f "$tmp"' EXIT
+
+required=0
+if [ -f /var/run/reboot-required ]; then
+    # Debian and Ubuntu
+    required=1
+elif command -v needs-restarting >/dev/null 2>&1; then
+    # Fedora and RHEL exit with 1 when a reboot is needed
+    needs-restarting -r >/dev/null 2>&1 || required=1
+fi
+
+cat >"$tmp" <<METRICS
+# HELP node_reboot_required Whether the host needs a reboot to finish installing updates.
+# TYPE node_reboot_required gauge
+node_reboot_required $required
+METRICS
+chmod 644 "$tmp"
+# Renamed into place, so the collector never reads a partial file
+mv "$tmp" "$out"
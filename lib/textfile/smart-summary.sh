@@ -0,0 +1,23 @@
+#!/bin/sh
+# Overall SMART health of each disk, for node_exporter's textfile collector.
+# Usage: smart-summary.sh <textfile directory>
+set -eu
+dir="${1:?usage: $0 <textfile directory>}"
+out="$dir/smart_summary.prom"
+tmp="$out.$$"
+trap 'rm -f "$tmp"' EXIT
+
+{
+    echo '# HELP smartmon_device_smart_healthy Whether the disk passed its SMART overall-health self-assessment.'
+    echo '# TYPE smartmon_device_smart_healthy gauge'
+    smartctl --scan | while read -r device _ type _; do
+        healthy=0
+        if smartctl -H -d "$type" "$device" | grep -Eq 'PASSED|: OK'; then
+            healthy=1
+        fi
+        echo "smartmon_device_smart_healthy{device=\"$device\",type=\"$type\"} $healthy"
+    done
+} >"$tmp"
+chmod 644 "$tmp"
+# Renamed into place, so the collector never reads a partial file
+mv "$tmp" "$out"
@@ -0,0 +1,35 @@
+#!/bin/sh
+# Pending dnf or yum updates, for node_exporter's textfile collector.
+# Usage: yum-updates.sh <textfile directory>
+set -eu
+dir="${1:?usage: $0 <textfile directory>}"
+out="$dir/yum_updates.prom"
+tmp="$out.$$"
+trap 'rm -f "$tmp"' EXIT
+
+if command -v dnf >/dev/null 2>&1; then
+    manager=dnf
+else
+    manager=yum
+fi
+# check-update exits with 100 when updates are available and lists one package per line
+status=0
+listing=$("$manager" -q check-update 2>/dev/null) || status=$?
+if [ "$status" -ne 0 ] && [ "$status" -ne 100 ]; then
+    echo "$manager check-update failed with status $status" >&2
+    exit 1
+fi
+updates=$(printf '%s\n' "$listing" | sed '/^Obsoleting/,$d' | grep -c '^[^[:space:]]' || true)
+security=$("$manager" -q updateinfo list --security 2>/dev/null | grep -c '^[^[:space:]]' || true)
+
+cat >"$tmp" <<METRICS
+# HELP yum_upgrades_pending Packages with an available update.
+# TYPE yum_upgrades_pending gauge
+yum_upgrades_pending $updates
+# HELP yum_security_upgrades_pending Pending security advisories.
+# TYPE yum_security_upgrades_pending gauge
+yum_security_upgrades_pending $security
+METRICS
+chmod 644 "$tmp"
+# Renamed into place, so the collector never reads a partial file
+mv "$tmp" "$out"
@@ -40,6 +40,10 @@ git tag -a "${VERSION}" -m "Release ${VERSION}"
 
 echo "Tag ${VERSION} created successfully!"
 echo ""
+echo "The release workflow embeds process-cpu-agent only with its digest pinned:"
+echo "set the repository variables PROCESS_CPU_AGENT_SHA256_LINUX_AMD64 and"
+echo "PROCESS_CPU_AGENT_SHA256_WINDOWS_AMD64 unless build.rs pins them."
+echo ""
 echo "To trigger the release, push the tag to GitHub:"
 echo "  git push origin ${VERSION}"
 echo ""
@@ -37,7 +37,11 @@ DOCKER
 fi
 
 log "CentOS 7용 릴리스 바이너리 빌드"
+# build.rs reads the artifact sources and digests from these variables
 docker run --rm \
+  -e PROCESS_CPU_AGENT_BUILD_FILE -e PROCESS_CPU_AGENT_BUILD_URL -e PROCESS_CPU_AGENT_BUILD_SHA256 \
+  -e NODE_EXPORTER_BUILD_FILE -e NODE_EXPORTER_BUILD_URL -e NODE_EXPORTER_BUILD_SHA256 \
+  -e WINDOWS_EXPORTER_BUILD_FILE -e WINDOWS_EXPORTER_BUILD_URL -e WINDOWS_EXPORTER_BUILD_SHA256 \
   -v "$REPO_ROOT":/workspace \
   -w /workspace \
   "$IMAGE_NAME" \
@@ -0,0 +1,31 @@
+#!/usr/bin/env bash
+# Print the pinned digest tables for build.rs from the upstream sha256sums.txt
+# of the exporter versions build.rs embeds.
+# Usage: scripts/pin-artifact-digests.sh
+set -euo pipefail
+
+REPO_ROOT="$(cd "$(dirname "${BASH_SOURCE[0]}")/.." && pwd)"
+
+version() {
+  sed -n "s/^const $1: &str = \"\(.*\)\";/\1/p" "$REPO_ROOT/build.rs"
+}
+
+pins() {
+  local table=$1 url=$2 pattern=$3
+  printf 'const %s: &[(&str, &str)] = &[\n' "$table"
+  curl -fsSL "$url" | awk -v pattern="$pattern" '$2 ~ pattern {
+    sub(/^\*/, "", $2)
+    printf "    (\n        \"%s\",\n        \"%s\",\n    ),\n", $2, $1
+  }'
+  printf '];\n\n'
+}
+
+NODE=$(version NODE_EXPORTER_VERSION)
+WINDOWS=$(version WINDOWS_EXPORTER_VERSION)
+
+pins NODE_EXPORTER_SHA256 \
+  "https://github.com/prometheus/node_exporter/releases/download/v$NODE/sha256sums.txt" \
+  '\.(linux|darwin)-.*\.tar\.gz$'
+pins WINDOWS_EXPORTER_SHA256 \
+  "https://github.com/prometheus-community/windows_exporter/releases/download/v$WINDOWS/sha256sums.txt" \
+  '\.msi$'
@@ -0,0 +1,144 @@
+//! CPU architecture mapping shared by the installer and `build.rs`.
+//! Only depends on `std` so the build script can include it with `#[path]`.
+
+/// Architectures for which upstream exporter releases are published
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+    Amd64,
+    I386,
+    Arm64,
+    Armv7,
+    Armv6,
+    Armv5,
+    Ppc64le,
+    Ppc64,
+    S390x,
+    Riscv64,
+    Mips,
+    Mipsle,
+    Mips64,
+    Mips64le,
+}
+
+impl Arch {
+    /// Parse the architecture part of a Rust target triple such as
+    /// `armv7-unknown-linux-gnueabihf` or `powerpc64le-unknown-linux-gnu`
+    pub fn from_triple(triple: &str) -> Option<Self> {
+        let cpu = triple.split('-').next().unwrap_or_default();
+        let arch = match cpu {
+            "x86_64" | "amd64" => Arch::Amd64,
+            "i386" | "i586" | "i686" | "x86" => Arch::I386,
+            "aarch64" | "arm64" => Arch::Arm64,
+            "arm" | "armv6" => Arch::Armv6,
+            "powerpc64le" | "ppc64le" => Arch::Ppc64le,
+            "powerpc64" | "ppc64" => Arch::Ppc64,
+            "s390x" => Arch::S390x,
+            "mips" => Arch::Mips,
+            "mipsel" | "mipsle" => Arch::Mipsle,
+            "mips64" => Arch::Mips64,
+            "mips64el" | "mips64le" => Arch::Mips64le,
+            cpu if cpu.starts_with("armv7") || cpu.starts_with("thumbv7") => Arch::Armv7,
+            cpu if cpu.starts_with("armv5") || cpu.starts_with("armv4") => Arch::Armv5,
+            cpu if cpu.starts_with("riscv64") => Arch::Riscv64,
+            _ => return None,
+        };
+        Some(arch)
+    }
+
+    /// Suffix used in node_exporter release archive names
+    pub fn node_exporter_name(self) -> &'static str {
+        match self {
+            Arch::Amd64 => "amd64",
+            Arch::I386 => "386",
+            Arch::Arm64 => "arm64",
+            Arch::Armv7 => "armv7",
+            Arch::Armv6 => "armv6",
+            Arch::Armv5 => "armv5",
+            Arch::Ppc64le => "ppc64le",
+            Arch::Ppc64 => "ppc64",
+            Arch::S390x => "s390x",
+            Arch::Riscv64 => "riscv64",
+            Arch::Mips => "mips",
+            Arch::Mipsle => "mipsle",
+            Arch::Mips64 => "mips64",
+            Arch::Mips64le => "mips64le",
+        }
+    }
+
+    /// Suffix used in darwin release archive names, if upstream builds one
+    pub fn darwin_name(self) -> Option<&'static str> {
+        match self {
+            Arch::Amd64 => Some("amd64"),
+            Arch::Arm64 => Some("arm64"),
+            _ => None,
+        }
+    }
+
+    /// Suffix used in windows_exporter MSI names, if upstream builds one
+    pub fn windows_exporter_name(self) -> Option<&'static str> {
+        match self {
+            Arch::Amd64 => Some("amd64"),
+            Arch::I386 => Some("386"),
+            Arch::Arm64 => Some("arm64"),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_triple() {
+        let cases = [
+            ("x86_64-unknown-linux-gnu", Arch::Amd64),
+            ("i686-pc-windows-msvc", Arch::I386),
+            ("aarch64-unknown-linux-musl", Arch::Arm64),
+            ("armv7-unknown-linux-gnueabihf", Arch::Armv7),
+            ("thumbv7neon-unknown-linux-gnueabihf", Arch::Armv7),
+            ("arm-unknown-linux-gnueabihf", Arch::Armv6),
+            ("armv5te-unknown-linux-gnueabi", Arch::Armv5),
+            ("powerpc64le-unknown-linux-gnu", Arch::Ppc64le),
+            ("powerpc64-unknown-linux-gnu", Arch::Ppc64),
+            ("s390x-unknown-linux-gnu", Arch::S390x),
+            ("riscv64gc-unknown-linux-gnu", Arch::Riscv64),
+            ("mips-unknown-linux-gnu", Arch::Mips),
+            ("mipsel-unknown-linux-gnu", Arch::Mipsle),
+            ("mips64-unknown-linux-gnuabi64", Arch::Mips64),
+            ("mips64el-unknown-linux-gnuabi64", Arch::Mips64le),
+        ];
+        for (triple, arch) in cases {
+            assert_eq!(Arch::from_triple(triple), Some(arch), "{triple}");
+        }
+        assert_eq!(Arch::from_triple("sparc64-unknown-linux-gnu"), None);
+        assert_eq!(Arch::from_triple("wasm32-unknown-unknown"), None);
+    }
+
+    #[test]
+    fn test_node_exporter_names() {
+        assert_eq!(Arch::Amd64.node_exporter_name(), "amd64");
+        assert_eq!(Arch::I386.node_exporter_name(), "386");
+        assert_eq!(Arch::Arm64.node_exporter_name(), "arm64");
+        assert_eq!(Arch::Armv7.node_exporter_name(), "armv7");
+        assert_eq!(Arch::Ppc64le.node_exporter_name(), "ppc64le");
+        assert_eq!(Arch::S390x.node_exporter_name(), "s390x");
+        assert_eq!(Arch::Mips64le.node_exporter_name(), "mips64le");
+    }
+
+    #[test]
+    fn test_darwin_names() {
+        assert_eq!(Arch::Amd64.darwin_name(), Some("amd64"));
+        assert_eq!(Arch::Arm64.darwin_name(), Some("arm64"));
+        assert_eq!(Arch::I386.darwin_name(), None);
+        assert_eq!(Arch::Armv7.darwin_name(), None);
+    }
+
+    #[test]
+    fn test_windows_exporter_names() {
+        assert_eq!(Arch::Amd64.windows_exporter_name(), Some("amd64"));
+        assert_eq!(Arch::I386.windows_exporter_name(), Some("386"));
+        assert_eq!(Arch::Arm64.windows_exporter_name(), Some("arm64"));
+        assert_eq!(Arch::Armv7.windows_exporter_name(), None);
+    }
+}
@@ -0,0 +1,517 @@
+use crate::exporter::init_system::InitSystem;
+use crate::exporter::ports::PortConflictPolicy;
+use crate::exporter::{Component, downloader, service_host};
+use crate::os_detector::OsType;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+/// Install and manage Prometheus exporters on this host
+#[derive(Debug, Parser)]
+#[command(name = "prometheus-agents-setup", version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Process CPU Agent download URL (legacy form of `install --process-cpu-agent-url`)
+    #[arg(value_name = "URL")]
+    pub process_cpu_agent_url: Option<String>,
+
+    /// Installer configuration file overriding ports, versions, paths and sources
+    ///
+    /// `process-cpu-agent.listen_address` is not applied: the agent has no
+    /// setting for the address it binds and always listens on every interface.
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        env = "PROMETHEUS_AGENTS_CONFIG"
+    )]
+    pub config: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Install and start the selected exporters
+    Install(InstallArgs),
+    /// Stop and remove the selected exporters
+    Uninstall(UninstallArgs),
+    /// Show installation and service state of the selected exporters
+    Status(SelectionArgs),
+    /// Reinstall the selected exporters with the versions bundled in this binary
+    Upgrade(InstallArgs),
+    /// Print what `install` would do without changing anything
+    Plan(InstallArgs),
+    /// Run a program as a Windows service; used by the services this tool registers
+    #[command(name = service_host::HOST_SUBCOMMAND, hide = true)]
+    ServiceHost(ServiceHostArgs),
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct InstallArgs {
+    #[command(flatten)]
+    pub selection: SelectionArgs,
+
+    /// Download the Process CPU Agent from this URL instead of using the embedded binary
+    #[arg(long, value_name = "URL", env = "PROCESS_CPU_AGENT_URL")]
+    pub process_cpu_agent_url: Option<String>,
+
+    /// Expected SHA-256 of the binary at --process-cpu-agent-url
+    #[arg(
+        long,
+        visible_alias = "sha256",
+        value_name = "HEX",
+        env = "PROCESS_CPU_AGENT_SHA256",
+        value_parser = downloader::normalize_sha256
+    )]
+    pub process_cpu_agent_sha256: Option<String>,
+
+    /// Register Linux services with this init system instead of the detected one
+    #[arg(long, value_enum, value_name = "NAME")]
+    pub init_system: Option<InitSystem>,
+
+    /// What to do when another program already listens on an exporter's port
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t)]
+    pub on_port_conflict: PortConflictPolicy,
+
+    #[command(flatten)]
+    pub dry_run: DryRunArgs,
+
+    #[command(flatten)]
+    pub health: HealthCheckArgs,
+
+    #[command(flatten)]
+    pub targets: ScrapeTargetArgs,
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct UninstallArgs {
+    #[command(flatten)]
+    pub selection: SelectionArgs,
+
+    #[command(flatten)]
+    pub dry_run: DryRunArgs,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ServiceHostArgs {
+    /// Name the service is registered under
+    #[arg(long)]
+    pub name: String,
+
+    /// Directory the program runs in
+    #[arg(long, value_name = "DIR")]
+    pub working_dir: String,
+
+    /// Program to run, followed by its arguments
+    #[arg(last = true, required = true, value_name = "COMMAND")]
+    pub command: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct DryRunArgs {
+    /// Print every action that would be taken without changing the system
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Output format of the dry-run plan
+    #[arg(long, value_enum, default_value_t)]
+    pub format: PlanFormat,
+}
+
+/// Seconds to wait for freshly installed exporters to serve metrics
+pub const DEFAULT_HEALTH_TIMEOUT_SECS: u64 = 30;
+
+#[derive(Debug, Clone, Args)]
+pub struct HealthCheckArgs {
+    /// Do not scrape the metrics endpoints after installing
+    #[arg(long)]
+    pub skip_health_check: bool,
+
+    /// Seconds to wait for the metrics endpoints to respond
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_HEALTH_TIMEOUT_SECS)]
+    pub health_timeout: u64,
+}
+
+impl Default for HealthCheckArgs {
+    fn default() -> Self {
+        Self {
+            skip_health_check: false,
+            health_timeout: DEFAULT_HEALTH_TIMEOUT_SECS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct ScrapeTargetArgs {
+    /// Write a Prometheus file_sd target file to FILE, to <hostname>.json inside a directory, or to stdout with -
+    #[arg(long, value_name = "PATH")]
+    pub file_sd_output: Option<String>,
+
+    /// Host name or IP address Prometheus scrapes this host at [default: primary IP address]
+    #[arg(long, value_name = "HOST")]
+    pub scrape_address: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum PlanFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct SelectionArgs {
+    /// Only act on these components (comma separated)
+    #[arg(long, value_enum, value_delimiter = ',', conflicts_with = "skip")]
+    pub only: Vec<Component>,
+
+    /// Act on every component except these (comma separated)
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub skip: Vec<Component>,
+}
+
+impl Cli {
+    /// Resolve the command to run, treating a bare invocation as `install`.
+    /// Fails when `PROCESS_CPU_AGENT_SHA256` is not a SHA-256 digest, as
+    /// `install` does.
+    pub fn into_command(self) -> Result<Command, String> {
+        if let Some(command) = self.command {
+            return Ok(command);
+        }
+        let process_cpu_agent_sha256 =
+            sha256_from_env(std::env::var("PROCESS_CPU_AGENT_SHA256").ok())?;
+        Ok(Command::Install(InstallArgs {
+            selection: SelectionArgs::default(),
+            process_cpu_agent_url: self
+                .process_cpu_agent_url
+                .or_else(|| std::env::var("PROCESS_CPU_AGENT_URL").ok()),
+            process_cpu_agent_sha256,
+            init_system: None,
+            on_port_conflict: PortConflictPolicy::default(),
+            dry_run: DryRunArgs::default(),
+            health: HealthCheckArgs::default(),
+            targets: ScrapeTargetArgs::default(),
+        }))
+    }
+}
+
+/// Digest set in `PROCESS_CPU_AGENT_SHA256`, checked like `--process-cpu-agent-sha256`
+fn sha256_from_env(value: Option<String>) -> Result<Option<String>, String> {
+    value
+        .map(|value| downloader::normalize_sha256(&value))
+        .transpose()
+        .map_err(|e| format!("PROCESS_CPU_AGENT_SHA256: {e}"))
+}
+
+impl Command {
+    /// Format of the plan printed instead of making changes, if this is a dry run
+    pub fn plan_format(&self) -> Option<PlanFormat> {
+        match self {
+            Command::Plan(args) => Some(args.dry_run.format),
+            Command::Install(args) | Command::Upgrade(args) if args.dry_run.dry_run => {
+                Some(args.dry_run.format)
+            }
+            Command::Uninstall(args) if args.dry_run.dry_run => Some(args.dry_run.format),
+            _ => None,
+        }
+    }
+}
+
+impl SelectionArgs {
+    /// Resolve the selected components for the given OS, in installation order
+    pub fn resolve(&self, os: &OsType) -> Result<Vec<Component>, String> {
+        let available = Component::available_for(os);
+        if available.is_empty() {
+            return Err("Unsupported operating system".to_string());
+        }
+
+        if let Some(unsupported) = self.only.iter().find(|c| !available.contains(c)) {
+            return Err(format!("{} is not supported on {os:?}", unsupported.name()));
+        }
+
+        let selected: Vec<Component> = available
+            .into_iter()
+            .filter(|c| self.only.is_empty() || self.only.contains(c))
+            .filter(|c| !self.skip.contains(c))
+            .collect();
+
+        if selected.is_empty() {
+            return Err("No components selected".to_string());
+        }
+
+        Ok(selected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Command {
+        let mut argv = vec!["prometheus-agents-setup"];
+        argv.extend_from_slice(args);
+        Cli::try_parse_from(argv).unwrap().into_command().unwrap()
+    }
+
+    #[test]
+    fn test_bare_invocation_is_install() {
+        match parse(&[]) {
+            Command::Install(args) => assert!(args.selection.only.is_empty()),
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_legacy_positional_url() {
+        match parse(&["https://example.com/agent"]) {
+            Command::Install(args) => assert_eq!(
+                args.process_cpu_agent_url.as_deref(),
+                Some("https://example.com/agent")
+            ),
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_only_and_skip_parsing() {
+        match parse(&["install", "--only", "node_exporter"]) {
+            Command::Install(args) => {
+                assert_eq!(args.selection.only, vec![Component::NodeExporter])
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        match parse(&["status", "--skip", "process-cpu-agent"]) {
+            Command::Status(selection) => {
+                assert_eq!(selection.skip, vec![Component::ProcessCpuAgent])
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_uninstall_parsing() {
+        match parse(&["uninstall", "--only", "process_cpu_agent"]) {
+            Command::Uninstall(args) => {
+                assert_eq!(args.selection.only, vec![Component::ProcessCpuAgent])
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_service_host_parsing() {
+        // The command line registered for services parses back into the host
+        let line = service_host::command_line(
+            "ProcessCpuAgent",
+            "C:\\agent",
+            "C:\\agent\\process-cpu-agent.exe",
+            &["--port".to_string(), "9256".to_string()],
+        );
+        let argv: Vec<&str> = line.split(' ').skip(1).collect();
+        match parse(&argv) {
+            Command::ServiceHost(args) => {
+                assert_eq!(args.name, "ProcessCpuAgent");
+                assert_eq!(args.working_dir, "C:\\agent");
+                assert_eq!(
+                    args.command,
+                    vec!["C:\\agent\\process-cpu-agent.exe", "--port", "9256"]
+                );
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+        assert!(
+            Cli::try_parse_from(["prometheus-agents-setup", "service-host", "--name", "A"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_sha256_parsing() {
+        let digest = "A".repeat(64);
+        match parse(&[
+            "install",
+            "--process-cpu-agent-url",
+            "https://example.com/agent",
+            "--sha256",
+            &digest,
+        ]) {
+            Command::Install(args) => {
+                assert_eq!(args.process_cpu_agent_sha256, Some("a".repeat(64)))
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        let result = Cli::try_parse_from(["prometheus-agents-setup", "install", "--sha256", "xyz"]);
+        assert!(result.is_err());
+
+        // A bare invocation reads the variable itself and must reject it the same way
+        assert_eq!(sha256_from_env(None), Ok(None));
+        assert_eq!(
+            sha256_from_env(Some(digest.clone())),
+            Ok(Some("a".repeat(64)))
+        );
+        let error = sha256_from_env(Some("xyz".to_string())).unwrap_err();
+        assert!(error.starts_with("PROCESS_CPU_AGENT_SHA256: 'xyz' is not a SHA-256 digest"));
+    }
+
+    #[test]
+    fn test_dry_run_parsing() {
+        assert_eq!(parse(&[]).plan_format(), None);
+        assert_eq!(parse(&["install"]).plan_format(), None);
+        assert_eq!(
+            parse(&["install", "--dry-run"]).plan_format(),
+            Some(PlanFormat::Text)
+        );
+        assert_eq!(
+            parse(&["uninstall", "--dry-run", "--format", "json"]).plan_format(),
+            Some(PlanFormat::Json)
+        );
+        assert_eq!(parse(&["plan"]).plan_format(), Some(PlanFormat::Text));
+        assert_eq!(parse(&["status"]).plan_format(), None);
+    }
+
+    #[test]
+    fn test_health_check_parsing() {
+        match parse(&[]) {
+            Command::Install(args) => {
+                assert!(!args.health.skip_health_check);
+                assert_eq!(args.health.health_timeout, DEFAULT_HEALTH_TIMEOUT_SECS);
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        match parse(&["upgrade", "--skip-health-check", "--health-timeout", "5"]) {
+            Command::Upgrade(args) => {
+                assert!(args.health.skip_health_check);
+                assert_eq!(args.health.health_timeout, 5);
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_init_system_parsing() {
+        match parse(&[]) {
+            Command::Install(args) => assert_eq!(args.init_system, None),
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        match parse(&["install", "--init-system", "openrc"]) {
+            Command::Install(args) => assert_eq!(args.init_system, Some(InitSystem::OpenRc)),
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        let result = Cli::try_parse_from([
+            "prometheus-agents-setup",
+            "install",
+            "--init-system",
+            "upstart",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_port_conflict_parsing() {
+        match parse(&[]) {
+            Command::Install(args) => {
+                assert_eq!(args.on_port_conflict, PortConflictPolicy::NextFree)
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        match parse(&["upgrade", "--on-port-conflict", "fail"]) {
+            Command::Upgrade(args) => assert_eq!(args.on_port_conflict, PortConflictPolicy::Fail),
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_scrape_target_parsing() {
+        match parse(&[
+            "install",
+            "--file-sd-output",
+            "/srv/prometheus/targets",
+            "--scrape-address",
+            "web-01.example.com",
+        ]) {
+            Command::Install(args) => {
+                assert_eq!(
+                    args.targets.file_sd_output.as_deref(),
+                    Some("/srv/prometheus/targets")
+                );
+                assert_eq!(
+                    args.targets.scrape_address.as_deref(),
+                    Some("web-01.example.com")
+                );
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_config_parsing() {
+        let cli =
+            Cli::try_parse_from(["prometheus-agents-setup", "--config", "agents.toml"]).unwrap();
+        assert_eq!(cli.config.as_deref(), Some("agents.toml"));
+        assert!(matches!(cli.into_command(), Ok(Command::Install(_))));
+
+        let cli = Cli::try_parse_from([
+            "prometheus-agents-setup",
+            "status",
+            "--config",
+            "agents.toml",
+        ])
+        .unwrap();
+        assert_eq!(cli.config.as_deref(), Some("agents.toml"));
+    }
+
+    #[test]
+    fn test_only_conflicts_with_skip() {
+        let result = Cli::try_parse_from([
+            "prometheus-agents-setup",
+            "install",
+            "--only",
+            "node_exporter",
+            "--skip",
+            "process-cpu-agent",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_resolve_selection() {
+        let all = SelectionArgs::default();
+        assert_eq!(
+            all.resolve(&OsType::Linux).unwrap(),
+            vec![Component::NodeExporter, Component::ProcessCpuAgent]
+        );
+
+        let skip = SelectionArgs {
+            skip: vec![Component::ProcessCpuAgent],
+            ..Default::default()
+        };
+        assert_eq!(
+            skip.resolve(&OsType::Windows).unwrap(),
+            vec![Component::WindowsExporter]
+        );
+    }
+
+    #[test]
+    fn test_resolve_rejects_unsupported_component() {
+        let only = SelectionArgs {
+            only: vec![Component::WindowsExporter],
+            ..Default::default()
+        };
+        assert!(only.resolve(&OsType::Linux).is_err());
+        assert!(SelectionArgs::default().resolve(&OsType::Unknown).is_err());
+    }
+
+    #[test]
+    fn test_resolve_rejects_empty_selection() {
+        let skip_all = SelectionArgs {
+            skip: vec![Component::NodeExporter, Component::ProcessCpuAgent],
+            ..Default::default()
+        };
+        assert!(skip_all.resolve(&OsType::Linux).is_err());
+    }
+}
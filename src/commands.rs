@@ -0,0 +1,1299 @@
+use crate::cli::{InstallArgs, PlanFormat, SelectionArgs, UninstallArgs};
+use crate::config::InstallerConfig;
+use crate::exporter::init_system::InitSystem;
+use crate::exporter::listen::{self, ListenAddress};
+use crate::exporter::manifest::{self, ArtifactSource, ComponentRecord, InstallManifest};
+use crate::exporter::node_exporter::NodeExporterSetup;
+use crate::exporter::ports::{self, Listener, PortConflictPolicy};
+use crate::exporter::preflight::{self, Finding, Problem, Requirements};
+use crate::exporter::process_exporter::ProcessCpuAgentSetup;
+use crate::exporter::recorder::{self, Action, progress};
+use crate::exporter::registration::Registration;
+use crate::exporter::scrape::{self, Host, TargetGroup};
+use crate::exporter::service::{self, WindowsStartMode};
+use crate::exporter::web_config::WebConfig;
+use crate::exporter::windows_exporter::WindowsExporterSetup;
+use crate::exporter::{Component, ComponentStatus, downloader, health, user_install_root};
+use crate::os_detector::{self, InterfaceAddress, OsType};
+use serde::Serialize;
+use std::net::IpAddr;
+use std::time::Duration;
+
+type CommandResult = Result<(), Box<dyn std::error::Error>>;
+
+/// Install the selected components
+pub fn install(os: &OsType, args: &InstallArgs, config: &InstallerConfig) -> CommandResult {
+    if args.dry_run.dry_run {
+        return plan(os, args, config);
+    }
+    install_components(os, args, config)
+}
+
+/// Reinstall the selected components over an existing installation
+pub fn upgrade(os: &OsType, args: &InstallArgs, config: &InstallerConfig) -> CommandResult {
+    if args.dry_run.dry_run {
+        let components = args.selection.resolve(os)?;
+        return print_plan("upgrade", &components, args.dry_run.format, || {
+            upgrade_components(os, args, config)
+        });
+    }
+    upgrade_components(os, args, config)
+}
+
+/// Remove the selected components and report what was removed
+pub fn uninstall(os: &OsType, args: &UninstallArgs, config: &InstallerConfig) -> CommandResult {
+    if args.dry_run.dry_run {
+        let components = args.selection.resolve(os)?;
+        return print_plan("uninstall", &components, args.dry_run.format, || {
+            uninstall_components(os, &args.selection, config)
+        });
+    }
+    uninstall_components(os, &args.selection, config)
+}
+
+/// Print the installation state of the selected components
+pub fn status(os: &OsType, selection: &SelectionArgs, config: &InstallerConfig) -> CommandResult {
+    let components = selection.resolve(os)?;
+    let init_system =
+        resolve_init_system(os, None, os_detector::is_privileged(), InitSystem::detect());
+    let setups = Setups::new(config, None, init_system);
+    let install_manifest = load_manifest(&manifest_path(init_system));
+
+    println!(
+        "{:<20} {:<10} {:<10} {:<16} Service",
+        "Component", "Installed", "Version", "Files"
+    );
+    let mut healthy = true;
+    for component in components {
+        let status = match component {
+            Component::NodeExporter => setups.node_exporter.status(),
+            Component::WindowsExporter => setups.windows_exporter.status(),
+            Component::ProcessCpuAgent => setups.process_cpu_agent.status(),
+        };
+        let record = install_manifest.get(component);
+        println!("{}", format_status_row(&status, record));
+        healthy &= status.installed && status.running == Some(true);
+        if let Some(record) = record {
+            let modified = record.modified_files();
+            for file in &modified {
+                println!("   modified or missing: {}", file.path);
+            }
+            healthy &= modified.is_empty();
+        }
+    }
+
+    if !healthy {
+        return Err(
+            "One or more components are not installed, not running or have modified files".into(),
+        );
+    }
+    Ok(())
+}
+
+/// Print every action `install` would take without changing anything
+pub fn plan(os: &OsType, args: &InstallArgs, config: &InstallerConfig) -> CommandResult {
+    let components = args.selection.resolve(os)?;
+    print_plan("install", &components, args.dry_run.format, || {
+        install_components(os, args, config)
+    })
+}
+
+/// Run `command` with side effects recorded, then print the recorded actions
+fn print_plan(
+    command: &str,
+    components: &[Component],
+    format: PlanFormat,
+    run: impl FnOnce() -> CommandResult,
+) -> CommandResult {
+    let (result, actions) = recorder::dry_run(run);
+    match format {
+        PlanFormat::Text => print!("{}", format_plan_text(command, &actions)),
+        PlanFormat::Json => {
+            let plan = Plan {
+                command,
+                components,
+                actions: &actions,
+            };
+            println!("{}", serde_json::to_string_pretty(&plan)?);
+        }
+    }
+    result
+}
+
+/// Ordered list of actions a dry run would perform, as emitted with `--format json`
+#[derive(Serialize)]
+struct Plan<'a> {
+    command: &'a str,
+    components: &'a [Component],
+    actions: &'a [Action],
+}
+
+fn format_plan_text(command: &str, actions: &[Action]) -> String {
+    let mut text = format!("Planned actions for {command} (nothing has been changed):\n");
+    if actions.is_empty() {
+        text.push_str("   (none)\n");
+    }
+    for (index, action) in actions.iter().enumerate() {
+        text.push_str(&format!("{:>3}. {}\n", index + 1, action.describe()));
+    }
+    text
+}
+
+/// Set up each component, recording it in the install manifest
+fn install_components(os: &OsType, args: &InstallArgs, config: &InstallerConfig) -> CommandResult {
+    let components = args.selection.resolve(os)?;
+    let privileged = os_detector::is_privileged();
+    let init_system = resolve_init_system(os, args.init_system, privileged, InitSystem::detect());
+    if args.init_system.is_none() && init_system == Some(InitSystem::SystemdUser) {
+        progress!(
+            "Not running as root: installing for the current user under {} with systemd --user\n",
+            user_install_root()
+        );
+    }
+    let user_config = if privileged {
+        None
+    } else {
+        per_user_config(os, &components, config)
+    };
+    let config = match &user_config {
+        Some(user_config) => {
+            progress!(
+                "Not running as Administrator: registering Process CPU Agent as a logon task of the current user\n"
+            );
+            user_config
+        }
+        None => config,
+    };
+    let setups = Setups::new(config, Some(args), init_system);
+    let (setups, mut findings) = resolve_listen_addresses(
+        &components,
+        setups,
+        config,
+        &os_detector::interface_addresses(),
+    );
+    let (setups, port_findings) =
+        resolve_ports(&components, setups, args.on_port_conflict, ports::listener);
+    findings.extend(port_findings);
+    let manifest_path = manifest_path(init_system);
+    check_preflight(&components, &setups, &manifest_path, privileged, findings)?;
+
+    if let ArtifactSource::Remote { url } = setups.process_cpu_agent.source() {
+        progress!("Using custom Process CPU Agent URL: {url}");
+    }
+
+    progress!("Setting up exporters for {}...\n", os_label(os));
+    if *os == OsType::MacOs {
+        progress!("Note: macOS support uses Node Exporter with limited collectors");
+    }
+
+    let mut install_manifest = load_manifest(&manifest_path);
+
+    let mut failed = Vec::new();
+    for (index, component) in components.iter().enumerate() {
+        progress!("{}. Setting up {}...", index + 1, component.display_name());
+        let previous = install_manifest.get(*component).cloned();
+        match install_component(*component, previous.as_ref(), &setups) {
+            Ok((record, changed)) => {
+                if !changed {
+                    progress!(
+                        "   {} is already installed and up to date",
+                        component.display_name()
+                    );
+                }
+                install_manifest.record(record);
+                install_manifest.save(&manifest_path)?;
+            }
+            Err(e) => {
+                eprintln!("{} setup failed: {e}", component.display_name());
+                failed.push(component.name());
+            }
+        }
+        progress!();
+    }
+
+    if !failed.is_empty() {
+        return Err(format!("Failed to set up: {}", failed.join(", ")).into());
+    }
+
+    if !args.health.skip_health_check && !recorder::is_dry_run() {
+        let timeout = Duration::from_secs(args.health.health_timeout);
+        verify_health(&components, &setups, timeout)?;
+    }
+
+    let host = Host::detect(args.targets.scrape_address.as_deref());
+    let groups = scrape_target_groups(
+        &components,
+        &setups,
+        &install_manifest,
+        &host,
+        args.targets.scrape_address.is_none(),
+    );
+    let file_sd_output = args.targets.file_sd_output.as_deref();
+    if let Some(output) = file_sd_output.filter(|&output| output != "-")
+        && let Some(path) = scrape::write_file_sd(output, &host, &groups)?
+    {
+        progress!("Prometheus file_sd targets written to: {path}");
+    }
+    if let Some(registration) = registration(config) {
+        registration
+            .register(&host, &groups)
+            .map_err(|e| format!("Registration with {} failed: {e}", registration.url))?;
+        progress!(
+            "Registered {} exporter(s) with {}",
+            groups.len(),
+            registration.url
+        );
+    }
+
+    progress!("✓ Exporter setup completed successfully!");
+    progress!("Install manifest written to: {manifest_path}");
+    print_next_steps(os, &components, &setups, &groups);
+    if file_sd_output == Some("-") {
+        progress!("\n{}", scrape::file_sd_json(&groups)?.trim_end());
+    }
+    Ok(())
+}
+
+/// Registration endpoint from the installer config, if one is configured
+fn registration(config: &InstallerConfig) -> Option<Registration> {
+    let settings = &config.registration;
+    Some(Registration {
+        url: settings.url.clone()?,
+        format: settings.format.unwrap_or_default(),
+        token: settings.token.clone(),
+    })
+}
+
+/// Scrape targets of the installed components with their actual ports and versions.
+/// With `follow_listen_address`, components bound to one address are scraped there
+/// rather than at `host`'s address.
+fn scrape_target_groups(
+    components: &[Component],
+    setups: &Setups,
+    install_manifest: &InstallManifest,
+    host: &Host,
+    follow_listen_address: bool,
+) -> Vec<TargetGroup> {
+    components
+        .iter()
+        .map(|&component| {
+            let version = install_manifest
+                .get(component)
+                .and_then(|record| record.version.as_deref());
+            let bound_host = match setups.listen_address(component) {
+                Some(ip) if follow_listen_address => Host {
+                    address: ip.to_string(),
+                    hostname: host.hostname.clone(),
+                },
+                _ => host.clone(),
+            };
+            let group =
+                scrape::target_group(&bound_host, component, setups.port(component), version);
+            match &setups.web_config {
+                Some(web_config) if component.serves_web_config() => {
+                    group.with_web_config(web_config)
+                }
+                _ => group,
+            }
+        })
+        .collect()
+}
+
+/// Installer config that installs the selected components without
+/// privileges where they support it. Returns `None` when there is nothing
+/// to switch, including when the config file chose the mode explicitly.
+fn per_user_config(
+    os: &OsType,
+    components: &[Component],
+    config: &InstallerConfig,
+) -> Option<InstallerConfig> {
+    if *os == OsType::Windows
+        && components.contains(&Component::ProcessCpuAgent)
+        && config.process_cpu_agent.windows_mode.is_none()
+    {
+        let mut user_config = config.clone();
+        user_config.process_cpu_agent.windows_mode = Some(WindowsStartMode::ScheduledTask);
+        return Some(user_config);
+    }
+    None
+}
+
+/// Init system to register services with: the `requested` one, otherwise
+/// systemd --user when a regular user installs on a systemd host.
+/// `None` leaves each component with the init system it detects.
+fn resolve_init_system(
+    os: &OsType,
+    requested: Option<InitSystem>,
+    privileged: bool,
+    detected: Option<InitSystem>,
+) -> Option<InitSystem> {
+    if requested.is_some() {
+        return requested;
+    }
+    (*os == OsType::Linux && !privileged && detected == Some(InitSystem::Systemd))
+        .then_some(InitSystem::SystemdUser)
+}
+
+/// Install manifest of a system-wide install, or of a rootless one under the user's home
+fn manifest_path(init_system: Option<InitSystem>) -> String {
+    match init_system {
+        Some(InitSystem::SystemdUser) => manifest::user_manifest_path(),
+        _ => manifest::default_manifest_path(),
+    }
+}
+
+/// Bind each component to the address its `listen_address` setting picks
+/// among the host's `interfaces`. Settings that cannot be satisfied are
+/// returned as preflight findings.
+fn resolve_listen_addresses(
+    components: &[Component],
+    mut setups: Setups,
+    config: &InstallerConfig,
+    interfaces: &[InterfaceAddress],
+) -> (Setups, Vec<Finding>) {
+    let mut findings = Vec::new();
+    for &component in components {
+        let setting = match component {
+            Component::NodeExporter => &config.node_exporter.listen_address,
+            Component::WindowsExporter => &config.windows_exporter.listen_address,
+            Component::ProcessCpuAgent => {
+                // Also shown by plan, whose dry run hides progress output
+                if let Some(setting) = &config.process_cpu_agent.listen_address {
+                    eprintln!(
+                        "Warning: process-cpu-agent.listen_address ({setting}) is not applied; \
+                         the agent has no setting for the address it binds and listens on every interface"
+                    );
+                }
+                continue;
+            }
+        };
+        let Some(setting) = setting else {
+            continue;
+        };
+        match setting.resolve(interfaces) {
+            Ok(ip) => {
+                if let Some(ip) = ip
+                    && matches!(
+                        setting,
+                        ListenAddress::Private | ListenAddress::Interface(_)
+                    )
+                {
+                    progress!(
+                        "{} will listen on {ip} ({setting})",
+                        component.display_name()
+                    );
+                }
+                setups = setups.with_listen_address(component, ip);
+            }
+            Err(reason) => findings.push(Finding {
+                subject: component.name(),
+                problem: Problem::ListenAddressUnavailable { reason },
+            }),
+        }
+    }
+    (setups, findings)
+}
+
+/// Move components whose port another program listens on to the next free
+/// port. With `PortConflictPolicy::Fail`, or when no port is free, the
+/// conflicts are returned as preflight findings instead.
+fn resolve_ports(
+    components: &[Component],
+    mut setups: Setups,
+    policy: PortConflictPolicy,
+    listener: impl Fn(u16) -> Option<Listener>,
+) -> (Setups, Vec<Finding>) {
+    let mut findings = Vec::new();
+    for (index, &component) in components.iter().enumerate() {
+        let port = setups.port(component);
+        // Components installed together would otherwise only clash once running
+        let sibling = components[..index]
+            .iter()
+            .find(|&&other| setups.port(other) == port);
+        let owner = match sibling {
+            Some(other) => format!("{}, which is installed with it", other.display_name()),
+            None => match listener(port).filter(|owner| !owner.is_component(component)) {
+                Some(owner) => owner.describe(),
+                None => continue,
+            },
+        };
+        let alternative = match policy {
+            PortConflictPolicy::NextFree => {
+                let reserved: Vec<u16> = components
+                    .iter()
+                    .filter(|&&other| other != component)
+                    .map(|&other| setups.port(other))
+                    .collect();
+                ports::next_free_port(port, component, &reserved, &listener)
+            }
+            PortConflictPolicy::Fail => None,
+        };
+        match alternative {
+            Some(alternative) => {
+                progress!(
+                    "Port {port} for {} is in use by {owner}; using port {alternative} instead",
+                    component.display_name()
+                );
+                setups = setups.with_port(component, alternative);
+            }
+            None => findings.push(Finding {
+                subject: component.name(),
+                problem: Problem::PortInUse { port, owner },
+            }),
+        }
+    }
+    (setups, findings)
+}
+
+/// Check privileges, target paths and tools before changing anything,
+/// failing with every problem found, including `port_findings`. A dry run only warns.
+fn check_preflight(
+    components: &[Component],
+    setups: &Setups,
+    manifest_path: &str,
+    privileged: bool,
+    port_findings: Vec<Finding>,
+) -> CommandResult {
+    let mut requirements: Vec<(&'static str, Requirements)> = components
+        .iter()
+        .map(|&component| (component.name(), setups.requirements(component)))
+        .collect();
+    requirements.push((
+        "install manifest",
+        Requirements {
+            writable_paths: downloader::get_parent_directory(manifest_path)
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        },
+    ));
+
+    let mut findings = preflight::check(&requirements, privileged);
+    findings.extend(port_findings);
+    if findings.is_empty() {
+        return Ok(());
+    }
+    let report = preflight::format_findings(&findings);
+    if recorder::is_dry_run() {
+        eprintln!("Warning: the installation would fail:\n{report}");
+        return Ok(());
+    }
+    Err(format!(
+        "Preflight checks failed, nothing was changed:\n{report}\n{}",
+        preflight_hint(&findings)
+    )
+    .into())
+}
+
+fn preflight_hint(findings: &[Finding]) -> &'static str {
+    let has = |matches: fn(&Problem) -> bool| findings.iter().any(|f| matches(&f.problem));
+    let missing_tool = has(|problem| matches!(problem, Problem::MissingTool { .. }));
+    let port_in_use = has(|problem| matches!(problem, Problem::PortInUse { .. }));
+    let listen_address = has(|problem| matches!(problem, Problem::ListenAddressUnavailable { .. }));
+    let unsupported = has(|problem| matches!(problem, Problem::Unsupported { .. }));
+    if findings
+        .iter()
+        .any(|finding| finding.problem == Problem::NotPrivileged)
+    {
+        if cfg!(windows) {
+            "Run the installer from an elevated prompt (Run as Administrator) or leave those components out with --skip"
+        } else {
+            "Run the installer with sudo or leave those components out with --skip"
+        }
+    } else if unsupported {
+        "Change those settings in the --config file or pick another service manager with --init-system"
+    } else if missing_tool {
+        "Install the missing tools or pick another service manager with --init-system"
+    } else if port_in_use {
+        "Stop the programs using those ports, set other ports in the --config file or rerun with --on-port-conflict next-free"
+    } else if listen_address {
+        "Set listen_address in the --config file to an interface or address of this host"
+    } else {
+        "Choose writable locations with install_path in the --config file"
+    }
+}
+
+/// Scrape each component's metrics endpoint and print a pass/fail table
+fn verify_health(components: &[Component], setups: &Setups, timeout: Duration) -> CommandResult {
+    progress!(
+        "Verifying metrics endpoints (waiting up to {}s)...",
+        timeout.as_secs()
+    );
+    let targets: Vec<(Component, String)> = components
+        .iter()
+        .map(|&component| (component, setups.local_endpoint(component)))
+        .collect();
+    let checks = health::check_all(&targets, timeout, setups.web_config.as_ref());
+    progress!("{}", health::format_table(&checks));
+
+    let failed: Vec<&str> = checks
+        .iter()
+        .filter(|check| !check.passed())
+        .map(|check| check.component.name())
+        .collect();
+    if !failed.is_empty() {
+        return Err(format!("Health check failed for: {}", failed.join(", ")).into());
+    }
+    Ok(())
+}
+
+/// Report the currently installed versions, then reinstall
+fn upgrade_components(os: &OsType, args: &InstallArgs, config: &InstallerConfig) -> CommandResult {
+    progress!("Upgrading exporters to the bundled versions...\n");
+
+    let init_system = resolve_init_system(
+        os,
+        args.init_system,
+        os_detector::is_privileged(),
+        InitSystem::detect(),
+    );
+    let install_manifest = load_manifest(&manifest_path(init_system));
+    for component in args.selection.resolve(os)? {
+        let current = install_manifest
+            .get(component)
+            .map(format_version)
+            .unwrap_or_else(|| "not installed".to_string());
+        progress!("{}: {current}", component.display_name());
+    }
+    progress!();
+
+    install_components(os, args, config)
+}
+
+/// Remove each component and drop it from the install manifest
+fn uninstall_components(
+    os: &OsType,
+    selection: &SelectionArgs,
+    config: &InstallerConfig,
+) -> CommandResult {
+    let components = selection.resolve(os)?;
+    let init_system =
+        resolve_init_system(os, None, os_detector::is_privileged(), InitSystem::detect());
+    let setups = Setups::new(config, None, init_system);
+
+    let manifest_path = manifest_path(init_system);
+    let mut install_manifest = load_manifest(&manifest_path);
+
+    progress!("Removing exporters from {}...\n", os_label(os));
+
+    let mut failed = Vec::new();
+    for (index, component) in components.iter().enumerate() {
+        progress!("{}. Removing {}...", index + 1, component.display_name());
+        match uninstall_component(*component, install_manifest.get(*component), &setups) {
+            Ok(removed) => {
+                if removed.is_empty() {
+                    progress!("   Nothing to remove");
+                }
+                for item in removed {
+                    progress!("   Removed: {item}");
+                }
+                install_manifest.remove(*component);
+            }
+            Err(e) => {
+                eprintln!("{} removal failed: {e}", component.display_name());
+                failed.push(component.name());
+            }
+        }
+        progress!();
+    }
+
+    if install_manifest.is_empty() {
+        if downloader::remove_path(&manifest_path)? {
+            progress!("Removed: {manifest_path}");
+        }
+        if let Some(root) = downloader::get_parent_directory(&manifest_path)
+            && downloader::remove_dir_if_empty(&root)
+        {
+            progress!("Removed: {root}");
+        }
+    } else {
+        install_manifest.save(&manifest_path)?;
+    }
+
+    if !failed.is_empty() {
+        return Err(format!("Failed to remove: {}", failed.join(", ")).into());
+    }
+
+    progress!("✓ Exporter removal completed successfully!");
+    Ok(())
+}
+
+/// Component setups with the installer config applied
+struct Setups {
+    node_exporter: NodeExporterSetup,
+    windows_exporter: WindowsExporterSetup,
+    process_cpu_agent: ProcessCpuAgentSetup,
+    /// Basic auth and TLS shared by every exporter serving them
+    web_config: Option<WebConfig>,
+}
+
+impl Setups {
+    /// Command line options, when given, take precedence over the config file.
+    /// Services are registered with `init_system` instead of the detected one when set.
+    fn new(
+        config: &InstallerConfig,
+        args: Option<&InstallArgs>,
+        init_system: Option<InitSystem>,
+    ) -> Self {
+        let agent = &config.process_cpu_agent;
+        let (url, sha256) = match args {
+            Some(args) if args.process_cpu_agent_url.is_some() => (
+                args.process_cpu_agent_url.clone(),
+                args.process_cpu_agent_sha256.clone(),
+            ),
+            Some(args) => (
+                agent.url.clone(),
+                args.process_cpu_agent_sha256
+                    .clone()
+                    .or_else(|| agent.sha256.clone()),
+            ),
+            None => (agent.url.clone(), agent.sha256.clone()),
+        };
+
+        let mut node_exporter = NodeExporterSetup::new();
+        let mut process_cpu_agent = ProcessCpuAgentSetup::new(url).with_expected_sha256(sha256);
+        // Before the settings, since the default install paths depend on it
+        if let Some(init) = init_system {
+            node_exporter = node_exporter.with_init_system(init);
+            process_cpu_agent = process_cpu_agent.with_init_system(init);
+        }
+
+        let web_config = WebConfig::from_settings(&config.web);
+        Self {
+            node_exporter: node_exporter
+                .with_settings(&config.node_exporter)
+                .with_web_config(web_config.clone()),
+            windows_exporter: WindowsExporterSetup::new()
+                .with_settings(&config.windows_exporter)
+                .with_web_config(web_config.clone()),
+            process_cpu_agent: process_cpu_agent.with_settings(agent),
+            web_config,
+        }
+    }
+
+    fn requirements(&self, component: Component) -> Requirements {
+        match component {
+            Component::NodeExporter => self.node_exporter.requirements(),
+            Component::WindowsExporter => self.windows_exporter.requirements(),
+            Component::ProcessCpuAgent => self.process_cpu_agent.requirements(),
+        }
+    }
+
+    /// Configure `component` to listen on `port`
+    fn with_port(mut self, component: Component, port: u16) -> Self {
+        match component {
+            Component::NodeExporter => self.node_exporter = self.node_exporter.with_port(port),
+            Component::WindowsExporter => {
+                self.windows_exporter = self.windows_exporter.with_port(port)
+            }
+            Component::ProcessCpuAgent => {
+                self.process_cpu_agent = self.process_cpu_agent.with_port(port)
+            }
+        }
+        self
+    }
+
+    fn port(&self, component: Component) -> u16 {
+        match component {
+            Component::NodeExporter => self.node_exporter.port(),
+            Component::WindowsExporter => self.windows_exporter.port(),
+            Component::ProcessCpuAgent => self.process_cpu_agent.port(),
+        }
+    }
+
+    /// Configure `component` to listen on `ip` only, or on every interface with `None`
+    fn with_listen_address(mut self, component: Component, ip: Option<IpAddr>) -> Self {
+        match component {
+            Component::NodeExporter => {
+                self.node_exporter = self.node_exporter.with_listen_address(ip)
+            }
+            Component::WindowsExporter => {
+                self.windows_exporter = self.windows_exporter.with_listen_address(ip)
+            }
+            // The agent always listens on every interface
+            Component::ProcessCpuAgent => {}
+        }
+        self
+    }
+
+    fn listen_address(&self, component: Component) -> Option<IpAddr> {
+        match component {
+            Component::NodeExporter => self.node_exporter.listen_address(),
+            Component::WindowsExporter => self.windows_exporter.listen_address(),
+            Component::ProcessCpuAgent => None,
+        }
+    }
+
+    /// `host:port` where `component` answers on this host
+    fn local_endpoint(&self, component: Component) -> String {
+        listen::local_endpoint(self.listen_address(component), self.port(component))
+    }
+}
+
+/// Install or repair a component on top of its `previous` record.
+/// Returns what was installed and whether anything changed.
+fn install_component(
+    component: Component,
+    previous: Option<&ComponentRecord>,
+    setups: &Setups,
+) -> Result<(ComponentRecord, bool), Box<dyn std::error::Error>> {
+    match component {
+        Component::NodeExporter => {
+            let changed = setups.node_exporter.setup(previous)?;
+            Ok((setups.node_exporter.manifest_record()?, changed))
+        }
+        Component::WindowsExporter => {
+            let changed = setups.windows_exporter.setup(previous)?;
+            Ok((setups.windows_exporter.manifest_record()?, changed))
+        }
+        Component::ProcessCpuAgent => {
+            let changed = setups.process_cpu_agent.setup(previous)?;
+            Ok((setups.process_cpu_agent.manifest_record()?, changed))
+        }
+    }
+}
+
+fn uninstall_component(
+    component: Component,
+    record: Option<&ComponentRecord>,
+    setups: &Setups,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    match component {
+        Component::NodeExporter => setups
+            .node_exporter
+            .uninstall(record.and_then(|r| r.init_system())),
+        Component::WindowsExporter => setups
+            .windows_exporter
+            .uninstall(record.and_then(|r| r.msi_product_code())),
+        Component::ProcessCpuAgent => setups
+            .process_cpu_agent
+            .uninstall(record.and_then(|r| r.init_system())),
+    }
+}
+
+/// Load the install manifest, falling back to an empty one if it is unreadable
+fn load_manifest(path: &str) -> InstallManifest {
+    InstallManifest::load(path).unwrap_or_else(|e| {
+        eprintln!("Warning: {e}; ignoring existing install manifest");
+        InstallManifest::default()
+    })
+}
+
+fn format_version(record: &ComponentRecord) -> String {
+    match &record.version {
+        Some(version) => format!("{version} ({})", record.source.describe()),
+        None => record.source.describe(),
+    }
+}
+
+fn os_label(os: &OsType) -> &'static str {
+    match os {
+        OsType::Linux => "Linux",
+        OsType::Windows => "Windows",
+        OsType::MacOs => "macOS",
+        OsType::Unknown => "unknown OS",
+    }
+}
+
+fn format_status_row(status: &ComponentStatus, record: Option<&ComponentRecord>) -> String {
+    let installed = if status.installed { "yes" } else { "no" };
+    let service = match status.running {
+        Some(true) => "running",
+        Some(false) => "stopped",
+        None => "unknown",
+    };
+    let (version, files) = match record {
+        Some(record) => {
+            let modified = record.modified_files().len();
+            let files = if modified == 0 {
+                "ok".to_string()
+            } else {
+                format!("{modified} modified")
+            };
+            (
+                record.version.clone().unwrap_or_else(|| "-".to_string()),
+                files,
+            )
+        }
+        None => ("-".to_string(), "untracked".to_string()),
+    };
+    format!(
+        "{:<20} {:<10} {:<10} {:<16} {service}",
+        status.component.name(),
+        installed,
+        version,
+        files
+    )
+}
+
+fn print_next_steps(
+    os: &OsType,
+    components: &[Component],
+    setups: &Setups,
+    groups: &[TargetGroup],
+) {
+    let mut steps = Vec::new();
+    let mut user_units = false;
+    for component in components {
+        match (os, component) {
+            (
+                OsType::Linux | OsType::MacOs,
+                Component::NodeExporter | Component::ProcessCpuAgent,
+            ) => {
+                let (service, init) = match component {
+                    Component::NodeExporter => {
+                        ("node_exporter", setups.node_exporter.init_system())
+                    }
+                    _ => ("process-cpu-agent", setups.process_cpu_agent.init_system()),
+                };
+                user_units |= init == Some(InitSystem::SystemdUser);
+                steps.push(match init {
+                    Some(init) => format!(
+                        "Start {}: {}",
+                        component.display_name(),
+                        init.start_command(service)
+                    ),
+                    None => format!(
+                        "Configure a service for {} with your init system",
+                        component.display_name()
+                    ),
+                });
+            }
+            (OsType::Windows, Component::WindowsExporter) => {
+                steps.push("Check Windows Exporter: sc.exe query windows_exporter".to_string());
+            }
+            (OsType::Windows, Component::ProcessCpuAgent) => {
+                let agent = &setups.process_cpu_agent;
+                steps.push(match agent.windows_mode() {
+                    WindowsStartMode::Service => format!(
+                        "Process CPU Agent runs as a Windows service ({}):\n   \
+                         - Check service: sc.exe query ProcessCpuAgent",
+                        agent.windows_service_account().object_name()
+                    ),
+                    WindowsStartMode::ScheduledTask => {
+                        "Process CPU Agent is registered as a scheduled task:\n   \
+                         - Check task: schtasks /Query /TN ProcessCpuAgent\n   \
+                         - Run once now: schtasks /Run /TN ProcessCpuAgent"
+                            .to_string()
+                    }
+                });
+            }
+            _ => {}
+        }
+    }
+    // The user manager, and every service in it, stops when the user's last session ends
+    if user_units && service::user_lingering() != Some(true) {
+        steps.push(
+            "Keep the services running after you log out: loginctl enable-linger".to_string(),
+        );
+    }
+    let tls = setups
+        .web_config
+        .as_ref()
+        .is_some_and(WebConfig::tls_enabled);
+    for component in components {
+        let tls = tls && component.serves_web_config();
+        let url = health::metrics_url(&setups.local_endpoint(*component), tls);
+        steps.push(format!("Check {} metrics: {url}", component.display_name()));
+    }
+    steps.push(
+        "Configure Prometheus to scrape these exporters, for example in prometheus.yml:"
+            .to_string(),
+    );
+
+    progress!("Next steps:");
+    for (index, step) in steps.iter().enumerate() {
+        progress!("{}. {step}", index + 1);
+    }
+    for line in scrape::scrape_configs_yaml(groups).lines() {
+        progress!("   {line}");
+    }
+
+    progress!("\n📌 Custom Download URLs:");
+    progress!("   You can specify a custom Process CPU Agent download URL:");
+    progress!(
+        "   - Via environment variable: PROCESS_CPU_AGENT_URL=<url> PROCESS_CPU_AGENT_SHA256=<sha256> ./prometheus-agents-setup"
+    );
+    progress!(
+        "   - Via command line option: ./prometheus-agents-setup install --process-cpu-agent-url <url> --sha256 <sha256>"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_status_row() {
+        let status = ComponentStatus {
+            component: Component::NodeExporter,
+            installed: true,
+            running: Some(true),
+        };
+        let record = ComponentRecord::new(
+            Component::NodeExporter,
+            Some("1.7.0".to_string()),
+            ArtifactSource::Embedded,
+        );
+        let row = format_status_row(&status, Some(&record));
+        assert!(row.starts_with("node_exporter"));
+        assert!(row.contains("yes"));
+        assert!(row.contains("1.7.0"));
+        assert!(row.contains("ok"));
+        assert!(row.ends_with("running"));
+
+        let status = ComponentStatus {
+            component: Component::ProcessCpuAgent,
+            installed: false,
+            running: None,
+        };
+        let row = format_status_row(&status, None);
+        assert!(row.contains("no"));
+        assert!(row.contains("untracked"));
+        assert!(row.ends_with("unknown"));
+    }
+
+    #[test]
+    fn test_format_version() {
+        let record = ComponentRecord::new(
+            Component::ProcessCpuAgent,
+            None,
+            ArtifactSource::Remote {
+                url: "https://example.com/agent".to_string(),
+            },
+        );
+        assert_eq!(format_version(&record), "https://example.com/agent");
+
+        let record = ComponentRecord::new(
+            Component::NodeExporter,
+            Some("1.7.0".to_string()),
+            ArtifactSource::Embedded,
+        );
+        assert_eq!(format_version(&record), "1.7.0 (embedded)");
+    }
+
+    #[test]
+    fn test_os_label() {
+        assert_eq!(os_label(&OsType::Linux), "Linux");
+        assert_eq!(os_label(&OsType::MacOs), "macOS");
+    }
+
+    #[test]
+    fn test_install_rejects_unsupported_selection() {
+        let args = InstallArgs {
+            selection: SelectionArgs {
+                only: vec![Component::WindowsExporter],
+                ..Default::default()
+            },
+            process_cpu_agent_url: None,
+            process_cpu_agent_sha256: None,
+            init_system: None,
+            on_port_conflict: Default::default(),
+            dry_run: Default::default(),
+            health: Default::default(),
+            targets: Default::default(),
+        };
+        assert!(install(&OsType::Linux, &args, &InstallerConfig::default()).is_err());
+    }
+
+    #[test]
+    fn test_format_plan_text() {
+        let actions = vec![
+            Action::CreateDirectory {
+                path: "/opt/prometheus".to_string(),
+            },
+            Action::RunCommand {
+                program: "systemctl".to_string(),
+                args: vec!["daemon-reload".to_string()],
+            },
+        ];
+        assert_eq!(
+            format_plan_text("install", &actions),
+            "Planned actions for install (nothing has been changed):\n  \
+             1. create directory /opt/prometheus\n  \
+             2. run systemctl daemon-reload\n"
+        );
+        assert!(format_plan_text("uninstall", &[]).contains("(none)"));
+    }
+
+    #[test]
+    fn test_per_user_config() {
+        let config = InstallerConfig::default();
+        let all = [Component::WindowsExporter, Component::ProcessCpuAgent];
+        let user_config = per_user_config(&OsType::Windows, &all, &config).unwrap();
+        assert_eq!(
+            user_config.process_cpu_agent.windows_mode,
+            Some(WindowsStartMode::ScheduledTask)
+        );
+
+        assert!(
+            per_user_config(&OsType::Windows, &[Component::WindowsExporter], &config).is_none()
+        );
+        assert!(per_user_config(&OsType::Linux, &all[1..], &config).is_none());
+
+        let mut explicit = InstallerConfig::default();
+        explicit.process_cpu_agent.windows_mode = Some(WindowsStartMode::Service);
+        assert!(per_user_config(&OsType::Windows, &all, &explicit).is_none());
+    }
+
+    #[test]
+    fn test_resolve_init_system() {
+        let systemd = Some(InitSystem::Systemd);
+        assert_eq!(
+            resolve_init_system(&OsType::Linux, None, false, systemd),
+            Some(InitSystem::SystemdUser)
+        );
+        assert_eq!(
+            resolve_init_system(&OsType::Linux, None, true, systemd),
+            None
+        );
+        assert_eq!(
+            resolve_init_system(&OsType::Linux, None, false, Some(InitSystem::OpenRc)),
+            None
+        );
+        assert_eq!(
+            resolve_init_system(&OsType::Linux, systemd, false, systemd),
+            systemd
+        );
+        assert_eq!(
+            resolve_init_system(&OsType::MacOs, None, false, Some(InitSystem::Launchd)),
+            None
+        );
+
+        assert_eq!(
+            manifest_path(Some(InitSystem::SystemdUser)),
+            manifest::user_manifest_path()
+        );
+        assert_eq!(manifest_path(systemd), manifest::default_manifest_path());
+    }
+
+    #[test]
+    fn test_resolve_ports() {
+        let components = [Component::NodeExporter, Component::ProcessCpuAgent];
+        let nginx = Listener {
+            pid: Some(812),
+            name: Some("nginx".to_string()),
+        };
+        let listener = |port: u16| match port {
+            31415 | 31417 => Some(nginx.clone()),
+            31416 => Some(Listener {
+                pid: None,
+                name: Some("process-cpu-age".to_string()),
+            }),
+            _ => None,
+        };
+        let setups = Setups::new(&InstallerConfig::default(), None, None);
+
+        let (moved, findings) = resolve_ports(
+            &components,
+            Setups::new(&InstallerConfig::default(), None, None),
+            PortConflictPolicy::NextFree,
+            listener,
+        );
+        assert!(findings.is_empty());
+        // 31416 belongs to the agent and 31417 is taken
+        assert_eq!(moved.port(Component::NodeExporter), 31418);
+        assert_eq!(moved.port(Component::ProcessCpuAgent), 31416);
+
+        let (kept, findings) =
+            resolve_ports(&components, setups, PortConflictPolicy::Fail, listener);
+        assert_eq!(kept.port(Component::NodeExporter), 31415);
+        assert_eq!(
+            findings,
+            vec![Finding {
+                subject: "node_exporter",
+                problem: Problem::PortInUse {
+                    port: 31415,
+                    owner: "nginx (pid 812)".to_string(),
+                },
+            }]
+        );
+        assert!(preflight_hint(&findings).contains("--on-port-conflict"));
+    }
+
+    #[test]
+    fn test_resolve_ports_between_components() {
+        let components = [Component::NodeExporter, Component::ProcessCpuAgent];
+        let shared = || {
+            Setups::new(&InstallerConfig::default(), None, None)
+                .with_port(Component::ProcessCpuAgent, 31415)
+        };
+
+        // Nothing listens yet, so only the ports chosen for both reveal the clash
+        let (moved, findings) =
+            resolve_ports(&components, shared(), PortConflictPolicy::NextFree, |_| {
+                None
+            });
+        assert!(findings.is_empty());
+        assert_eq!(moved.port(Component::NodeExporter), 31415);
+        assert_eq!(moved.port(Component::ProcessCpuAgent), 31416);
+
+        let (_, findings) =
+            resolve_ports(&components, shared(), PortConflictPolicy::Fail, |_| None);
+        assert_eq!(
+            findings,
+            vec![Finding {
+                subject: "process-cpu-agent",
+                problem: Problem::PortInUse {
+                    port: 31415,
+                    owner: "Node Exporter, which is installed with it".to_string(),
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn test_scrape_target_groups() {
+        let host = Host {
+            address: "10.0.0.5".to_string(),
+            hostname: "web-01".to_string(),
+        };
+        let mut install_manifest = InstallManifest::default();
+        install_manifest.record(ComponentRecord::new(
+            Component::NodeExporter,
+            Some("1.7.0".to_string()),
+            ArtifactSource::Embedded,
+        ));
+        let setups = Setups::new(&InstallerConfig::default(), None, None)
+            .with_port(Component::ProcessCpuAgent, 31418);
+
+        let groups = scrape_target_groups(
+            &[Component::NodeExporter, Component::ProcessCpuAgent],
+            &setups,
+            &install_manifest,
+            &host,
+            true,
+        );
+        assert_eq!(groups[0].targets, vec!["10.0.0.5:31415"]);
+        assert_eq!(groups[0].labels["version"], "1.7.0");
+        assert_eq!(groups[1].targets, vec!["10.0.0.5:31418"]);
+        assert!(!groups[1].labels.contains_key("version"));
+
+        // Bound components are scraped where they listen, unless the address was given
+        let setups = setups.with_listen_address(
+            Component::NodeExporter,
+            Some("192.168.1.9".parse().unwrap()),
+        );
+        let components = [Component::NodeExporter];
+        let groups = scrape_target_groups(&components, &setups, &install_manifest, &host, true);
+        assert_eq!(groups[0].targets, vec!["192.168.1.9:31415"]);
+        assert_eq!(groups[0].address, "192.168.1.9");
+        let groups = scrape_target_groups(&components, &setups, &install_manifest, &host, false);
+        assert_eq!(groups[0].targets, vec!["10.0.0.5:31415"]);
+
+        // The agent cannot be bound
+        let setups = setups.with_listen_address(
+            Component::ProcessCpuAgent,
+            Some("192.168.1.9".parse().unwrap()),
+        );
+        assert_eq!(setups.listen_address(Component::ProcessCpuAgent), None);
+
+        // The agent keeps serving plain HTTP when the exporters use TLS
+        let mut config = InstallerConfig::default();
+        config.web.self_signed_cert = Some(true);
+        let groups = scrape_target_groups(
+            &[Component::NodeExporter, Component::ProcessCpuAgent],
+            &Setups::new(&config, None, None),
+            &install_manifest,
+            &host,
+            true,
+        );
+        assert_eq!(groups[0].scheme(), "https");
+        assert_eq!(groups[1].scheme(), "http");
+    }
+
+    #[test]
+    fn test_resolve_listen_addresses() {
+        let mut config = InstallerConfig::default();
+        config.node_exporter.listen_address = Some(ListenAddress::Private);
+        config.windows_exporter.listen_address = Some("eth9".parse().unwrap());
+        let interfaces = vec![InterfaceAddress {
+            interface: "eth0".to_string(),
+            ip: "10.0.0.5".parse().unwrap(),
+        }];
+        let components = [
+            Component::NodeExporter,
+            Component::WindowsExporter,
+            Component::ProcessCpuAgent,
+        ];
+
+        let (setups, findings) = resolve_listen_addresses(
+            &components,
+            Setups::new(&config, None, None),
+            &config,
+            &interfaces,
+        );
+        assert_eq!(
+            setups.local_endpoint(Component::NodeExporter),
+            "10.0.0.5:31415"
+        );
+        assert_eq!(setups.listen_address(Component::ProcessCpuAgent), None);
+        assert_eq!(
+            findings,
+            vec![Finding {
+                subject: "windows_exporter",
+                problem: Problem::ListenAddressUnavailable {
+                    reason: "interface eth9 has no usable address".to_string(),
+                },
+            }]
+        );
+        assert!(preflight_hint(&findings).contains("listen_address"));
+    }
+
+    #[test]
+    fn test_preflight_hint() {
+        let finding = |problem| Finding {
+            subject: "node_exporter",
+            problem,
+        };
+        let not_writable = finding(Problem::NotWritable {
+            path: "/opt/prometheus".to_string(),
+        });
+        let missing_tool = finding(Problem::MissingTool { tool: "systemctl" });
+        assert!(preflight_hint(std::slice::from_ref(&not_writable)).contains("install_path"));
+        assert!(preflight_hint(&[not_writable.clone(), missing_tool]).contains("--init-system"));
+        assert!(
+            preflight_hint(&[not_writable.clone(), finding(Problem::NotPrivileged)])
+                .contains("--skip")
+        );
+        let unsupported = finding(Problem::Unsupported {
+            reason: "textfile producer smart-summary needs root".to_string(),
+        });
+        assert!(preflight_hint(&[not_writable, unsupported]).contains("--config"));
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn test_dry_run_install_only_records_actions() {
+        use crate::exporter::init_system::InitSystem;
+        use crate::exporter::process_exporter;
+
+        let args = InstallArgs {
+            selection: SelectionArgs {
+                only: vec![Component::ProcessCpuAgent],
+                ..Default::default()
+            },
+            init_system: Some(InitSystem::Systemd),
+            ..Default::default()
+        };
+        let config = InstallerConfig::default();
+        let (result, actions) =
+            recorder::dry_run(|| install_components(&OsType::Linux, &args, &config));
+        result.unwrap();
+
+        let binary =
+            process_exporter::get_binary_path(&process_exporter::get_default_install_path());
+        assert!(actions.contains(&Action::WriteFile {
+            path: binary.clone()
+        }));
+        assert!(actions.contains(&Action::RunCommand {
+            program: "systemctl".to_string(),
+            args: vec![
+                "enable".to_string(),
+                "--now".to_string(),
+                "process-cpu-agent".to_string()
+            ],
+        }));
+        assert!(!std::path::Path::new(&binary).exists());
+    }
+}
@@ -0,0 +1,668 @@
+use crate::exporter::downloader;
+use crate::exporter::listen::ListenAddress;
+use crate::exporter::node_collectors::CollectorProfile;
+use crate::exporter::node_exporter::{NODE_EXPORTER_PORT, NODE_EXPORTER_VERSION};
+use crate::exporter::process_exporter::PROCESS_CPU_AGENT_PORT;
+use crate::exporter::registration::RegistrationFormat;
+use crate::exporter::service::{HardeningProfile, WindowsServiceAccount, WindowsStartMode};
+use crate::exporter::textfile::TextfileProducer;
+use crate::exporter::web_config;
+use crate::exporter::windows_config::{self, DEFAULT_COLLECTORS};
+use crate::exporter::windows_exporter::WINDOWS_EXPORTER_PORT;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+
+/// Installer configuration loaded with `--config`.
+/// Every setting is optional; unset values fall back to the built-in defaults.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InstallerConfig {
+    pub node_exporter: NodeExporterSettings,
+    pub windows_exporter: WindowsExporterSettings,
+    #[serde(rename = "process-cpu-agent", alias = "process_cpu_agent")]
+    pub process_cpu_agent: ProcessCpuAgentSettings,
+    pub registration: RegistrationSettings,
+    pub web: WebSettings,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeExporterSettings {
+    pub version: Option<String>,
+    pub port: Option<u16>,
+    /// Interface to listen on instead of all of them
+    pub listen_address: Option<ListenAddress>,
+    pub install_path: Option<String>,
+    /// Sandboxing of the systemd unit
+    pub hardening: Option<HardeningProfile>,
+    /// Collectors enabled or disabled on top of the upstream defaults, and their flags
+    pub collectors: Option<CollectorProfile>,
+    /// Directory the textfile collector reads `.prom` files from
+    pub textfile_directory: Option<String>,
+    /// Bundled jobs writing into the textfile directory on systemd timers
+    pub textfile_producers: Option<Vec<TextfileProducer>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowsExporterSettings {
+    pub version: Option<String>,
+    pub port: Option<u16>,
+    /// Interface to listen on instead of all of them
+    pub listen_address: Option<ListenAddress>,
+    pub install_path: Option<String>,
+    /// Collectors passed to the MSI and written to the config file
+    pub collectors: Option<Vec<String>>,
+    /// WQL `WHERE` clauses by collector, replacing the default service filter
+    pub where_clauses: Option<BTreeMap<String, String>>,
+    /// Directory the textfile collector reads `.prom` files from
+    pub textfile_directory: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProcessCpuAgentSettings {
+    pub port: Option<u16>,
+    /// Not applied: the agent has no setting for the address it binds and
+    /// always listens on every interface, which install and plan warn about
+    pub listen_address: Option<ListenAddress>,
+    pub install_path: Option<String>,
+    /// Download the agent from this URL instead of using the embedded binary
+    pub url: Option<String>,
+    /// Expected SHA-256 of the binary at `url`
+    pub sha256: Option<String>,
+    /// Sandboxing of the systemd unit
+    pub hardening: Option<HardeningProfile>,
+    /// Windows service or logon scheduled task
+    pub windows_mode: Option<WindowsStartMode>,
+    /// Account the Windows service runs as
+    pub windows_service_account: Option<WindowsServiceAccount>,
+}
+
+/// Service discovery endpoint the installed exporters are announced to
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RegistrationSettings {
+    /// Registration is skipped unless this is set
+    pub url: Option<String>,
+    pub format: Option<RegistrationFormat>,
+    pub token: Option<String>,
+}
+
+/// Basic auth and TLS of every exporter's metrics endpoint
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebSettings {
+    /// User names with plain passwords, which are bcrypt-hashed, or bcrypt hashes
+    pub basic_auth_users: Option<BTreeMap<String, String>>,
+    pub tls_cert_file: Option<String>,
+    pub tls_key_file: Option<String>,
+    /// Generate a self-signed certificate at the TLS paths, or next to
+    /// `web-config.yml` without them, when it does not exist yet
+    pub self_signed_cert: Option<bool>,
+}
+
+impl InstallerConfig {
+    /// Load and validate an installer configuration file
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read installer config {path}: {e}"))?;
+        let config =
+            Self::parse(&content).map_err(|e| format!("Invalid installer config {path}: {e}"))?;
+        Ok(config)
+    }
+
+    /// Parse and validate configuration from TOML text
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut config: Self = toml::from_str(content).map_err(|e| e.to_string())?;
+        config.validate()?;
+        if let Some(sha256) = &config.process_cpu_agent.sha256 {
+            config.process_cpu_agent.sha256 = Some(downloader::normalize_sha256(sha256)?);
+        }
+        Ok(config)
+    }
+
+    /// Check every configured value, reporting all problems at once
+    pub fn validate(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+
+        let node = &self.node_exporter;
+        check_version(
+            "node_exporter.version",
+            node.version.as_deref(),
+            &mut errors,
+        );
+        check_port("node_exporter.port", node.port, &mut errors);
+        check_install_path(
+            "node_exporter.install_path",
+            node.install_path.as_deref(),
+            &mut errors,
+        );
+        if let Some(collectors) = &node.collectors {
+            errors.extend(collectors.validate(
+                "node_exporter.collectors",
+                node.version.as_deref().unwrap_or(NODE_EXPORTER_VERSION),
+            ));
+        }
+        check_install_path(
+            "node_exporter.textfile_directory",
+            node.textfile_directory.as_deref(),
+            &mut errors,
+        );
+        let collectors = node.collectors.clone().unwrap_or_default();
+        if node.textfile_directory.is_some() && collectors.flags.contains_key("textfile.directory")
+        {
+            errors.push(
+                "node_exporter.textfile_directory and the textfile.directory collector flag are both set"
+                    .to_string(),
+            );
+        }
+        let producers = node.textfile_producers.as_deref().unwrap_or_default();
+        if !producers.is_empty() && !collectors.runs_default("textfile") {
+            errors.push(
+                "node_exporter.textfile_producers need the textfile collector, which is disabled"
+                    .to_string(),
+            );
+        }
+        let mut seen = HashSet::new();
+        for producer in producers {
+            if !seen.insert(producer) {
+                errors.push(format!(
+                    "node_exporter.textfile_producers lists {:?} more than once",
+                    producer.name()
+                ));
+            }
+        }
+
+        let windows = &self.windows_exporter;
+        check_version(
+            "windows_exporter.version",
+            windows.version.as_deref(),
+            &mut errors,
+        );
+        check_port("windows_exporter.port", windows.port, &mut errors);
+        check_install_path(
+            "windows_exporter.install_path",
+            windows.install_path.as_deref(),
+            &mut errors,
+        );
+        if let Some(collectors) = &windows.collectors {
+            check_collectors("windows_exporter.collectors", collectors, &mut errors);
+        }
+        let enabled = |name: &str| match &windows.collectors {
+            Some(collectors) => collectors.iter().any(|collector| collector == name),
+            None => DEFAULT_COLLECTORS.contains(&name),
+        };
+        for (collector, clause) in windows.where_clauses.iter().flatten() {
+            let key = format!("windows_exporter.where_clauses.{collector}");
+            if windows_config::where_option(collector).is_none() {
+                errors.push(format!(
+                    "{key}: the {collector:?} collector takes no WHERE clause"
+                ));
+            } else if !enabled(collector) {
+                errors.push(format!("{key} is set, but the collector is not enabled"));
+            }
+            if clause.trim().is_empty() || clause.chars().any(char::is_control) {
+                errors.push(format!(
+                    "{key} must be a non-empty clause without control characters"
+                ));
+            }
+        }
+        check_install_path(
+            "windows_exporter.textfile_directory",
+            windows.textfile_directory.as_deref(),
+            &mut errors,
+        );
+        if windows.textfile_directory.is_some() && !enabled("textfile") {
+            errors.push(
+                "windows_exporter.textfile_directory needs the textfile collector, which is not enabled"
+                    .to_string(),
+            );
+        }
+
+        let agent = &self.process_cpu_agent;
+        check_port("process-cpu-agent.port", agent.port, &mut errors);
+        check_install_path(
+            "process-cpu-agent.install_path",
+            agent.install_path.as_deref(),
+            &mut errors,
+        );
+        check_url("process-cpu-agent.url", agent.url.as_deref(), &mut errors);
+        match (&agent.url, &agent.sha256) {
+            (Some(_), None) => {
+                errors.push("process-cpu-agent.url requires process-cpu-agent.sha256".to_string())
+            }
+            (_, Some(sha256)) => {
+                if let Err(e) = downloader::normalize_sha256(sha256) {
+                    errors.push(format!("process-cpu-agent.sha256: {e}"));
+                }
+            }
+            _ => {}
+        }
+
+        let registration = &self.registration;
+        check_url("registration.url", registration.url.as_deref(), &mut errors);
+        if registration.url.is_none()
+            && (registration.format.is_some() || registration.token.is_some())
+        {
+            errors.push(
+                "registration.format and registration.token require registration.url".to_string(),
+            );
+        }
+
+        let web = &self.web;
+        for (user, secret) in web.basic_auth_users.iter().flatten() {
+            if user.is_empty() || user.contains(':') {
+                errors.push(format!(
+                    "web.basic_auth_users contains an invalid user name {user:?}"
+                ));
+            }
+            if secret.is_empty() {
+                errors.push(format!("web.basic_auth_users.{user} has an empty password"));
+            } else if secret.starts_with("$2") && !web_config::is_bcrypt_hash(secret) {
+                errors.push(format!(
+                    "web.basic_auth_users.{user} looks like a truncated bcrypt hash"
+                ));
+            }
+        }
+        match (&web.tls_cert_file, &web.tls_key_file) {
+            (Some(_), None) | (None, Some(_)) => errors
+                .push("web.tls_cert_file and web.tls_key_file must be set together".to_string()),
+            (Some(cert), Some(key)) => {
+                check_install_path("web.tls_cert_file", Some(cert), &mut errors);
+                check_install_path("web.tls_key_file", Some(key), &mut errors);
+            }
+            (None, None) => {}
+        }
+
+        // Exporters sharing a host with the agent must not share its port,
+        // whether set here or left at the default
+        let effective = |key: &str, port: Option<u16>, default: u16| match port {
+            Some(port) => (key.to_string(), port),
+            None => (format!("the default {key}"), default),
+        };
+        let (agent_key, agent_port) =
+            effective("process-cpu-agent.port", agent.port, PROCESS_CPU_AGENT_PORT);
+        for (key, port) in [
+            effective("node_exporter.port", node.port, NODE_EXPORTER_PORT),
+            effective("windows_exporter.port", windows.port, WINDOWS_EXPORTER_PORT),
+        ] {
+            if port == agent_port {
+                errors.push(format!("{key} and {agent_key} are both {agent_port}"));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+}
+
+fn check_version(key: &str, version: Option<&str>, errors: &mut Vec<String>) {
+    let Some(version) = version else { return };
+    let valid = !version.is_empty()
+        && version
+            .split('.')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()));
+    if !valid {
+        errors.push(format!(
+            "{key} must be a release version such as 1.7.0, got {version:?}"
+        ));
+    }
+}
+
+fn check_port(key: &str, port: Option<u16>, errors: &mut Vec<String>) {
+    if port == Some(0) {
+        errors.push(format!("{key} must be between 1 and 65535"));
+    }
+}
+
+fn check_url(key: &str, url: Option<&str>, errors: &mut Vec<String>) {
+    let Some(url) = url else { return };
+    if !(url.starts_with("https://") || url.starts_with("http://")) {
+        errors.push(format!("{key} must be an http(s) URL, got {url:?}"));
+    }
+}
+
+fn check_install_path(key: &str, path: Option<&str>, errors: &mut Vec<String>) {
+    let Some(path) = path else { return };
+    if !is_absolute_path(path) {
+        errors.push(format!("{key} must be an absolute path, got {path:?}"));
+    }
+}
+
+fn check_collectors(key: &str, collectors: &[String], errors: &mut Vec<String>) {
+    if collectors.is_empty() {
+        errors.push(format!("{key} must not be empty"));
+    }
+    let mut seen = HashSet::new();
+    for collector in collectors {
+        let valid = !collector.is_empty()
+            && collector
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '.');
+        if !valid {
+            errors.push(format!(
+                "{key} contains an invalid collector name {collector:?}"
+            ));
+        } else if !seen.insert(collector) {
+            errors.push(format!("{key} lists {collector:?} more than once"));
+        }
+    }
+}
+
+/// Absolute on either platform, so configs can be validated anywhere
+fn is_absolute_path(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    path.starts_with('/')
+        || path.starts_with("\\\\")
+        || (bytes.len() >= 3
+            && bytes[0].is_ascii_alphabetic()
+            && bytes[1] == b':'
+            && (bytes[2] == b'\\' || bytes[2] == b'/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const SAMPLE: &str = r#"
+[node_exporter]
+version = "1.8.2"
+port = 9100
+listen_address = "loopback"
+install_path = "/srv/prometheus"
+
+[windows_exporter]
+port = 9182
+install_path = 'D:\prometheus'
+collectors = ["cpu", "memory", "net"]
+
+[process-cpu-agent]
+port = 9256
+url = "https://example.com/process-cpu-agent"
+sha256 = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA"
+"#;
+
+    #[test]
+    fn test_example_config_parses() {
+        let example = include_str!("../lib/agents.example.toml");
+        assert_eq!(
+            InstallerConfig::parse(example).unwrap(),
+            InstallerConfig::default()
+        );
+    }
+
+    #[test]
+    fn test_empty_config_uses_defaults() {
+        let config = InstallerConfig::parse("").unwrap();
+        assert_eq!(config, InstallerConfig::default());
+    }
+
+    #[test]
+    fn test_parse_sample() {
+        let config = InstallerConfig::parse(SAMPLE).unwrap();
+        assert_eq!(config.node_exporter.version.as_deref(), Some("1.8.2"));
+        assert_eq!(config.node_exporter.port, Some(9100));
+        assert_eq!(
+            config.node_exporter.listen_address,
+            Some(ListenAddress::Loopback)
+        );
+        assert_eq!(
+            config.windows_exporter.install_path.as_deref(),
+            Some("D:\\prometheus")
+        );
+        assert_eq!(
+            config.windows_exporter.collectors,
+            Some(vec![
+                "cpu".to_string(),
+                "memory".to_string(),
+                "net".to_string()
+            ])
+        );
+        assert_eq!(config.process_cpu_agent.port, Some(9256));
+        assert_eq!(config.process_cpu_agent.sha256, Some("a".repeat(64)));
+    }
+
+    #[test]
+    fn test_hardening_profiles() {
+        let config = InstallerConfig::parse(
+            "[node_exporter]\nhardening = \"none\"\n[process-cpu-agent]\nhardening = \"strict\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.node_exporter.hardening, Some(HardeningProfile::None));
+        assert_eq!(
+            config.process_cpu_agent.hardening,
+            Some(HardeningProfile::Strict)
+        );
+        assert!(InstallerConfig::parse("[node_exporter]\nhardening = \"paranoid\"\n").is_err());
+    }
+
+    #[test]
+    fn test_windows_start_modes() {
+        let config = InstallerConfig::parse(
+            "[process-cpu-agent]\nwindows_mode = \"scheduled-task\"\n\
+             windows_service_account = \"networkservice\"\n",
+        )
+        .unwrap();
+        let agent = &config.process_cpu_agent;
+        assert_eq!(agent.windows_mode, Some(WindowsStartMode::ScheduledTask));
+        assert_eq!(
+            agent.windows_service_account,
+            Some(WindowsServiceAccount::NetworkService)
+        );
+        assert!(
+            InstallerConfig::parse("[process-cpu-agent]\nwindows_mode = \"startup\"\n").is_err()
+        );
+        assert!(
+            InstallerConfig::parse("[process-cpu-agent]\nwindows_service_account = \"system\"\n")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_registration() {
+        let config = InstallerConfig::parse(
+            "[registration]\nurl = \"http://127.0.0.1:8500/v1/agent/service/register\"\n\
+             format = \"file-sd\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.registration.format, Some(RegistrationFormat::FileSd));
+
+        let error = InstallerConfig::parse("[registration]\nurl = \"consul:8500\"\n").unwrap_err();
+        assert!(error.contains("registration.url must be an http(s) URL"));
+        assert!(InstallerConfig::parse("[registration]\ntoken = \"secret\"\n").is_err());
+        assert!(InstallerConfig::parse("[registration]\nformat = \"etcd\"\n").is_err());
+    }
+
+    #[test]
+    fn test_web_settings() {
+        let config = InstallerConfig::parse(
+            "[web]\nself_signed_cert = true\n\
+             basic_auth_users = { prometheus = \"secret\" }\n",
+        )
+        .unwrap();
+        assert_eq!(config.web.self_signed_cert, Some(true));
+        assert_eq!(config.web.basic_auth_users.unwrap()["prometheus"], "secret");
+
+        let error = InstallerConfig::parse(
+            "[web]\ntls_cert_file = \"/etc/ssl/exporter.crt\"\n\
+             basic_auth_users = { \"a:b\" = \"secret\", prometheus = \"$2b$10$short\" }\n",
+        )
+        .unwrap_err();
+        assert!(error.contains("must be set together"));
+        assert!(error.contains("invalid user name \"a:b\""));
+        assert!(error.contains("truncated bcrypt hash"));
+    }
+
+    #[test]
+    fn test_node_exporter_collectors() {
+        let config = InstallerConfig::parse(
+            "[node_exporter.collectors]\nenable = [\"systemd\"]\ndisable = [\"wifi\"]\n\
+             flags = { \"filesystem.mount-points-exclude\" = '^/(dev|sys)($|/)' }\n",
+        )
+        .unwrap();
+        let collectors = config.node_exporter.collectors.unwrap();
+        assert_eq!(collectors.enable, vec!["systemd"]);
+        assert_eq!(
+            collectors.flags["filesystem.mount-points-exclude"],
+            "^/(dev|sys)($|/)"
+        );
+
+        let error =
+            InstallerConfig::parse("[node_exporter.collectors]\nenable = [\"gpu\"]\n").unwrap_err();
+        assert!(error.contains("node_exporter 1.7.0 does not have"));
+        // A pinned version may have collectors the bundled one lacks
+        assert!(
+            InstallerConfig::parse(
+                "[node_exporter]\nversion = \"1.9.0\"\ncollectors = { enable = [\"gpu\"] }\n"
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_textfile_settings() {
+        let config = InstallerConfig::parse(
+            "[node_exporter]\ntextfile_directory = \"/srv/textfile\"\n\
+             textfile_producers = [\"apt-updates\", \"reboot-required\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.node_exporter.textfile_producers,
+            Some(vec![
+                TextfileProducer::AptUpdates,
+                TextfileProducer::RebootRequired
+            ])
+        );
+
+        assert!(
+            InstallerConfig::parse("[node_exporter]\ntextfile_producers = [\"ntp\"]\n").is_err()
+        );
+        let error = InstallerConfig::parse(
+            "[node_exporter]\ntextfile_directory = \"textfile\"\n\
+             textfile_producers = [\"smart-summary\", \"smart-summary\"]\n\
+             collectors = { disable = [\"textfile\"] }\n",
+        )
+        .unwrap_err();
+        assert!(error.contains("textfile_directory must be an absolute path"));
+        assert!(error.contains("need the textfile collector"));
+        assert!(error.contains("lists \"smart-summary\" more than once"));
+    }
+
+    #[test]
+    fn test_windows_exporter_config_settings() {
+        let config = InstallerConfig::parse(
+            "[windows_exporter]\ntextfile_directory = 'D:\\metrics'\n\
+             where_clauses = { service = \"StartMode='Auto'\" }\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.windows_exporter.where_clauses,
+            Some([("service".to_string(), "StartMode='Auto'".to_string())].into())
+        );
+
+        let error = InstallerConfig::parse(
+            "[windows_exporter]\ncollectors = [\"cpu\"]\n\
+             textfile_directory = 'D:\\metrics'\n\
+             where_clauses = { process = \"Name='x'\", service = \"\" }\n",
+        )
+        .unwrap_err();
+        assert!(error.contains("the \"process\" collector takes no WHERE clause"));
+        assert!(error.contains("where_clauses.service is set, but the collector is not enabled"));
+        assert!(error.contains("where_clauses.service must be a non-empty clause"));
+        assert!(error.contains("textfile_directory needs the textfile collector"));
+    }
+
+    #[test]
+    fn test_underscore_section_alias() {
+        let config = InstallerConfig::parse("[process_cpu_agent]\nport = 9256\n").unwrap();
+        assert_eq!(config.process_cpu_agent.port, Some(9256));
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        assert!(InstallerConfig::parse("[node_exporter]\nprot = 9100\n").is_err());
+        assert!(InstallerConfig::parse("[blackbox_exporter]\nport = 9115\n").is_err());
+    }
+
+    #[test]
+    fn test_validation_reports_every_problem() {
+        let error = InstallerConfig::parse(
+            r#"
+[node_exporter]
+version = "latest"
+port = 0
+install_path = "relative/path"
+
+[windows_exporter]
+collectors = ["cpu", "cpu", "Bad Name"]
+
+[process-cpu-agent]
+url = "ftp://example.com/agent"
+"#,
+        )
+        .unwrap_err();
+
+        assert!(error.contains("node_exporter.version"));
+        assert!(error.contains("node_exporter.port"));
+        assert!(error.contains("node_exporter.install_path"));
+        assert!(error.contains("more than once"));
+        assert!(error.contains("invalid collector name"));
+        assert!(error.contains("process-cpu-agent.url must be an http(s) URL"));
+        assert!(error.contains("requires process-cpu-agent.sha256"));
+
+        // Only warned about, since the agent cannot apply it
+        let config =
+            InstallerConfig::parse("[process-cpu-agent]\nlisten_address = \"loopback\"\n").unwrap();
+        assert_eq!(
+            config.process_cpu_agent.listen_address,
+            Some(ListenAddress::Loopback)
+        );
+    }
+
+    #[test]
+    fn test_port_clash_with_agent() {
+        let error = InstallerConfig::parse(
+            "[node_exporter]\nport = 9100\n[process-cpu-agent]\nport = 9100\n",
+        )
+        .unwrap_err();
+        assert!(error.contains("node_exporter.port and process-cpu-agent.port"));
+
+        // The agent keeps its default port when only the exporter moves onto it
+        let error = InstallerConfig::parse("[windows_exporter]\nport = 31416\n").unwrap_err();
+        assert_eq!(
+            error,
+            "windows_exporter.port and the default process-cpu-agent.port are both 31416"
+        );
+        let error = InstallerConfig::parse("[process-cpu-agent]\nport = 31415\n").unwrap_err();
+        assert!(error.contains("the default node_exporter.port and process-cpu-agent.port"));
+        assert!(InstallerConfig::parse("[node_exporter]\nport = 31416\n").is_err());
+    }
+
+    #[test]
+    fn test_is_absolute_path() {
+        assert!(is_absolute_path("/opt/prometheus"));
+        assert!(is_absolute_path("C:\\Program Files\\prometheus"));
+        assert!(is_absolute_path("\\\\server\\share"));
+        assert!(!is_absolute_path("opt/prometheus"));
+        assert!(!is_absolute_path("C:prometheus"));
+    }
+
+    #[test]
+    fn test_load_reports_path() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("agents.toml");
+        downloader::write_file(path.to_str().unwrap(), b"[node_exporter]\nport = \"x\"\n").unwrap();
+
+        let error = InstallerConfig::load(path.to_str().unwrap()).unwrap_err();
+        assert!(error.to_string().contains("agents.toml"));
+
+        let missing = temp_dir.path().join("missing.toml");
+        assert!(InstallerConfig::load(missing.to_str().unwrap()).is_err());
+    }
+}
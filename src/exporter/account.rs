@@ -0,0 +1,345 @@
+use crate::exporter::downloader;
+use crate::exporter::recorder::{self, progress};
+use crate::os_detector;
+use std::collections::HashSet;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::process::Command;
+
+/// User and group the generated systemd units run as
+pub const SERVICE_ACCOUNT: &str = "prometheus";
+
+/// IDs reserved for system accounts; new ones are taken from the top down like `useradd --system`
+const SYSTEM_ID_RANGE: RangeInclusive<u32> = 100..=999;
+
+const ETC_DIR: &str = "/etc";
+const ACCOUNT_COMMENT: &str = "Prometheus exporters";
+const ACCOUNT_HOME: &str = "/nonexistent";
+
+/// Create a locked system group and user called `name` if they are missing.
+/// Uses `groupadd`/`useradd` when available and edits the account databases
+/// directly on minimal images without them.
+/// Returns `true` when anything was created.
+pub fn ensure_system_account(name: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    if !cfg!(target_os = "linux") {
+        return Err(
+            format!("Creating the {name} service account is only supported on Linux").into(),
+        );
+    }
+
+    let group_missing = lookup_id("group", name).is_none();
+    let user_missing = lookup_id("passwd", name).is_none();
+    if !group_missing && !user_missing {
+        return Ok(false);
+    }
+
+    let shell = nologin_shell();
+    let has_tools = os_detector::find_program("groupadd").is_some()
+        && os_detector::find_program("useradd").is_some();
+    if has_tools {
+        if group_missing {
+            run("groupadd", &["--system", name])?;
+        }
+        if user_missing {
+            run(
+                "useradd",
+                &[
+                    "--system",
+                    "--gid",
+                    name,
+                    "--no-create-home",
+                    "--home-dir",
+                    ACCOUNT_HOME,
+                    "--shell",
+                    shell,
+                    "--comment",
+                    ACCOUNT_COMMENT,
+                    name,
+                ],
+            )?;
+        }
+    } else {
+        append_account_entries(ETC_DIR, name, shell)?;
+    }
+
+    progress!("Created system account: {name}");
+    Ok(true)
+}
+
+/// Give `path` to root and group `group` with mode 750, so the service
+/// can read its files but not replace them.
+/// Returns `true` when ownership or permissions were changed.
+#[cfg(unix)]
+pub fn restrict_to_group(path: &str, group: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let gid = lookup_id("group", group);
+    let metadata = fs::metadata(path).ok();
+    let owned = metadata
+        .as_ref()
+        .is_some_and(|m| m.uid() == 0 && Some(m.gid()) == gid);
+    let restricted = metadata.as_ref().is_some_and(|m| m.mode() & 0o777 == 0o750);
+
+    if !owned {
+        run("chown", &["-R", &format!("root:{group}"), path])?;
+    }
+    if !restricted {
+        downloader::set_permissions(path, 0o750)?;
+    }
+    Ok(!owned || !restricted)
+}
+
+/// Give the file `path` to root and group `group` with mode 640, so the
+/// service can read secrets such as a TLS key that nobody else may.
+/// Returns `true` when ownership or permissions were changed.
+#[cfg(unix)]
+pub fn share_with_group(path: &str, group: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let gid = lookup_id("group", group);
+    let metadata = fs::metadata(path).ok();
+    let owned = metadata
+        .as_ref()
+        .is_some_and(|m| m.uid() == 0 && Some(m.gid()) == gid);
+    let shared = metadata.as_ref().is_some_and(|m| m.mode() & 0o777 == 0o640);
+
+    if !owned {
+        run("chown", &[&format!("root:{group}"), path])?;
+    }
+    if !shared {
+        downloader::set_permissions(path, 0o640)?;
+    }
+    Ok(!owned || !shared)
+}
+
+/// SIDs of `NT AUTHORITY\SYSTEM` and `BUILTIN\Administrators`
+#[cfg(any(windows, test))]
+const WINDOWS_ADMIN_SIDS: [&str; 2] = ["S-1-5-18", "S-1-5-32-544"];
+
+/// Replace the inherited ACL of `path` with full control for SYSTEM and
+/// Administrators and read access for the account `reader_sid`, if any, so
+/// other users can neither read secrets in it nor plant files such as DLLs.
+#[cfg(windows)]
+pub fn restrict_windows_acl(
+    path: &str,
+    reader_sid: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let args = windows_acl_args(path, Path::new(path).is_dir(), reader_sid);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    run("icacls", &args)
+}
+
+/// `icacls` arguments for `restrict_windows_acl`; directories pass the
+/// grants on to everything created in them
+#[cfg(any(windows, test))]
+fn windows_acl_args(path: &str, is_dir: bool, reader_sid: Option<&str>) -> Vec<String> {
+    let inherit = if is_dir { "(OI)(CI)" } else { "" };
+    let mut args = vec![
+        path.to_string(),
+        "/inheritance:r".to_string(),
+        "/grant:r".to_string(),
+    ];
+    args.extend(
+        WINDOWS_ADMIN_SIDS
+            .iter()
+            .map(|sid| format!("*{sid}:{inherit}F")),
+    );
+    args.extend(reader_sid.map(|sid| format!("*{sid}:{inherit}RX")));
+    args
+}
+
+/// Numeric ID of a user (`passwd`) or group (`group`) entry.
+/// Falls back to the files in `/etc` when `getent` is not available.
+pub fn lookup_id(database: &str, name: &str) -> Option<u32> {
+    let content = match Command::new("getent").args([database, name]).output() {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).into_owned(),
+        Err(_) => fs::read_to_string(format!("{ETC_DIR}/{database}")).ok()?,
+    };
+    find_id(&content, name)
+}
+
+/// Append the group and user entries for `name` to the account databases
+/// in `etc_dir`, locking the password in the shadow files when present.
+fn append_account_entries(
+    etc_dir: &str,
+    name: &str,
+    shell: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let passwd_path = format!("{etc_dir}/passwd");
+    let group_path = format!("{etc_dir}/group");
+    let passwd = fs::read_to_string(&passwd_path)?;
+    let group = fs::read_to_string(&group_path)?;
+
+    let free_id =
+        free_system_id(&passwd, &group).ok_or("No free system user ID between 100 and 999")?;
+    let gid = match find_id(&group, name) {
+        Some(gid) => gid,
+        None => {
+            append_line(&group_path, &group, &format!("{name}:x:{free_id}:"))?;
+            append_shadow_line(&format!("{etc_dir}/gshadow"), &format!("{name}:!::"))?;
+            free_id
+        }
+    };
+    if find_id(&passwd, name).is_none() {
+        let entry = format!("{name}:x:{free_id}:{gid}:{ACCOUNT_COMMENT}:{ACCOUNT_HOME}:{shell}");
+        append_line(&passwd_path, &passwd, &entry)?;
+        append_shadow_line(&format!("{etc_dir}/shadow"), &format!("{name}:!:::::::"))?;
+    }
+    Ok(())
+}
+
+fn append_line(path: &str, existing: &str, line: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let separator = if existing.is_empty() || existing.ends_with('\n') {
+        ""
+    } else {
+        "\n"
+    };
+    downloader::append_file(path, format!("{separator}{line}\n").as_bytes())
+}
+
+/// Shadow databases are optional on minimal images
+fn append_shadow_line(path: &str, line: &str) -> Result<(), Box<dyn std::error::Error>> {
+    match fs::read_to_string(path) {
+        Ok(existing) => append_line(path, &existing, line),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Third field of the `name` entry in a passwd or group database
+fn find_id(content: &str, name: &str) -> Option<u32> {
+    content.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        if fields.next()? != name {
+            return None;
+        }
+        fields.nth(1)?.trim().parse().ok()
+    })
+}
+
+/// Highest system ID not used as a UID or GID yet
+fn free_system_id(passwd: &str, group: &str) -> Option<u32> {
+    let used: HashSet<u32> = passwd
+        .lines()
+        .chain(group.lines())
+        .filter_map(|line| line.split(':').nth(2)?.trim().parse().ok())
+        .collect();
+    SYSTEM_ID_RANGE.rev().find(|id| !used.contains(id))
+}
+
+fn nologin_shell() -> &'static str {
+    ["/usr/sbin/nologin", "/sbin/nologin"]
+        .into_iter()
+        .find(|shell| Path::new(shell).exists())
+        .unwrap_or("/bin/false")
+}
+
+fn run(program: &str, args: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+    let output = recorder::run_command(program, args)?;
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("{program} failed: {}", error.trim()).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const PASSWD: &str = "root:x:0:0:root:/root:/bin/bash\n\
+                          daemon:x:1:1:daemon:/usr/sbin:/usr/sbin/nologin\n\
+                          systemd-network:x:999:999::/:/usr/sbin/nologin\n";
+    const GROUP: &str = "root:x:0:\ndaemon:x:1:\nsystemd-network:x:999:\nprometheus:x:998:";
+
+    #[test]
+    fn test_find_id() {
+        assert_eq!(find_id(PASSWD, "root"), Some(0));
+        assert_eq!(find_id(PASSWD, "systemd-network"), Some(999));
+        assert_eq!(find_id(GROUP, "prometheus"), Some(998));
+        assert_eq!(find_id(PASSWD, "prometheus"), None);
+        assert_eq!(find_id(PASSWD, "roo"), None);
+    }
+
+    #[test]
+    fn test_free_system_id() {
+        assert_eq!(free_system_id(PASSWD, GROUP), Some(997));
+        assert_eq!(free_system_id("", ""), Some(999));
+    }
+
+    #[test]
+    fn test_append_account_entries() {
+        let temp_dir = TempDir::new().unwrap();
+        let etc = temp_dir.path();
+        fs::write(etc.join("passwd"), PASSWD).unwrap();
+        fs::write(etc.join("group"), "root:x:0:\nsystemd-network:x:999:").unwrap();
+        fs::write(etc.join("shadow"), "root:*:19000:0:99999:7:::\n").unwrap();
+
+        append_account_entries(etc.to_str().unwrap(), "prometheus", "/bin/false").unwrap();
+
+        let passwd = fs::read_to_string(etc.join("passwd")).unwrap();
+        assert!(
+            passwd.ends_with("prometheus:x:998:998:Prometheus exporters:/nonexistent:/bin/false\n")
+        );
+        let group = fs::read_to_string(etc.join("group")).unwrap();
+        assert_eq!(
+            group,
+            "root:x:0:\nsystemd-network:x:999:\nprometheus:x:998:\n"
+        );
+        let shadow = fs::read_to_string(etc.join("shadow")).unwrap();
+        assert!(shadow.ends_with("prometheus:!:::::::\n"));
+        assert!(!etc.join("gshadow").exists());
+
+        // Running again adds nothing
+        append_account_entries(etc.to_str().unwrap(), "prometheus", "/bin/false").unwrap();
+        assert_eq!(fs::read_to_string(etc.join("passwd")).unwrap(), passwd);
+    }
+
+    #[test]
+    fn test_append_user_to_existing_group() {
+        let temp_dir = TempDir::new().unwrap();
+        let etc = temp_dir.path();
+        fs::write(etc.join("passwd"), PASSWD).unwrap();
+        fs::write(etc.join("group"), GROUP).unwrap();
+
+        append_account_entries(etc.to_str().unwrap(), "prometheus", "/bin/false").unwrap();
+
+        let passwd = fs::read_to_string(etc.join("passwd")).unwrap();
+        assert!(passwd.contains("prometheus:x:997:998:"));
+        assert_eq!(fs::read_to_string(etc.join("group")).unwrap(), GROUP);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_ensure_system_account_dry_run() {
+        let name = "prometheus-agents-setup-nonexistent";
+        let (result, actions) = recorder::dry_run(|| ensure_system_account(name));
+        assert!(result.unwrap());
+        assert!(!actions.is_empty());
+        assert_eq!(lookup_id("passwd", name), None);
+    }
+
+    #[test]
+    fn test_windows_acl_args() {
+        assert_eq!(
+            windows_acl_args("C:\\agent", true, Some("S-1-5-19")),
+            vec![
+                "C:\\agent",
+                "/inheritance:r",
+                "/grant:r",
+                "*S-1-5-18:(OI)(CI)F",
+                "*S-1-5-32-544:(OI)(CI)F",
+                "*S-1-5-19:(OI)(CI)RX",
+            ]
+        );
+        assert_eq!(
+            windows_acl_args("C:\\agent\\key.pem", false, Some("S-1-5-20"))[3..],
+            ["*S-1-5-18:F", "*S-1-5-32-544:F", "*S-1-5-20:RX"]
+        );
+        assert_eq!(
+            windows_acl_args("C:\\prometheus\\web-config.yml", false, None)[3..],
+            ["*S-1-5-18:F", "*S-1-5-32-544:F"]
+        );
+    }
+}
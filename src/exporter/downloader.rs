@@ -1,31 +1,31 @@
+use crate::exporter::recorder::{self, Action, progress};
 use reqwest::blocking::Client;
+use sha2::{Digest, Sha256};
 use std::fs::{self, File};
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-/// Download a file from URL to the specified path
-pub fn download_file(url: &str, dest_path: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    println!("Downloading from: {url}");
+/// Download a file from URL to the specified path.
+/// The content is verified against `expected_sha256` before anything is written.
+pub fn download_file(
+    url: &str,
+    dest_path: &str,
+    expected_sha256: &str,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    progress!("Downloading from: {url}");
 
     // Create parent directories if they don't exist
-    if let Some(parent) = Path::new(dest_path).parent() {
-        fs::create_dir_all(parent)?;
+    if let Some(parent) = get_parent_directory(dest_path) {
+        ensure_directory_exists(&parent)?;
     }
 
     // Download the file
-    let client = Client::new();
-    let response = client.get(url).send()?;
-
-    if !response.status().is_success() {
-        return Err(format!("Failed to download: HTTP {}", response.status()).into());
-    }
-
-    let bytes = response.bytes()?.to_vec();
+    let bytes = download_content_verified(url, expected_sha256)?;
 
     // Write to file
     write_file(dest_path, &bytes)?;
 
-    println!("Downloaded to: {dest_path}");
+    progress!("Downloaded to: {dest_path}");
 
     // Set executable permissions on Unix
     #[cfg(unix)]
@@ -36,24 +36,129 @@ pub fn download_file(url: &str, dest_path: &str) -> Result<Vec<u8>, Box<dyn std:
 
 /// Write bytes to a file
 pub fn write_file(path: &str, content: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    if recorder::record(Action::WriteFile {
+        path: path.to_string(),
+    }) {
+        return Ok(());
+    }
     let mut file = File::create(path)?;
     file.write_all(content)?;
     Ok(())
 }
 
+/// Write a file only when its content differs.
+/// The new content goes to a temporary sibling that is renamed into place,
+/// so a binary that is currently running is never truncated.
+/// Returns `true` when the file was (or, in dry-run mode, would be) written.
+pub fn write_file_if_changed(
+    path: &str,
+    content: &[u8],
+) -> Result<bool, Box<dyn std::error::Error>> {
+    if sha256_file(path).is_ok_and(|hash| hash == sha256_hex(content)) {
+        return Ok(false);
+    }
+    if recorder::record(Action::WriteFile {
+        path: path.to_string(),
+    }) {
+        return Ok(true);
+    }
+    let temp_path = format!("{path}.tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(content)?;
+    drop(file);
+    fs::rename(&temp_path, path)?;
+    Ok(true)
+}
+
+/// What happened when writing a configuration file users may edit
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigWrite {
+    /// The file already has the shipped content
+    Unchanged,
+    Written,
+    /// The file was edited locally; the shipped content went to `new_path`
+    Preserved {
+        new_path: String,
+    },
+}
+
+/// Write a configuration file without discarding local edits.
+/// `shipped_sha256` is the digest of the content the installer wrote last
+/// time; a file still matching it has not been edited and is replaced.
+/// An edited file is kept and the new default is written next to it as
+/// `<path>.new`.
+pub fn write_config_file(
+    path: &str,
+    content: &[u8],
+    shipped_sha256: Option<&str>,
+) -> Result<ConfigWrite, Box<dyn std::error::Error>> {
+    let new_path = format!("{path}.new");
+    let current = sha256_file(path).ok();
+
+    let outcome = match current.as_deref() {
+        None => {
+            write_file(path, content)?;
+            ConfigWrite::Written
+        }
+        Some(hash) if hash == sha256_hex(content) => ConfigWrite::Unchanged,
+        Some(hash) if Some(hash) == shipped_sha256 => {
+            write_file_if_changed(path, content)?;
+            ConfigWrite::Written
+        }
+        Some(_) => {
+            write_file_if_changed(&new_path, content)?;
+            return Ok(ConfigWrite::Preserved { new_path });
+        }
+    };
+
+    // A pending default from an earlier run is obsolete now
+    remove_path(&new_path)?;
+    Ok(outcome)
+}
+
 /// Set executable permissions on Unix systems
 #[cfg(unix)]
 pub fn set_executable_permissions(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    set_permissions(path, 0o755)
+}
+
+/// Set the Unix permission bits of a file or directory
+#[cfg(unix)]
+pub fn set_permissions(path: &str, mode: u32) -> Result<(), Box<dyn std::error::Error>> {
     use std::os::unix::fs::PermissionsExt;
+    if recorder::record(Action::SetPermissions {
+        path: path.to_string(),
+        mode: format!("{mode:o}"),
+    }) {
+        return Ok(());
+    }
     let metadata = fs::metadata(path)?;
     let mut permissions = metadata.permissions();
-    permissions.set_mode(0o755);
+    permissions.set_mode(mode);
     fs::set_permissions(path, permissions)?;
     Ok(())
 }
 
+/// Append bytes to a file in place, keeping its ownership and permissions
+pub fn append_file(path: &str, content: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    if recorder::record(Action::WriteFile {
+        path: path.to_string(),
+    }) {
+        return Ok(());
+    }
+    let mut file = fs::OpenOptions::new().append(true).open(path)?;
+    file.write_all(content)?;
+    Ok(())
+}
+
 /// Download content from URL
+/// In dry-run mode the download is recorded and no content is returned.
 pub fn download_content(url: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    if recorder::record(Action::Download {
+        url: url.to_string(),
+    }) {
+        return Ok(Vec::new());
+    }
     let client = Client::new();
     let response = client.get(url).send()?;
 
@@ -64,31 +169,221 @@ pub fn download_content(url: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>
     Ok(response.bytes()?.to_vec())
 }
 
-/// Extract tar.gz archive to specified path
+/// Download content from URL and verify its SHA-256 digest
+pub fn download_content_verified(
+    url: &str,
+    expected_sha256: &str,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let bytes = download_content(url)?;
+    verify_sha256(&bytes, expected_sha256, url)?;
+    Ok(bytes)
+}
+
+/// Fetch the expected digest of `file_name` from an upstream `sha256sums.txt`
+pub fn fetch_sha256_from_sums(
+    sums_url: &str,
+    file_name: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let content = download_content(sums_url)?;
+    if recorder::is_dry_run() {
+        // Nothing was fetched; the digest is only needed once content exists
+        return Ok(String::new());
+    }
+    parse_sha256sums(&String::from_utf8_lossy(&content), file_name)
+        .ok_or_else(|| format!("No checksum for {file_name} in {sums_url}").into())
+}
+
+/// Find the digest of `file_name` in `sha256sum` formatted output
+pub fn parse_sha256sums(content: &str, file_name: &str) -> Option<String> {
+    content.lines().find_map(|line| {
+        let mut parts = line.split_whitespace();
+        let digest = parts.next()?;
+        // `sha256sum -b` prefixes file names with '*'
+        let name = parts.next()?.trim_start_matches('*');
+        (name == file_name).then(|| digest.to_ascii_lowercase())
+    })
+}
+
+/// Validate and normalize a hex encoded SHA-256 digest
+pub fn normalize_sha256(value: &str) -> Result<String, String> {
+    let value = value.trim().to_ascii_lowercase();
+    if value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(value)
+    } else {
+        Err(format!(
+            "'{value}' is not a SHA-256 digest (expected 64 hex characters)"
+        ))
+    }
+}
+
+/// Fail unless `bytes` hash to `expected_sha256`
+pub fn verify_sha256(
+    bytes: &[u8],
+    expected_sha256: &str,
+    artifact: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if recorder::is_dry_run() {
+        return Ok(());
+    }
+    let expected = normalize_sha256(expected_sha256)?;
+    let actual = sha256_hex(bytes);
+    if actual != expected {
+        return Err(
+            format!("Checksum mismatch for {artifact}: expected {expected}, got {actual}").into(),
+        );
+    }
+    progress!("Verified SHA-256 of {artifact}");
+    Ok(())
+}
+
+/// Archive entry rejected because extracting it would escape the destination
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExtractError {
+    /// Entry path is absolute or contains `..` components
+    UnsafePath { entry: String },
+    /// Symlink or hard link pointing outside the destination
+    UnsafeLink { entry: String, target: String },
+}
+
+impl std::fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtractError::UnsafePath { entry } => {
+                write!(
+                    f,
+                    "Archive entry '{entry}' escapes the extraction directory"
+                )
+            }
+            ExtractError::UnsafeLink { entry, target } => write!(
+                f,
+                "Archive link '{entry}' -> '{target}' escapes the extraction directory"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ExtractError {}
+
+/// Reject entry paths that are absolute or climb out with `..`
+fn check_entry_path(entry: &str, path: &Path) -> Result<(), ExtractError> {
+    let unsafe_path = || ExtractError::UnsafePath {
+        entry: entry.to_string(),
+    };
+    if path.as_os_str().is_empty() {
+        return Err(unsafe_path());
+    }
+    for component in path.components() {
+        match component {
+            std::path::Component::Normal(_) | std::path::Component::CurDir => {}
+            _ => return Err(unsafe_path()),
+        }
+    }
+    Ok(())
+}
+
+/// Reject link targets that resolve outside the destination.
+/// Symlink targets are relative to the link's directory, hard link targets
+/// to the archive root.
+fn check_link_target(
+    entry: &str,
+    entry_path: &Path,
+    target: &Path,
+    relative_to_entry: bool,
+) -> Result<(), ExtractError> {
+    let unsafe_link = || ExtractError::UnsafeLink {
+        entry: entry.to_string(),
+        target: target.display().to_string(),
+    };
+
+    let mut depth: usize = if relative_to_entry {
+        entry_path
+            .parent()
+            .map(|p| {
+                p.components()
+                    .filter(|c| matches!(c, std::path::Component::Normal(_)))
+                    .count()
+            })
+            .unwrap_or(0)
+    } else {
+        0
+    };
+
+    for component in target.components() {
+        match component {
+            std::path::Component::Normal(_) => depth += 1,
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                depth = depth.checked_sub(1).ok_or_else(unsafe_link)?;
+            }
+            _ => return Err(unsafe_link()),
+        }
+    }
+    Ok(())
+}
+
+/// Extract tar.gz archive to specified path.
+/// Entries with absolute paths, `..` components or links escaping
+/// `extract_path` are rejected with an [`ExtractError`].
 pub fn extract_tar_gz(
     archive_bytes: &[u8],
     extract_path: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
     use flate2::read::GzDecoder;
-    use tar::Archive;
+    use tar::{Archive, EntryType};
 
+    if recorder::record(Action::Extract {
+        destination: extract_path.to_string(),
+    }) {
+        return Ok(());
+    }
     fs::create_dir_all(extract_path)?;
 
     let tar_gz = GzDecoder::new(archive_bytes);
     let mut archive = Archive::new(tar_gz);
-    archive.unpack(extract_path)?;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let name = path.display().to_string();
+        check_entry_path(&name, &path)?;
+
+        let entry_type = entry.header().entry_type();
+        if matches!(entry_type, EntryType::Symlink | EntryType::Link) {
+            let target = entry
+                .link_name()?
+                .ok_or_else(|| ExtractError::UnsafeLink {
+                    entry: name.clone(),
+                    target: String::new(),
+                })?
+                .into_owned();
+            check_link_target(&name, &path, &target, entry_type == EntryType::Symlink)?;
+        }
+
+        entry.unpack_in(extract_path)?;
+    }
 
     Ok(())
 }
 
-/// Extract zip archive to specified path
+/// Extract zip archive to specified path.
+/// Entries with absolute paths, `..` components or symlinks escaping
+/// `extract_path` are rejected with an [`ExtractError`].
+#[allow(dead_code)]
 pub fn extract_zip(
     archive_bytes: &[u8],
     extract_path: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    use std::io::Cursor;
+    use std::io::{Cursor, Read};
     use zip::ZipArchive;
 
+    const S_IFMT: u32 = 0o170000;
+    const S_IFLNK: u32 = 0o120000;
+
+    if recorder::record(Action::Extract {
+        destination: extract_path.to_string(),
+    }) {
+        return Ok(());
+    }
     fs::create_dir_all(extract_path)?;
 
     // Create a cursor from bytes for zip archive
@@ -97,23 +392,41 @@ pub fn extract_zip(
 
     for i in 0..archive.len() {
         let mut file = archive.by_index(i)?;
-        let outpath = Path::new(extract_path).join(file.name());
+        let name = file.name().to_string();
+        // Zip entries created on Windows may use backslashes as separators
+        let relative = PathBuf::from(name.replace('\\', "/"));
+        if name.contains(':') {
+            return Err(ExtractError::UnsafePath { entry: name }.into());
+        }
+        check_entry_path(&name, &relative)?;
+        let outpath = Path::new(extract_path).join(&relative);
 
-        if file.name().ends_with('/') {
+        if file.is_dir() {
             fs::create_dir_all(&outpath)?;
         } else {
+            let mut content = Vec::new();
+            file.read_to_end(&mut content)?;
+
+            if file
+                .unix_mode()
+                .is_some_and(|mode| mode & S_IFMT == S_IFLNK)
+            {
+                let target = PathBuf::from(String::from_utf8_lossy(&content).into_owned());
+                check_link_target(&name, &relative, &target, true)?;
+            }
+
             if let Some(p) = outpath.parent()
                 && !p.exists()
             {
                 fs::create_dir_all(p)?;
             }
             let mut outfile = File::create(&outpath)?;
-            std::io::copy(&mut file, &mut outfile)?;
+            outfile.write_all(&content)?;
 
             // Set executable permissions for binaries on Unix
             #[cfg(unix)]
             {
-                if file.name().contains("exporter") || file.name().contains("agent") {
+                if name.contains("exporter") || name.contains("agent") {
                     set_executable_permissions(outpath.to_str().unwrap())?;
                 }
             }
@@ -123,40 +436,93 @@ pub fn extract_zip(
     Ok(())
 }
 
-/// Download and extract a tar.gz file
+/// Download, verify and extract a tar.gz file
 pub fn download_and_extract_tar_gz(
     url: &str,
     extract_path: &str,
+    expected_sha256: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Downloading tar.gz from: {url}");
+    progress!("Downloading tar.gz from: {url}");
 
-    let bytes = download_content(url)?;
+    let bytes = download_content_verified(url, expected_sha256)?;
     extract_tar_gz(&bytes, extract_path)?;
 
-    println!("Extracted to: {extract_path}");
+    progress!("Extracted to: {extract_path}");
     Ok(())
 }
 
-/// Download and extract a zip file
+/// Download, verify and extract a zip file
+#[allow(dead_code)]
 pub fn download_and_extract_zip(
     url: &str,
     extract_path: &str,
+    expected_sha256: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Downloading zip from: {url}");
+    progress!("Downloading zip from: {url}");
 
-    let bytes = download_content(url)?;
+    let bytes = download_content_verified(url, expected_sha256)?;
     extract_zip(&bytes, extract_path)?;
 
-    println!("Extracted to: {extract_path}");
+    progress!("Extracted to: {extract_path}");
     Ok(())
 }
 
 /// Create directory if it doesn't exist
 pub fn ensure_directory_exists(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if !Path::new(path).is_dir()
+        && recorder::record(Action::CreateDirectory {
+            path: path.to_string(),
+        })
+    {
+        return Ok(());
+    }
     fs::create_dir_all(path)?;
     Ok(())
 }
 
+/// Compute the lowercase hex SHA-256 digest of bytes
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Compute the lowercase hex SHA-256 digest of a file
+pub fn sha256_file(path: &str) -> Result<String, Box<dyn std::error::Error>> {
+    Ok(sha256_hex(&fs::read(path)?))
+}
+
+/// Remove a file or directory tree.
+/// Returns `false` when the path did not exist.
+pub fn remove_path(path: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    if fs::symlink_metadata(path).is_ok()
+        && recorder::record(Action::Remove {
+            path: path.to_string(),
+        })
+    {
+        return Ok(true);
+    }
+    let path = Path::new(path);
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path)?,
+        Ok(_) => fs::remove_file(path)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    }
+    Ok(true)
+}
+
+/// Remove a directory only if it exists and is empty.
+/// Returns `true` when the directory was removed. Never removes anything
+/// in dry-run mode, since planned removals have not emptied it.
+pub fn remove_dir_if_empty(path: &str) -> bool {
+    if recorder::is_dry_run() {
+        return false;
+    }
+    let is_empty = fs::read_dir(path)
+        .map(|mut entries| entries.next().is_none())
+        .unwrap_or(false);
+    is_empty && fs::remove_dir(path).is_ok()
+}
+
 /// Check if path exists
 pub fn path_exists(path: &str) -> bool {
     Path::new(path).exists()
@@ -175,6 +541,8 @@ mod tests {
     use super::*;
     use tempfile::TempDir;
 
+    const ZERO_SHA256: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
     #[test]
     fn test_write_file() {
         let temp_dir = TempDir::new().unwrap();
@@ -188,6 +556,65 @@ mod tests {
         assert_eq!(read_content, content);
     }
 
+    #[test]
+    fn test_write_file_if_changed() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("binary");
+        let path = path.to_str().unwrap();
+
+        assert!(write_file_if_changed(path, b"v1").unwrap());
+        assert!(!write_file_if_changed(path, b"v1").unwrap());
+        assert!(write_file_if_changed(path, b"v2").unwrap());
+        assert_eq!(fs::read(path).unwrap(), b"v2");
+        assert!(!path_exists(&format!("{path}.tmp")));
+    }
+
+    #[test]
+    fn test_write_config_file_preserves_local_edits() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        let path = path.to_str().unwrap();
+        let new_path = format!("{path}.new");
+        let v1_sha = sha256_hex(b"port = 1");
+
+        // First install writes the default
+        assert_eq!(
+            write_config_file(path, b"port = 1", None).unwrap(),
+            ConfigWrite::Written
+        );
+        assert_eq!(
+            write_config_file(path, b"port = 1", Some(&v1_sha)).unwrap(),
+            ConfigWrite::Unchanged
+        );
+
+        // An unedited file follows a changed default
+        assert_eq!(
+            write_config_file(path, b"port = 2", Some(&v1_sha)).unwrap(),
+            ConfigWrite::Written
+        );
+        assert_eq!(fs::read(path).unwrap(), b"port = 2");
+
+        // A locally edited file is kept and the default goes to .new
+        write_file(path, b"port = 9").unwrap();
+        let v2_sha = sha256_hex(b"port = 2");
+        assert_eq!(
+            write_config_file(path, b"port = 3", Some(&v2_sha)).unwrap(),
+            ConfigWrite::Preserved {
+                new_path: new_path.clone()
+            }
+        );
+        assert_eq!(fs::read(path).unwrap(), b"port = 9");
+        assert_eq!(fs::read(&new_path).unwrap(), b"port = 3");
+
+        // Once the edit matches the default, the stale .new is removed
+        write_file(path, b"port = 3").unwrap();
+        assert_eq!(
+            write_config_file(path, b"port = 3", Some(&v2_sha)).unwrap(),
+            ConfigWrite::Unchanged
+        );
+        assert!(!path_exists(&new_path));
+    }
+
     #[test]
     fn test_ensure_directory_exists() {
         let temp_dir = TempDir::new().unwrap();
@@ -233,9 +660,11 @@ mod tests {
         let result = download_file(
             "http://192.0.2.1:9999/nonexistent/file.txt",
             dest_path.to_str().unwrap(),
+            ZERO_SHA256,
         );
 
         assert!(result.is_err());
+        assert!(!dest_path.exists());
     }
 
     #[test]
@@ -258,6 +687,184 @@ mod tests {
         assert!(result.is_err());
     }
 
+    /// Build a tar.gz with a single raw entry, bypassing the path checks of
+    /// `tar::Builder` so that malicious names can be crafted
+    fn crafted_tar_gz(name: &str, entry_type: tar::EntryType, link: Option<&str>) -> Vec<u8> {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+
+        let data: &[u8] = if entry_type == tar::EntryType::Regular {
+            b"payload"
+        } else {
+            b""
+        };
+        let mut header = tar::Header::new_gnu();
+        let gnu = header.as_gnu_mut().unwrap();
+        gnu.name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_entry_type(entry_type);
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        if let Some(link) = link {
+            header.set_link_name(link).unwrap();
+        }
+        header.set_cksum();
+
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        builder.append(&header, data).unwrap();
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn crafted_zip(entries: &[(&str, Option<&str>)]) -> Vec<u8> {
+        use zip::write::{FileOptions, ZipWriter};
+
+        let mut writer = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, symlink_target) in entries {
+            match symlink_target {
+                Some(target) => writer
+                    .add_symlink(*name, *target, FileOptions::default())
+                    .unwrap(),
+                None => {
+                    writer.start_file(*name, FileOptions::default()).unwrap();
+                    writer.write_all(b"payload").unwrap();
+                }
+            }
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn extract_error(result: Result<(), Box<dyn std::error::Error>>) -> ExtractError {
+        result
+            .unwrap_err()
+            .downcast_ref::<ExtractError>()
+            .expect("expected an ExtractError")
+            .clone()
+    }
+
+    #[test]
+    fn test_extract_tar_gz_valid_archive() {
+        let temp_dir = TempDir::new().unwrap();
+        let extract_path = temp_dir.path().join("extract");
+
+        let archive = crafted_tar_gz("dir/file.txt", tar::EntryType::Regular, None);
+        extract_tar_gz(&archive, extract_path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            fs::read(extract_path.join("dir").join("file.txt")).unwrap(),
+            b"payload"
+        );
+
+        let archive = crafted_tar_gz("dir/link", tar::EntryType::Symlink, Some("../other"));
+        assert!(extract_tar_gz(&archive, extract_path.to_str().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_extract_tar_gz_rejects_parent_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        let extract_path = temp_dir.path().join("extract");
+
+        let archive = crafted_tar_gz("../evil.txt", tar::EntryType::Regular, None);
+        let err = extract_error(extract_tar_gz(&archive, extract_path.to_str().unwrap()));
+        assert_eq!(
+            err,
+            ExtractError::UnsafePath {
+                entry: "../evil.txt".to_string()
+            }
+        );
+        assert!(!temp_dir.path().join("evil.txt").exists());
+    }
+
+    #[test]
+    fn test_extract_tar_gz_rejects_absolute_path() {
+        let temp_dir = TempDir::new().unwrap();
+        let extract_path = temp_dir.path().join("extract");
+
+        let archive = crafted_tar_gz("/tmp/evil.txt", tar::EntryType::Regular, None);
+        let err = extract_error(extract_tar_gz(&archive, extract_path.to_str().unwrap()));
+        assert!(matches!(err, ExtractError::UnsafePath { entry } if entry == "/tmp/evil.txt"));
+    }
+
+    #[test]
+    fn test_extract_tar_gz_rejects_escaping_symlink() {
+        let temp_dir = TempDir::new().unwrap();
+        let extract_path = temp_dir.path().join("extract");
+
+        let archive = crafted_tar_gz("dir/link", tar::EntryType::Symlink, Some("../../outside"));
+        let err = extract_error(extract_tar_gz(&archive, extract_path.to_str().unwrap()));
+        assert_eq!(
+            err,
+            ExtractError::UnsafeLink {
+                entry: "dir/link".to_string(),
+                target: "../../outside".to_string()
+            }
+        );
+
+        let archive = crafted_tar_gz("link", tar::EntryType::Symlink, Some("/etc"));
+        let err = extract_error(extract_tar_gz(&archive, extract_path.to_str().unwrap()));
+        assert!(matches!(err, ExtractError::UnsafeLink { .. }));
+        assert!(!extract_path.join("link").exists());
+    }
+
+    #[test]
+    fn test_extract_tar_gz_rejects_escaping_hard_link() {
+        let temp_dir = TempDir::new().unwrap();
+        let extract_path = temp_dir.path().join("extract");
+
+        let archive = crafted_tar_gz("dir/link", tar::EntryType::Link, Some("../secret"));
+        let err = extract_error(extract_tar_gz(&archive, extract_path.to_str().unwrap()));
+        assert!(matches!(err, ExtractError::UnsafeLink { .. }));
+    }
+
+    #[test]
+    fn test_extract_zip_valid_archive() {
+        let temp_dir = TempDir::new().unwrap();
+        let extract_path = temp_dir.path().join("extract");
+
+        let archive = crafted_zip(&[("dir/file.txt", None), ("dir/link", Some("file.txt"))]);
+        extract_zip(&archive, extract_path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            fs::read(extract_path.join("dir").join("file.txt")).unwrap(),
+            b"payload"
+        );
+    }
+
+    #[test]
+    fn test_extract_zip_rejects_traversal() {
+        let temp_dir = TempDir::new().unwrap();
+        let extract_path = temp_dir.path().join("extract");
+
+        for name in [
+            "../evil.txt",
+            "/tmp/evil.txt",
+            "dir\\..\\..\\evil.txt",
+            "C:/evil.txt",
+        ] {
+            let archive = crafted_zip(&[(name, None)]);
+            let err = extract_error(extract_zip(&archive, extract_path.to_str().unwrap()));
+            assert_eq!(
+                err,
+                ExtractError::UnsafePath {
+                    entry: name.to_string()
+                }
+            );
+        }
+        assert!(!temp_dir.path().join("evil.txt").exists());
+    }
+
+    #[test]
+    fn test_extract_zip_rejects_escaping_symlink() {
+        let temp_dir = TempDir::new().unwrap();
+        let extract_path = temp_dir.path().join("extract");
+
+        let archive = crafted_zip(&[("link", Some("../../etc/passwd"))]);
+        let err = extract_error(extract_zip(&archive, extract_path.to_str().unwrap()));
+        assert_eq!(
+            err,
+            ExtractError::UnsafeLink {
+                entry: "link".to_string(),
+                target: "../../etc/passwd".to_string()
+            }
+        );
+    }
+
     #[test]
     fn test_download_and_extract_tar_gz_invalid_url() {
         let temp_dir = TempDir::new().unwrap();
@@ -265,6 +872,7 @@ mod tests {
         let result = download_and_extract_tar_gz(
             "http://192.0.2.1:9999/archive.tar.gz",
             temp_dir.path().to_str().unwrap(),
+            ZERO_SHA256,
         );
 
         assert!(result.is_err());
@@ -277,11 +885,150 @@ mod tests {
         let result = download_and_extract_zip(
             "http://192.0.2.1:9999/archive.zip",
             temp_dir.path().to_str().unwrap(),
+            ZERO_SHA256,
         );
 
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_sha256() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("hello.txt");
+        write_file(file_path.to_str().unwrap(), b"hello").unwrap();
+
+        let expected = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        assert_eq!(sha256_hex(b"hello"), expected);
+        assert_eq!(sha256_file(file_path.to_str().unwrap()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_verify_sha256() {
+        let digest = sha256_hex(b"artifact");
+        assert!(verify_sha256(b"artifact", &digest, "artifact").is_ok());
+        assert!(verify_sha256(b"artifact", &digest.to_uppercase(), "artifact").is_ok());
+
+        let err = verify_sha256(b"tampered", &digest, "artifact").unwrap_err();
+        assert!(err.to_string().contains("Checksum mismatch for artifact"));
+
+        assert!(verify_sha256(b"artifact", "not-a-digest", "artifact").is_err());
+    }
+
+    #[test]
+    fn test_normalize_sha256() {
+        let digest = sha256_hex(b"artifact");
+        assert_eq!(
+            normalize_sha256(&format!("  {}\n", digest.to_uppercase())),
+            Ok(digest)
+        );
+        assert!(normalize_sha256("abc").is_err());
+        assert!(normalize_sha256(&"g".repeat(64)).is_err());
+    }
+
+    #[test]
+    fn test_parse_sha256sums() {
+        let content = "\
+aaaa1111  node_exporter-1.7.0.linux-386.tar.gz
+BBBB2222  node_exporter-1.7.0.linux-amd64.tar.gz
+cccc3333 *windows_exporter-0.25.1-amd64.msi
+";
+        assert_eq!(
+            parse_sha256sums(content, "node_exporter-1.7.0.linux-amd64.tar.gz"),
+            Some("bbbb2222".to_string())
+        );
+        assert_eq!(
+            parse_sha256sums(content, "windows_exporter-0.25.1-amd64.msi"),
+            Some("cccc3333".to_string())
+        );
+        assert_eq!(
+            parse_sha256sums(content, "node_exporter-1.7.0.linux-arm64.tar.gz"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_dry_run_records_instead_of_writing() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path().join("planned");
+        let file_path = dir_path.join("file.txt");
+        let existing = temp_dir.path().join("existing.txt");
+        write_file(existing.to_str().unwrap(), b"keep").unwrap();
+
+        let (result, actions) = recorder::dry_run(|| {
+            ensure_directory_exists(dir_path.to_str().unwrap())?;
+            write_file(file_path.to_str().unwrap(), b"content")?;
+            extract_tar_gz(b"not an archive", dir_path.to_str().unwrap())?;
+            remove_path(existing.to_str().unwrap())
+        });
+
+        assert!(result.unwrap());
+        assert!(!dir_path.exists());
+        assert!(existing.exists());
+        assert_eq!(
+            actions,
+            vec![
+                Action::CreateDirectory {
+                    path: dir_path.to_str().unwrap().to_string()
+                },
+                Action::WriteFile {
+                    path: file_path.to_str().unwrap().to_string()
+                },
+                Action::Extract {
+                    destination: dir_path.to_str().unwrap().to_string()
+                },
+                Action::Remove {
+                    path: existing.to_str().unwrap().to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dry_run_download_is_not_fetched() {
+        let (result, actions) = recorder::dry_run(|| {
+            download_content_verified("http://192.0.2.1:9999/archive.tar.gz", ZERO_SHA256)
+        });
+        assert!(result.unwrap().is_empty());
+        assert_eq!(
+            actions,
+            vec![Action::Download {
+                url: "http://192.0.2.1:9999/archive.tar.gz".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_remove_path() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("file.txt");
+        let dir_path = temp_dir.path().join("dir");
+        write_file(file_path.to_str().unwrap(), b"content").unwrap();
+        ensure_directory_exists(dir_path.join("nested").to_str().unwrap()).unwrap();
+
+        assert!(remove_path(file_path.to_str().unwrap()).unwrap());
+        assert!(remove_path(dir_path.to_str().unwrap()).unwrap());
+        assert!(!file_path.exists());
+        assert!(!dir_path.exists());
+
+        assert!(!remove_path(file_path.to_str().unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_remove_dir_if_empty() {
+        let temp_dir = TempDir::new().unwrap();
+        let empty = temp_dir.path().join("empty");
+        let non_empty = temp_dir.path().join("non_empty");
+        ensure_directory_exists(empty.to_str().unwrap()).unwrap();
+        ensure_directory_exists(non_empty.to_str().unwrap()).unwrap();
+        write_file(non_empty.join("keep").to_str().unwrap(), b"").unwrap();
+
+        assert!(remove_dir_if_empty(empty.to_str().unwrap()));
+        assert!(!empty.exists());
+        assert!(!remove_dir_if_empty(non_empty.to_str().unwrap()));
+        assert!(non_empty.exists());
+        assert!(!remove_dir_if_empty(empty.to_str().unwrap()));
+    }
+
     #[test]
     fn test_create_nested_directories() {
         let temp_dir = TempDir::new().unwrap();
@@ -0,0 +1,348 @@
+use crate::exporter::Component;
+use crate::exporter::web_config::WebConfig;
+use reqwest::StatusCode;
+use reqwest::blocking::Client;
+use std::collections::HashSet;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Delay between scrapes while waiting for an exporter to come up
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Outcome of scraping one component's metrics endpoint
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthCheck {
+    pub component: Component,
+    pub url: String,
+    /// Why the check failed, `None` when it passed
+    pub error: Option<String>,
+}
+
+impl HealthCheck {
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Metric family every healthy instance of `component` exposes
+pub fn expected_metric(component: Component) -> &'static str {
+    match component {
+        Component::NodeExporter => "node_cpu_seconds_total",
+        Component::WindowsExporter => "windows_cpu_time_total",
+        Component::ProcessCpuAgent => "process_cpu_percent",
+    }
+}
+
+/// Metrics endpoint of a component reachable at `endpoint`, a `host:port`
+pub fn metrics_url(endpoint: &str, tls: bool) -> String {
+    let scheme = if tls { "https" } else { "http" };
+    format!("{scheme}://{endpoint}/metrics")
+}
+
+/// Scrape each `(component, endpoint)` until it exposes its expected metric family,
+/// authenticating as configured in `web` where the component serves it.
+/// All components share one `timeout`, since they start in parallel.
+pub fn check_all(
+    targets: &[(Component, String)],
+    timeout: Duration,
+    web: Option<&WebConfig>,
+) -> Vec<HealthCheck> {
+    let deadline = Instant::now() + timeout;
+    let client = Client::builder()
+        .no_proxy()
+        .timeout(Duration::from_secs(5))
+        // The certificate is checked by Prometheus; this only asks whether the exporter answers
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap_or_default();
+
+    targets
+        .iter()
+        .map(|(component, endpoint)| {
+            let component = *component;
+            let web = web.filter(|_| component.serves_web_config());
+            let url = metrics_url(endpoint, web.is_some_and(WebConfig::tls_enabled));
+            let error = poll(&client, &url, expected_metric(component), web, deadline).err();
+            HealthCheck {
+                component,
+                url,
+                error,
+            }
+        })
+        .collect()
+}
+
+/// Retry `scrape` until it succeeds or `deadline` passes, returning the last error
+fn poll(
+    client: &Client,
+    url: &str,
+    metric: &str,
+    web: Option<&WebConfig>,
+    deadline: Instant,
+) -> Result<(), String> {
+    loop {
+        let result = scrape(client, url, metric, web);
+        if result.is_ok() || Instant::now() + POLL_INTERVAL > deadline {
+            return result;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn scrape(client: &Client, url: &str, metric: &str, web: Option<&WebConfig>) -> Result<(), String> {
+    let mut request = client.get(url);
+    let credentials = web.and_then(WebConfig::credentials);
+    if let Some((user, password)) = credentials {
+        request = request.basic_auth(user, Some(password));
+    }
+    let response = request.send().map_err(|e| {
+        if e.is_connect() {
+            "connection refused".to_string()
+        } else {
+            e.to_string()
+        }
+    })?;
+    // Without a known password, rejecting the scrape is all an exporter
+    // enforcing basic auth can show
+    if response.status() == StatusCode::UNAUTHORIZED
+        && credentials.is_none()
+        && web.is_some_and(|web| web.username().is_some())
+    {
+        return Ok(());
+    }
+    if !response.status().is_success() {
+        return Err(format!("HTTP {}", response.status()));
+    }
+    let body = response.text().map_err(|e| e.to_string())?;
+    if metric_families(&body).contains(metric) {
+        Ok(())
+    } else {
+        Err(format!("{metric} not exposed"))
+    }
+}
+
+/// Names of the metric families in a Prometheus text exposition.
+/// Families are taken from `# HELP` / `# TYPE` lines and from sample names,
+/// so untyped samples are found as well.
+pub fn metric_families(exposition: &str) -> HashSet<&str> {
+    let mut families = HashSet::new();
+    for line in exposition.lines().map(str::trim) {
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(comment) = line.strip_prefix('#') {
+            let mut words = comment.split_whitespace();
+            if let (Some("HELP" | "TYPE"), Some(name)) = (words.next(), words.next()) {
+                families.insert(name);
+            }
+            continue;
+        }
+        let end = line
+            .find(|c: char| c == '{' || c.is_whitespace())
+            .unwrap_or(line.len());
+        let name = &line[..end];
+        if !name.is_empty() {
+            families.insert(name);
+        }
+    }
+    families
+}
+
+/// Render the per-component pass/fail table
+pub fn format_table(checks: &[HealthCheck]) -> String {
+    let mut table = format!(
+        "{:<20} {:<6} {:<34} Details\n",
+        "Component", "Result", "Endpoint"
+    );
+    for check in checks {
+        let (result, details) = match &check.error {
+            None => ("PASS", expected_metric(check.component).to_string()),
+            Some(error) => ("FAIL", error.clone()),
+        };
+        table.push_str(&format!(
+            "{:<20} {:<6} {:<34} {details}\n",
+            check.component.name(),
+            result,
+            check.url
+        ));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WebSettings;
+    use crate::exporter::listen::local_endpoint;
+    use std::io::{Read, Write};
+    use std::net::{Ipv4Addr, TcpListener};
+
+    const NODE_SAMPLE: &str = r#"# HELP node_cpu_seconds_total Seconds the CPUs spent in each mode.
+# TYPE node_cpu_seconds_total counter
+node_cpu_seconds_total{cpu="0",mode="idle"} 1234.5
+node_cpu_seconds_total{cpu="0",mode="user"} 42
+# HELP go_goroutines Number of goroutines that currently exist.
+# TYPE go_goroutines gauge
+go_goroutines 8
+untyped_metric 1 1700000000000
+"#;
+
+    /// Serve a single HTTP response on a random local port
+    fn serve_once(status: &str, body: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let response = format!(
+            "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn test_metric_families() {
+        let families = metric_families(NODE_SAMPLE);
+        assert!(families.contains("node_cpu_seconds_total"));
+        assert!(families.contains("go_goroutines"));
+        assert!(families.contains("untyped_metric"));
+        assert!(!families.contains("node_memory_MemFree_bytes"));
+        assert!(metric_families("").is_empty());
+    }
+
+    #[test]
+    fn test_expected_metrics() {
+        assert_eq!(
+            expected_metric(Component::NodeExporter),
+            "node_cpu_seconds_total"
+        );
+        assert_eq!(
+            expected_metric(Component::ProcessCpuAgent),
+            "process_cpu_percent"
+        );
+    }
+
+    #[test]
+    fn test_check_passes_when_metric_exposed() {
+        let port = serve_once("200 OK", NODE_SAMPLE);
+        // As reached when the exporter listens on loopback only
+        let endpoint = local_endpoint(Some(Ipv4Addr::LOCALHOST.into()), port);
+        let checks = check_all(&[(Component::NodeExporter, endpoint)], Duration::ZERO, None);
+        assert!(checks[0].passed(), "{:?}", checks[0].error);
+        assert_eq!(checks[0].url, format!("http://127.0.0.1:{port}/metrics"));
+    }
+
+    #[test]
+    fn test_check_fails_when_metric_missing() {
+        let port = serve_once("200 OK", "go_goroutines 8\n");
+        let checks = check_all(
+            &[(Component::ProcessCpuAgent, local_endpoint(None, port))],
+            Duration::ZERO,
+            None,
+        );
+        assert_eq!(
+            checks[0].error.as_deref(),
+            Some("process_cpu_percent not exposed")
+        );
+
+        let port = serve_once("503 Service Unavailable", "");
+        let checks = check_all(
+            &[(Component::NodeExporter, local_endpoint(None, port))],
+            Duration::ZERO,
+            None,
+        );
+        assert!(checks[0].error.as_deref().unwrap().contains("503"));
+    }
+
+    #[test]
+    fn test_check_with_basic_auth() {
+        let web = |password: &str| {
+            WebConfig::from_settings(&WebSettings {
+                basic_auth_users: Some([("prometheus".to_string(), password.to_string())].into()),
+                ..Default::default()
+            })
+        };
+        // Only a hash is known, so the rejection shows the exporter enforces basic auth
+        let hashed = web("$2b$10$wUVOwnfLyjkI6cOg4SFoIebu/EDFQuBJv9M7z0wCeXHCX9bHhWV2O");
+        let port = serve_once("401 Unauthorized", "");
+        let checks = check_all(
+            &[(Component::NodeExporter, local_endpoint(None, port))],
+            Duration::ZERO,
+            hashed.as_ref(),
+        );
+        assert!(checks[0].passed(), "{:?}", checks[0].error);
+
+        let plain = web("secret");
+        let port = serve_once("401 Unauthorized", "");
+        let checks = check_all(
+            &[(Component::NodeExporter, local_endpoint(None, port))],
+            Duration::ZERO,
+            plain.as_ref(),
+        );
+        assert!(checks[0].error.as_deref().unwrap().contains("401"));
+    }
+
+    #[test]
+    fn test_agent_is_checked_without_web_config() {
+        let web = WebConfig::from_settings(&WebSettings {
+            basic_auth_users: Some([("prometheus".to_string(), "secret".to_string())].into()),
+            self_signed_cert: Some(true),
+            ..Default::default()
+        });
+        let port = serve_once("200 OK", "process_cpu_percent{pid=\"1\"} 0.5\n");
+        let checks = check_all(
+            &[(Component::ProcessCpuAgent, local_endpoint(None, port))],
+            Duration::ZERO,
+            web.as_ref(),
+        );
+        assert!(checks[0].passed(), "{:?}", checks[0].error);
+        assert!(checks[0].url.starts_with("http://"));
+    }
+
+    #[test]
+    fn test_check_fails_when_nothing_listens() {
+        // Bind and drop to find a port that is very likely closed
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let checks = check_all(
+            &[(Component::NodeExporter, local_endpoint(None, port))],
+            Duration::ZERO,
+            None,
+        );
+        assert!(!checks[0].passed());
+    }
+
+    #[test]
+    fn test_format_table() {
+        let checks = vec![
+            HealthCheck {
+                component: Component::NodeExporter,
+                url: metrics_url("localhost:31415", false),
+                error: None,
+            },
+            HealthCheck {
+                component: Component::ProcessCpuAgent,
+                url: metrics_url("localhost:31416", true),
+                error: Some("connection refused".to_string()),
+            },
+        ];
+        let table = format_table(&checks);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("Component"));
+        assert!(lines[1].starts_with("node_exporter"));
+        assert!(lines[1].contains("PASS"));
+        assert!(lines[1].contains("http://localhost:31415/metrics"));
+        assert!(lines[2].contains("https://localhost:31416/metrics"));
+        assert!(lines[2].contains("FAIL"));
+        assert!(lines[2].ends_with("connection refused"));
+    }
+}
@@ -0,0 +1,1024 @@
+use crate::exporter::manifest::ServiceRecord;
+use crate::exporter::recorder::{self, progress};
+use crate::exporter::service::{HardeningProfile, SystemdScope};
+use crate::exporter::{downloader, service};
+use crate::os_detector;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+const INIT_D_DIR: &str = "/etc/init.d";
+const RUNIT_SERVICE_DIR: &str = "/etc/sv";
+const S6_SERVICE_DIR: &str = "/etc/s6/sv";
+/// Scan directories watched by `runsvdir`, in order of preference
+const RUNIT_SCAN_DIRS: [&str; 3] = ["/var/service", "/etc/service", "/service"];
+/// Scan directories watched by `s6-svscan`, in order of preference
+const S6_SCAN_DIRS: [&str; 2] = ["/run/service", "/service"];
+const LAUNCH_DAEMONS_DIR: &str = "/Library/LaunchDaemons";
+const LAUNCHD_LABEL_PREFIX: &str = "io.prometheus.";
+const LAUNCHD_LOG_DIR: &str = "/var/log";
+
+/// Account for launchd daemons that need no privileges; macOS has no prometheus account
+pub const LAUNCHD_UNPRIVILEGED_USER: &str = "nobody";
+
+/// Service managers the exporters can be registered with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InitSystem {
+    Systemd,
+    /// The installing user's systemd manager, for installs without root
+    #[value(name = "systemd-user")]
+    #[serde(rename = "systemd-user")]
+    SystemdUser,
+    #[value(name = "openrc")]
+    OpenRc,
+    #[value(name = "sysvinit")]
+    SysVinit,
+    Runit,
+    S6,
+    /// Always used on macOS, so it is not offered as a `--init-system` choice
+    #[value(skip)]
+    Launchd,
+}
+
+/// A long-running exporter service, rendered for each init system
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceDefinition {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub command: String,
+    pub args: Vec<String>,
+    pub user: &'static str,
+    /// Sandboxing of the systemd unit; other init systems have no equivalent
+    pub hardening: HardeningProfile,
+    /// Files the service runs from, mounted read-only by the systemd sandbox
+    pub read_only_paths: Vec<String>,
+    /// Directory the service runs in, for programs reading files relative to it
+    pub working_directory: Option<String>,
+}
+
+impl InitSystem {
+    /// Detect the init system managing this host, `None` when it is not supported
+    pub fn detect() -> Option<Self> {
+        if cfg!(target_os = "macos") {
+            return Some(InitSystem::Launchd);
+        }
+        if !cfg!(target_os = "linux") {
+            return None;
+        }
+        let pid1 = fs::read_to_string("/proc/1/comm").unwrap_or_default();
+        classify(
+            pid1.trim(),
+            |path| Path::new(path).is_dir(),
+            |program| os_detector::find_program(program).is_some(),
+        )
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            InitSystem::Systemd => "systemd",
+            InitSystem::SystemdUser => "systemd --user",
+            InitSystem::OpenRc => "OpenRC",
+            InitSystem::SysVinit => "SysVinit",
+            InitSystem::Runit => "runit",
+            InitSystem::S6 => "s6",
+            InitSystem::Launchd => "launchd",
+        }
+    }
+
+    /// systemd instance managing the services, `None` for other init systems
+    pub fn systemd_scope(self) -> Option<SystemdScope> {
+        match self {
+            InitSystem::Systemd => Some(SystemdScope::System),
+            InitSystem::SystemdUser => Some(SystemdScope::User),
+            _ => None,
+        }
+    }
+
+    /// Whether registering services needs root; the user manager runs them as the installing user
+    pub fn needs_root(self) -> bool {
+        self != InitSystem::SystemdUser
+    }
+
+    /// File the service definition for `service` is written to
+    pub fn definition_path(self, service: &str) -> String {
+        match self {
+            InitSystem::Systemd => SystemdScope::System.unit_path(service),
+            InitSystem::SystemdUser => SystemdScope::User.unit_path(service),
+            InitSystem::OpenRc | InitSystem::SysVinit => format!("{INIT_D_DIR}/{service}"),
+            InitSystem::Runit => format!("{RUNIT_SERVICE_DIR}/{service}/run"),
+            InitSystem::S6 => format!("{S6_SERVICE_DIR}/{service}/run"),
+            InitSystem::Launchd => format!("{LAUNCH_DAEMONS_DIR}/{}.plist", launchd_label(service)),
+        }
+    }
+
+    /// Programs used to register and control services
+    pub fn required_tools(self) -> Vec<&'static str> {
+        match self {
+            InitSystem::Systemd | InitSystem::SystemdUser => vec!["systemctl"],
+            InitSystem::OpenRc => vec!["rc-service", "rc-update"],
+            // Detection already found chkconfig or update-rc.d
+            InitSystem::SysVinit => Vec::new(),
+            InitSystem::Runit => vec!["sv"],
+            InitSystem::S6 => vec!["s6-svc", "s6-svscanctl"],
+            InitSystem::Launchd => vec!["launchctl"],
+        }
+    }
+
+    /// Command an administrator runs to start `service`
+    pub fn start_command(self, service: &str) -> String {
+        match self {
+            InitSystem::Systemd => format!("sudo systemctl enable --now {service}"),
+            InitSystem::SystemdUser => format!("systemctl --user enable --now {service}"),
+            InitSystem::OpenRc => format!("sudo rc-service {service} start"),
+            InitSystem::SysVinit => format!("sudo {INIT_D_DIR}/{service} start"),
+            InitSystem::Runit => format!("sudo sv up {}", supervised_path(self, service)),
+            InitSystem::S6 => format!("sudo s6-svc -u {}", supervised_path(self, service)),
+            InitSystem::Launchd => format!("sudo launchctl kickstart {}", launchd_target(service)),
+        }
+    }
+
+    /// Manifest entry for a service registered with this init system
+    pub fn service_record(self, service: &str) -> ServiceRecord {
+        let path = self.definition_path(service);
+        match self {
+            InitSystem::Systemd => ServiceRecord::SystemdUnit {
+                name: service.to_string(),
+                unit_path: path,
+            },
+            init => ServiceRecord::InitService {
+                init,
+                name: service.to_string(),
+                path,
+            },
+        }
+    }
+}
+
+/// Pick the init system from the name of PID 1 and what exists on the host
+fn classify(
+    pid1: &str,
+    dir_exists: impl Fn(&str) -> bool,
+    has_program: impl Fn(&str) -> bool,
+) -> Option<InitSystem> {
+    if dir_exists("/run/systemd/system") {
+        Some(InitSystem::Systemd)
+    } else if dir_exists("/run/openrc") || pid1 == "openrc-init" {
+        Some(InitSystem::OpenRc)
+    } else if pid1 == "runit" || dir_exists("/run/runit") {
+        Some(InitSystem::Runit)
+    } else if pid1 == "s6-svscan" {
+        Some(InitSystem::S6)
+    } else if dir_exists(INIT_D_DIR) && (has_program("chkconfig") || has_program("update-rc.d")) {
+        Some(InitSystem::SysVinit)
+    } else {
+        None
+    }
+}
+
+impl ServiceDefinition {
+    /// Command and arguments as one line, each word quoted with `quote`
+    fn command_line(&self, quote: fn(&str) -> String) -> String {
+        std::iter::once(&self.command)
+            .chain(&self.args)
+            .map(|word| quote(word))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// `cd` into the working directory before the command of a shell script
+    fn cd_command(&self) -> Option<String> {
+        let dir = self.working_directory.as_deref()?;
+        Some(format!("cd {} || exit 1", shell_quote(dir)))
+    }
+
+    /// Render the service definition for `init`
+    pub fn render(&self, init: InitSystem) -> String {
+        let ServiceDefinition {
+            name,
+            description,
+            command,
+            user,
+            ..
+        } = self;
+        // Specifiers start with `%` in unit files
+        let working_directory = self
+            .working_directory
+            .as_deref()
+            .map(|dir| format!("WorkingDirectory={}\n", dir.replace('%', "%%")))
+            .unwrap_or_default();
+        let cd_line = self
+            .cd_command()
+            .map(|cd| format!("{cd}\n"))
+            .unwrap_or_default();
+        match init {
+            InitSystem::Systemd => {
+                let read_only_paths: Vec<&str> =
+                    self.read_only_paths.iter().map(String::as_str).collect();
+                format!(
+                    r#"[Unit]
+Description={description}
+After=network.target
+
+[Service]
+Type=simple
+ExecStart={command_line}
+{working_directory}Restart=always
+RestartSec=10
+User={user}
+Group={user}
+{hardening}
+[Install]
+WantedBy=multi-user.target
+"#,
+                    command_line = self.command_line(systemd_quote),
+                    hardening = self.hardening.directives(&read_only_paths)
+                )
+            }
+            // The user manager runs services as its owner and cannot drop capabilities
+            InitSystem::SystemdUser => format!(
+                r#"[Unit]
+Description={description}
+
+[Service]
+Type=simple
+ExecStart={command_line}
+{working_directory}Restart=always
+RestartSec=10
+
+[Install]
+WantedBy=default.target
+"#,
+                command_line = self.command_line(systemd_quote)
+            ),
+            InitSystem::OpenRc => format!(
+                r#"#!/sbin/openrc-run
+
+name="{name}"
+description="{description}"
+command="{command}"
+command_args="{args}"
+command_user="{user}:{user}"
+{directory}command_background=true
+pidfile="/run/${{RC_SVCNAME}}.pid"
+
+depend() {{
+    need net
+}}
+"#,
+                // OpenRC evals command_args, so it takes shell words
+                args = double_quote_escape(
+                    &self
+                        .args
+                        .iter()
+                        .map(|arg| shell_quote(arg))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                ),
+                directory = self
+                    .working_directory
+                    .as_deref()
+                    .map(|dir| format!("directory=\"{}\"\n", double_quote_escape(dir)))
+                    .unwrap_or_default()
+            ),
+            InitSystem::SysVinit => format!(
+                r#"#!/bin/sh
+### BEGIN INIT INFO
+# Provides:          {name}
+# Required-Start:    $network $remote_fs
+# Required-Stop:     $network $remote_fs
+# Default-Start:     2 3 4 5
+# Default-Stop:      0 1 6
+# Short-Description: {description}
+### END INIT INFO
+# chkconfig: 2345 80 20
+# description: {description}
+
+NAME={name}
+USER={user}
+PIDFILE=/var/run/$NAME.pid
+
+is_running() {{
+    [ -f "$PIDFILE" ] && kill -0 "$(cat "$PIDFILE")" 2>/dev/null
+}}
+
+case "$1" in
+    start)
+        is_running && exit 0
+        echo "Starting $NAME"
+        su -s /bin/sh "$USER" -c '{command_line} >/dev/null 2>&1 & echo $!' > "$PIDFILE"
+        ;;
+    stop)
+        if is_running; then
+            echo "Stopping $NAME"
+            kill "$(cat "$PIDFILE")"
+        fi
+        rm -f "$PIDFILE"
+        ;;
+    restart)
+        "$0" stop
+        sleep 1
+        "$0" start
+        ;;
+    status)
+        if is_running; then
+            echo "$NAME is running"
+        else
+            echo "$NAME is stopped"
+            exit 3
+        fi
+        ;;
+    *)
+        echo "Usage: $0 {{start|stop|restart|status}}"
+        exit 2
+        ;;
+esac
+"#,
+                // Passed to `su -c` inside single quotes
+                command_line = match self.cd_command() {
+                    Some(cd) => format!("{cd}; {}", self.command_line(shell_quote)),
+                    None => self.command_line(shell_quote),
+                }
+                .replace('\'', "'\\''")
+            ),
+            InitSystem::Runit => format!(
+                "#!/bin/sh\n# {description}\nexec 2>&1\n{cd_line}exec chpst -u {user}:{user} {}\n",
+                self.command_line(shell_quote)
+            ),
+            InitSystem::S6 => format!(
+                "#!/bin/sh\n# {description}\nexec 2>&1\n{cd_line}exec s6-setuidgid {user} {}\n",
+                self.command_line(shell_quote)
+            ),
+            InitSystem::Launchd => self.render_plist(),
+        }
+    }
+
+    /// LaunchDaemon property list that keeps the service running from boot
+    fn render_plist(&self) -> String {
+        let label = xml_escape(&launchd_label(self.name));
+        let arguments: String = std::iter::once(&self.command)
+            .chain(&self.args)
+            .map(|arg| format!("        <string>{}</string>\n", xml_escape(arg)))
+            .collect();
+        // Daemons run as root unless a user is named
+        let user = match self.user {
+            "root" => String::new(),
+            user => format!(
+                "    <key>UserName</key>\n    <string>{}</string>\n",
+                xml_escape(user)
+            ),
+        };
+        let working_directory = match &self.working_directory {
+            Some(dir) => format!(
+                "    <key>WorkingDirectory</key>\n    <string>{}</string>\n",
+                xml_escape(dir)
+            ),
+            None => String::new(),
+        };
+        let log = format!("{LAUNCHD_LOG_DIR}/{}.log", xml_escape(self.name));
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{label}</string>
+    <key>ProgramArguments</key>
+    <array>
+{arguments}    </array>
+{working_directory}{user}    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <true/>
+    <key>StandardOutPath</key>
+    <string>{log}</string>
+    <key>StandardErrorPath</key>
+    <string>{log}</string>
+</dict>
+</plist>
+"#
+        )
+    }
+}
+
+/// Write the service definition for `init`, register it to start at boot
+/// and bring it into the desired state. The service is restarted when its
+/// definition or `files_changed`, and started when it is not running.
+/// Returns `true` when anything was changed.
+pub fn install_service(
+    init: InitSystem,
+    definition: &ServiceDefinition,
+    files_changed: bool,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let name = definition.name;
+    let path = init.definition_path(name);
+    if let Some(parent) = downloader::get_parent_directory(&path) {
+        downloader::ensure_directory_exists(&parent)?;
+    }
+    let changed = downloader::write_file_if_changed(&path, definition.render(init).as_bytes())?;
+    if changed {
+        // Unit files and property lists are read by the init system; every other definition is a script
+        #[cfg(unix)]
+        match init {
+            InitSystem::Systemd | InitSystem::SystemdUser => {}
+            // launchd refuses property lists that are writable by anyone but root
+            InitSystem::Launchd => downloader::set_permissions(&path, 0o644)?,
+            _ => downloader::set_executable_permissions(&path)?,
+        }
+        progress!("{} service created at: {path}", init.name());
+    }
+
+    if let Some(scope) = init.systemd_scope() {
+        let started = service::apply_systemd_unit(scope, name, changed, files_changed)?;
+        return Ok(changed || started);
+    }
+    match init {
+        // Handled above
+        InitSystem::Systemd | InitSystem::SystemdUser => {}
+        InitSystem::OpenRc | InitSystem::SysVinit => {
+            if changed {
+                register_init_script(init, name)?;
+            }
+        }
+        InitSystem::Runit | InitSystem::S6 => {
+            let supervised = supervised_path(init, name);
+            if fs::symlink_metadata(&supervised).is_err() {
+                // The supervisor starts the service once it appears in the scan directory
+                let service_dir = downloader::get_parent_directory(&path).unwrap_or_default();
+                run("ln", &["-s", &service_dir, &supervised])?;
+                if init == InitSystem::S6 {
+                    run("s6-svscanctl", &["-a", &scan_dir(init)])?;
+                }
+                return Ok(true);
+            }
+        }
+        InitSystem::Launchd => {
+            let loaded = launchd_loaded(name);
+            if loaded && changed {
+                // A loaded daemon keeps its old definition until it is booted out
+                run("launchctl", &["bootout", &launchd_target(name)])?;
+            }
+            if !loaded || changed {
+                run("launchctl", &["enable", &launchd_target(name)])?;
+                run("launchctl", &["bootstrap", "system", &path])?;
+                return Ok(true);
+            }
+        }
+    }
+
+    if service_running(init, name) != Some(true) {
+        control(init, name, Control::Start)?;
+    } else if changed || files_changed {
+        control(init, name, Control::Restart)?;
+    } else {
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Whether `service` is running under `init`.
+/// Returns `None` when the init system could not be queried.
+pub fn service_running(init: InitSystem, service: &str) -> Option<bool> {
+    let output = match init {
+        InitSystem::Systemd => return service::systemd_unit_active(SystemdScope::System, service),
+        InitSystem::SystemdUser => {
+            return service::systemd_unit_active(SystemdScope::User, service);
+        }
+        InitSystem::OpenRc => Command::new("rc-service")
+            .args([service, "status"])
+            .output(),
+        InitSystem::SysVinit => {
+            let script = init.definition_path(service);
+            if !Path::new(&script).exists() {
+                return Some(false);
+            }
+            Command::new(script).arg("status").output()
+        }
+        InitSystem::Runit => Command::new("sv")
+            .args(["status", &supervised_path(init, service)])
+            .output(),
+        InitSystem::S6 => Command::new("s6-svstat")
+            .arg(supervised_path(init, service))
+            .output(),
+        InitSystem::Launchd => Command::new("launchctl")
+            .args(["print", &launchd_target(service)])
+            .output(),
+    }
+    .ok()?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Some(match init {
+        InitSystem::Runit => stdout.starts_with("run:"),
+        InitSystem::S6 => stdout.starts_with("up"),
+        InitSystem::Launchd => stdout.lines().any(|line| line.trim() == "state = running"),
+        _ => output.status.success(),
+    })
+}
+
+/// Stop `service`, unregister it from `init` and delete its definition.
+/// Returns a description of everything that was removed.
+pub fn remove_service(
+    init: InitSystem,
+    service: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    if let Some(scope) = init.systemd_scope() {
+        return service::remove_systemd_unit(scope, service);
+    }
+
+    let mut removed = Vec::new();
+    let path = init.definition_path(service);
+    if !Path::new(&path).exists() {
+        return Ok(removed);
+    }
+
+    control(init, service, Control::Stop).ok();
+    match init {
+        InitSystem::OpenRc => {
+            recorder::run_command("rc-update", &["del", service, "default"])?;
+        }
+        InitSystem::SysVinit => {
+            if os_detector::find_program("chkconfig").is_some() {
+                recorder::run_command("chkconfig", &["--del", service])?;
+            } else {
+                recorder::run_command("update-rc.d", &["-f", service, "remove"])?;
+            }
+        }
+        InitSystem::Runit | InitSystem::S6 => {
+            let supervised = supervised_path(init, service);
+            if downloader::remove_path(&supervised)? {
+                removed.push(supervised);
+            }
+            if init == InitSystem::S6 {
+                recorder::run_command("s6-svscanctl", &["-a", &scan_dir(init)])?;
+            }
+        }
+        // Stopping a launchd daemon already unloaded it
+        InitSystem::Systemd | InitSystem::SystemdUser | InitSystem::Launchd => {}
+    }
+    removed.push(format!("{} service {service}", init.name()));
+
+    // runit and s6 services are whole directories
+    let definition = match init {
+        InitSystem::Runit | InitSystem::S6 => {
+            downloader::get_parent_directory(&path).unwrap_or(path)
+        }
+        _ => path,
+    };
+    downloader::remove_path(&definition)?;
+    removed.push(definition);
+    Ok(removed)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Control {
+    Start,
+    Stop,
+    Restart,
+}
+
+fn control(
+    init: InitSystem,
+    service: &str,
+    action: Control,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let verb = match action {
+        Control::Start => "start",
+        Control::Stop => "stop",
+        Control::Restart => "restart",
+    };
+    match init {
+        InitSystem::Systemd => run("systemctl", &[verb, service]),
+        InitSystem::SystemdUser => run(
+            "systemctl",
+            &SystemdScope::User.systemctl_args(&[verb, service]),
+        ),
+        InitSystem::OpenRc => run("rc-service", &[service, verb]),
+        InitSystem::SysVinit => run(&format!("{INIT_D_DIR}/{service}"), &[verb]),
+        InitSystem::Runit => {
+            let verb = match action {
+                Control::Start => "up",
+                Control::Stop => "down",
+                Control::Restart => "restart",
+            };
+            run("sv", &[verb, &supervised_path(init, service)])
+        }
+        InitSystem::S6 => {
+            let flag = match action {
+                Control::Start => "-u",
+                Control::Stop => "-d",
+                Control::Restart => "-r",
+            };
+            run("s6-svc", &[flag, &supervised_path(init, service)])
+        }
+        InitSystem::Launchd => {
+            let target = launchd_target(service);
+            match action {
+                Control::Start => run("launchctl", &["kickstart", &target]),
+                Control::Stop => run("launchctl", &["bootout", &target]),
+                Control::Restart => run("launchctl", &["kickstart", "-k", &target]),
+            }
+        }
+    }
+}
+
+/// Enable an init script in the default runlevels
+fn register_init_script(init: InitSystem, service: &str) -> Result<(), Box<dyn std::error::Error>> {
+    match init {
+        InitSystem::OpenRc => run("rc-update", &["add", service, "default"]),
+        _ if os_detector::find_program("chkconfig").is_some() => {
+            run("chkconfig", &["--add", service])
+        }
+        _ => run("update-rc.d", &[service, "defaults"]),
+    }
+}
+
+/// Scan directory of a runit or s6 supervisor
+fn scan_dir(init: InitSystem) -> String {
+    let candidates: &[&str] = match init {
+        InitSystem::S6 => &S6_SCAN_DIRS,
+        _ => &RUNIT_SCAN_DIRS,
+    };
+    candidates
+        .iter()
+        .find(|dir| Path::new(dir).is_dir())
+        .unwrap_or(&candidates[0])
+        .to_string()
+}
+
+/// Link to the service directory inside the supervisor's scan directory
+fn supervised_path(init: InitSystem, service: &str) -> String {
+    format!("{}/{service}", scan_dir(init))
+}
+
+/// Reverse-DNS label launchd knows the service by
+fn launchd_label(service: &str) -> String {
+    format!("{LAUNCHD_LABEL_PREFIX}{service}")
+}
+
+/// Service target in the system domain, as used by `launchctl`
+fn launchd_target(service: &str) -> String {
+    format!("system/{}", launchd_label(service))
+}
+
+fn launchd_loaded(service: &str) -> bool {
+    Command::new("launchctl")
+        .args(["print", &launchd_target(service)])
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+/// `word` as one word of a shell command, single-quoted unless it is plain
+fn shell_quote(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=,+@%".contains(c));
+    if plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+/// `value` inside a double-quoted shell string
+fn double_quote_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('$', "\\$")
+        .replace('`', "\\`")
+}
+
+/// `word` as one word of a systemd `ExecStart=` line, where `%` starts a
+/// specifier and `$` a variable
+pub fn systemd_quote(word: &str) -> String {
+    let word = word.replace('%', "%%").replace('$', "$$");
+    let plain = !word.is_empty()
+        && !word
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | ';'));
+    if plain {
+        word
+    } else {
+        format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn run(program: &str, args: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+    let output = recorder::run_command(program, args)?;
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("{program} {} failed: {}", args.join(" "), error.trim()).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition() -> ServiceDefinition {
+        ServiceDefinition {
+            name: "example_exporter",
+            description: "Example Exporter",
+            command: "/opt/example/example_exporter".to_string(),
+            args: vec!["--port".to_string(), "9999".to_string()],
+            user: "prometheus",
+            hardening: HardeningProfile::None,
+            read_only_paths: vec!["/opt/example".to_string()],
+            working_directory: None,
+        }
+    }
+
+    #[test]
+    fn test_classify() {
+        let none = |_: &str| false;
+        assert_eq!(
+            classify("systemd", |d| d == "/run/systemd/system", none),
+            Some(InitSystem::Systemd)
+        );
+        assert_eq!(
+            classify("init", |d| d == "/run/openrc", none),
+            Some(InitSystem::OpenRc)
+        );
+        assert_eq!(
+            classify("openrc-init", none, none),
+            Some(InitSystem::OpenRc)
+        );
+        assert_eq!(classify("runit", none, none), Some(InitSystem::Runit));
+        assert_eq!(classify("s6-svscan", none, none), Some(InitSystem::S6));
+        assert_eq!(
+            classify("init", |d| d == INIT_D_DIR, |p| p == "update-rc.d"),
+            Some(InitSystem::SysVinit)
+        );
+        assert_eq!(classify("init", |d| d == INIT_D_DIR, none), None);
+        assert_eq!(classify("sh", none, none), None);
+    }
+
+    #[test]
+    fn test_definition_paths() {
+        assert_eq!(
+            InitSystem::Systemd.definition_path("node_exporter"),
+            "/etc/systemd/system/node_exporter.service"
+        );
+        assert_eq!(
+            InitSystem::OpenRc.definition_path("node_exporter"),
+            "/etc/init.d/node_exporter"
+        );
+        assert_eq!(
+            InitSystem::SysVinit.definition_path("node_exporter"),
+            "/etc/init.d/node_exporter"
+        );
+        assert_eq!(
+            InitSystem::Runit.definition_path("node_exporter"),
+            "/etc/sv/node_exporter/run"
+        );
+        assert_eq!(
+            InitSystem::S6.definition_path("node_exporter"),
+            "/etc/s6/sv/node_exporter/run"
+        );
+    }
+
+    #[test]
+    fn test_service_record() {
+        assert_eq!(
+            InitSystem::Systemd.service_record("node_exporter"),
+            ServiceRecord::SystemdUnit {
+                name: "node_exporter".to_string(),
+                unit_path: "/etc/systemd/system/node_exporter.service".to_string(),
+            }
+        );
+        assert_eq!(
+            InitSystem::Runit.service_record("node_exporter"),
+            ServiceRecord::InitService {
+                init: InitSystem::Runit,
+                name: "node_exporter".to_string(),
+                path: "/etc/sv/node_exporter/run".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_render_systemd() {
+        let content = definition().render(InitSystem::Systemd);
+        assert!(content.contains("Description=Example Exporter\n"));
+        assert!(content.contains("ExecStart=/opt/example/example_exporter --port 9999\n"));
+        assert!(content.contains("User=prometheus\nGroup=prometheus\n"));
+        assert!(content.ends_with("WantedBy=multi-user.target\n"));
+    }
+
+    #[test]
+    fn test_systemd_user() {
+        assert!(
+            InitSystem::SystemdUser
+                .definition_path("node_exporter")
+                .ends_with("/systemd/user/node_exporter.service")
+        );
+        assert_eq!(
+            InitSystem::SystemdUser.start_command("node_exporter"),
+            "systemctl --user enable --now node_exporter"
+        );
+        assert!(!InitSystem::SystemdUser.needs_root());
+        assert_eq!(
+            InitSystem::from_str("systemd-user", true),
+            Ok(InitSystem::SystemdUser)
+        );
+
+        let definition = ServiceDefinition {
+            hardening: HardeningProfile::Strict,
+            ..definition()
+        };
+        let content = definition.render(InitSystem::SystemdUser);
+        assert!(content.contains("ExecStart=/opt/example/example_exporter --port 9999\n"));
+        assert!(!content.contains("User="));
+        assert!(!content.contains("ProtectSystem"));
+        assert!(content.ends_with("WantedBy=default.target\n"));
+    }
+
+    #[test]
+    fn test_render_openrc() {
+        let content = definition().render(InitSystem::OpenRc);
+        assert!(content.starts_with("#!/sbin/openrc-run\n"));
+        assert!(content.contains("command=\"/opt/example/example_exporter\"\n"));
+        assert!(content.contains("command_args=\"--port 9999\"\n"));
+        assert!(content.contains("command_user=\"prometheus:prometheus\"\n"));
+        assert!(content.contains("need net"));
+    }
+
+    #[test]
+    fn test_render_sysvinit() {
+        let content = definition().render(InitSystem::SysVinit);
+        assert!(content.contains("# Provides:          example_exporter\n"));
+        assert!(content.contains("# chkconfig: 2345 80 20\n"));
+        assert!(content.contains(
+            "su -s /bin/sh \"$USER\" -c '/opt/example/example_exporter --port 9999 >/dev/null 2>&1 & echo $!'"
+        ));
+        assert!(content.contains("Usage: $0 {start|stop|restart|status}"));
+    }
+
+    #[test]
+    fn test_render_supervised() {
+        assert_eq!(
+            definition().render(InitSystem::Runit),
+            "#!/bin/sh\n# Example Exporter\nexec 2>&1\n\
+             exec chpst -u prometheus:prometheus /opt/example/example_exporter --port 9999\n"
+        );
+        assert_eq!(
+            definition().render(InitSystem::S6),
+            "#!/bin/sh\n# Example Exporter\nexec 2>&1\n\
+             exec s6-setuidgid prometheus /opt/example/example_exporter --port 9999\n"
+        );
+    }
+
+    #[test]
+    fn test_render_quotes_arguments() {
+        let definition = ServiceDefinition {
+            args: vec![
+                "--collector.filesystem.mount-points-exclude=^/(sys|proc)($|/)".to_string(),
+                "--label=it's 100%".to_string(),
+            ],
+            ..definition()
+        };
+        assert!(definition.render(InitSystem::Systemd).contains(
+            "ExecStart=/opt/example/example_exporter \
+             --collector.filesystem.mount-points-exclude=^/(sys|proc)($$|/) \
+             \"--label=it's 100%%\"\n"
+        ));
+        assert!(definition.render(InitSystem::Runit).contains(
+            " /opt/example/example_exporter \
+             '--collector.filesystem.mount-points-exclude=^/(sys|proc)($|/)' \
+             '--label=it'\\''s 100%'\n"
+        ));
+        assert!(definition.render(InitSystem::OpenRc).contains(
+            "command_args=\"'--collector.filesystem.mount-points-exclude=^/(sys|proc)(\\$|/)' \
+             '--label=it'\\\\''s 100%'\"\n"
+        ));
+        assert!(definition.render(InitSystem::SysVinit).contains(
+            "-c '/opt/example/example_exporter \
+             '\\''--collector.filesystem.mount-points-exclude=^/(sys|proc)($|/)'\\'' "
+        ));
+    }
+
+    #[test]
+    fn test_render_working_directory() {
+        let definition = ServiceDefinition {
+            working_directory: Some("/opt/example dir".to_string()),
+            ..definition()
+        };
+        assert!(definition.render(InitSystem::Systemd).contains(
+            "ExecStart=/opt/example/example_exporter --port 9999\n\
+             WorkingDirectory=/opt/example dir\n"
+        ));
+        assert!(
+            definition
+                .render(InitSystem::SystemdUser)
+                .contains("WorkingDirectory=/opt/example dir\n")
+        );
+        assert!(
+            definition
+                .render(InitSystem::OpenRc)
+                .contains("directory=\"/opt/example dir\"\n")
+        );
+        assert!(definition.render(InitSystem::SysVinit).contains(
+            "-c 'cd '\\''/opt/example dir'\\'' || exit 1; /opt/example/example_exporter --port 9999 "
+        ));
+        assert_eq!(
+            definition.render(InitSystem::Runit),
+            "#!/bin/sh\n# Example Exporter\nexec 2>&1\ncd '/opt/example dir' || exit 1\n\
+             exec chpst -u prometheus:prometheus /opt/example/example_exporter --port 9999\n"
+        );
+        assert!(
+            definition
+                .render(InitSystem::S6)
+                .contains("\ncd '/opt/example dir' || exit 1\nexec s6-setuidgid ")
+        );
+        assert!(definition.render(InitSystem::Launchd).contains(
+            "    </array>\n    <key>WorkingDirectory</key>\n    <string>/opt/example dir</string>\n"
+        ));
+    }
+
+    #[test]
+    fn test_launchd_paths() {
+        assert_eq!(
+            InitSystem::Launchd.definition_path("node_exporter"),
+            "/Library/LaunchDaemons/io.prometheus.node_exporter.plist"
+        );
+        assert_eq!(
+            InitSystem::Launchd.start_command("node_exporter"),
+            "sudo launchctl kickstart system/io.prometheus.node_exporter"
+        );
+    }
+
+    #[test]
+    fn test_render_launchd_plist() {
+        let definition = ServiceDefinition {
+            args: vec![
+                "--collector.filesystem.mount-points-exclude=^/(dev|private)($|/)&".to_string(),
+            ],
+            ..definition()
+        };
+        let content = definition.render(InitSystem::Launchd);
+        assert!(content.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+        assert!(
+            content.contains(
+                "<key>Label</key>\n    <string>io.prometheus.example_exporter</string>\n"
+            )
+        );
+        assert!(content.contains(
+            "    <array>\n        <string>/opt/example/example_exporter</string>\n        \
+             <string>--collector.filesystem.mount-points-exclude=^/(dev|private)($|/)&amp;</string>\n    \
+             </array>\n"
+        ));
+        assert!(content.contains("<key>UserName</key>\n    <string>prometheus</string>\n"));
+        assert!(content.contains("<key>RunAtLoad</key>\n    <true/>\n"));
+        assert!(content.contains("<key>KeepAlive</key>\n    <true/>\n"));
+        assert!(content.contains("<string>/var/log/example_exporter.log</string>"));
+        assert!(content.ends_with("</dict>\n</plist>\n"));
+
+        let root = ServiceDefinition {
+            user: "root",
+            ..definition
+        };
+        assert!(!root.render(InitSystem::Launchd).contains("UserName"));
+    }
+
+    #[test]
+    fn test_launchd_is_not_a_cli_choice() {
+        assert!(InitSystem::from_str("launchd", true).is_err());
+        assert!(InitSystem::from_str("openrc", true).is_ok());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_install_openrc_service_dry_run() {
+        use crate::exporter::recorder::Action;
+
+        let definition = ServiceDefinition {
+            name: "prometheus-agents-setup-nonexistent",
+            ..definition()
+        };
+        let path = InitSystem::OpenRc.definition_path(definition.name);
+        let (result, actions) =
+            recorder::dry_run(|| install_service(InitSystem::OpenRc, &definition, false));
+        assert!(result.unwrap());
+
+        let command = |program: &str, args: &[&str]| Action::RunCommand {
+            program: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        };
+        assert!(actions.contains(&Action::WriteFile { path: path.clone() }));
+        assert!(actions.contains(&command("rc-update", &["add", definition.name, "default"])));
+        assert!(actions.contains(&command("rc-service", &[definition.name, "start"])));
+        assert!(!Path::new(&path).exists());
+    }
+}
@@ -0,0 +1,209 @@
+use crate::os_detector::InterfaceAddress;
+use serde::Deserialize;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+
+/// Where an exporter accepts connections, as written in the installer config
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum ListenAddress {
+    /// Every interface, the exporters' default
+    #[default]
+    All,
+    /// Only this host, for a Prometheus or agent running locally
+    Loopback,
+    /// The first private address found on the host's interfaces
+    Private,
+    /// The first address of the named network interface, such as a management NIC
+    Interface(String),
+    Address(IpAddr),
+}
+
+impl FromStr for ListenAddress {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        Ok(match value {
+            "" => return Err("listen address must not be empty".to_string()),
+            "all" => Self::All,
+            "loopback" | "localhost" => Self::Loopback,
+            "private" => Self::Private,
+            _ => match value.parse() {
+                Ok(ip) => Self::Address(ip),
+                Err(_) => Self::Interface(value.to_string()),
+            },
+        })
+    }
+}
+
+impl TryFrom<String> for ListenAddress {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::All => write!(f, "all"),
+            Self::Loopback => write!(f, "loopback"),
+            Self::Private => write!(f, "private"),
+            Self::Interface(name) => write!(f, "interface {name}"),
+            Self::Address(ip) => write!(f, "{ip}"),
+        }
+    }
+}
+
+impl ListenAddress {
+    /// Address to bind among the host's `interfaces`, `None` for every interface
+    pub fn resolve(&self, interfaces: &[InterfaceAddress]) -> Result<Option<IpAddr>, String> {
+        match self {
+            Self::All => Ok(None),
+            Self::Loopback => Ok(Some(IpAddr::V4(Ipv4Addr::LOCALHOST))),
+            Self::Private => interfaces
+                .iter()
+                .map(|address| address.ip)
+                .filter(is_private)
+                // Prometheus usually scrapes over IPv4
+                .min_by_key(IpAddr::is_ipv6)
+                .map(Some)
+                .ok_or_else(|| "no private address found on any interface".to_string()),
+            Self::Interface(name) => interfaces
+                .iter()
+                .filter(|address| address.interface == *name)
+                .map(|address| address.ip)
+                // Link-local addresses need a zone, which the exporters cannot take
+                .filter(|ip| !is_link_local(ip))
+                .min_by_key(IpAddr::is_ipv6)
+                .map(Some)
+                .ok_or_else(|| format!("interface {name} has no usable address")),
+            // Interfaces that could not be listed are not held against the address
+            Self::Address(ip)
+                if !interfaces.is_empty()
+                    && !ip.is_loopback()
+                    && !interfaces.iter().any(|address| address.ip == *ip) =>
+            {
+                Err(format!("{ip} is not assigned to any interface"))
+            }
+            Self::Address(ip) => Ok(Some(*ip)),
+        }
+    }
+}
+
+/// `host:port` for an exporter's listen flag, `:port` for every interface
+pub fn bind_address(ip: Option<IpAddr>, port: u16) -> String {
+    match ip {
+        Some(ip) => socket_address(&ip.to_string(), port),
+        None => format!(":{port}"),
+    }
+}
+
+/// `host:port` a local client connects to, `localhost` for every interface
+pub fn local_endpoint(ip: Option<IpAddr>, port: u16) -> String {
+    match ip {
+        Some(ip) => socket_address(&ip.to_string(), port),
+        None => format!("localhost:{port}"),
+    }
+}
+
+/// `host:port`, with IPv6 addresses in brackets
+pub fn socket_address(host: &str, port: u16) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{host}]:{port}")
+    } else {
+        format!("{host}:{port}")
+    }
+}
+
+/// RFC 1918 IPv4 and unique local IPv6 addresses
+fn is_private(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_private(),
+        IpAddr::V6(ip) => ip.segments()[0] & 0xfe00 == 0xfc00,
+    }
+}
+
+fn is_link_local(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_link_local(),
+        IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 == 0xfe80,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interfaces() -> Vec<InterfaceAddress> {
+        [
+            ("lo", "127.0.0.1"),
+            ("eth0", "fe80::1"),
+            ("eth0", "203.0.113.7"),
+            ("eth1", "fd00::5"),
+            ("eth1", "10.0.0.5"),
+        ]
+        .iter()
+        .map(|(interface, ip)| InterfaceAddress {
+            interface: interface.to_string(),
+            ip: ip.parse().unwrap(),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("all".parse(), Ok(ListenAddress::All));
+        assert_eq!("localhost".parse(), Ok(ListenAddress::Loopback));
+        assert_eq!("private".parse(), Ok(ListenAddress::Private));
+        assert_eq!(
+            "10.0.0.5".parse(),
+            Ok(ListenAddress::Address("10.0.0.5".parse().unwrap()))
+        );
+        assert_eq!(
+            "Ethernet 2".parse(),
+            Ok(ListenAddress::Interface("Ethernet 2".to_string()))
+        );
+        assert!("".parse::<ListenAddress>().is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let interfaces = interfaces();
+        let resolve = |value: &str| value.parse::<ListenAddress>().unwrap().resolve(&interfaces);
+        assert_eq!(resolve("all"), Ok(None));
+        assert_eq!(resolve("loopback"), Ok(Some("127.0.0.1".parse().unwrap())));
+        assert_eq!(resolve("private"), Ok(Some("10.0.0.5".parse().unwrap())));
+        assert_eq!(resolve("eth0"), Ok(Some("203.0.113.7".parse().unwrap())));
+        assert_eq!(resolve("10.0.0.5"), Ok(Some("10.0.0.5".parse().unwrap())));
+        assert_eq!(
+            resolve("eth9"),
+            Err("interface eth9 has no usable address".to_string())
+        );
+        assert_eq!(
+            resolve("192.0.2.1"),
+            Err("192.0.2.1 is not assigned to any interface".to_string())
+        );
+        assert!(ListenAddress::Private.resolve(&interfaces[..3]).is_err());
+        assert_eq!(
+            ListenAddress::Address("192.0.2.1".parse().unwrap()).resolve(&[]),
+            Ok(Some("192.0.2.1".parse().unwrap()))
+        );
+    }
+
+    #[test]
+    fn test_addresses() {
+        let ip = Some("10.0.0.5".parse().unwrap());
+        assert_eq!(bind_address(None, 9100), ":9100");
+        assert_eq!(bind_address(ip, 9100), "10.0.0.5:9100");
+        assert_eq!(
+            bind_address(Some("fd00::5".parse().unwrap()), 9100),
+            "[fd00::5]:9100"
+        );
+        assert_eq!(local_endpoint(None, 9100), "localhost:9100");
+        assert_eq!(local_endpoint(ip, 9100), "10.0.0.5:9100");
+    }
+}
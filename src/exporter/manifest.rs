@@ -0,0 +1,423 @@
+use crate::exporter::init_system::InitSystem;
+use crate::exporter::{Component, downloader, user_install_root};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MANIFEST_FILE_NAME: &str = "install-manifest.json";
+
+/// Record of everything the installer changed on this host
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct InstallManifest {
+    /// Version of prometheus-agents-setup that last wrote the manifest
+    pub tool_version: String,
+    pub components: Vec<ComponentRecord>,
+}
+
+/// What was installed for a single component
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ComponentRecord {
+    pub component: Component,
+    /// Upstream version, `None` when the artifact carries no version
+    pub version: Option<String>,
+    pub source: ArtifactSource,
+    /// Seconds since the Unix epoch
+    pub installed_at: u64,
+    pub files: Vec<FileRecord>,
+    pub services: Vec<ServiceRecord>,
+}
+
+/// Where an installed artifact came from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ArtifactSource {
+    Embedded,
+    Remote { url: String },
+}
+
+/// A file written by the installer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileRecord {
+    pub path: String,
+    pub sha256: String,
+    /// Configuration users may edit; `sha256` is the content the installer shipped
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub config: bool,
+}
+
+/// A service registration created by the installer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ServiceRecord {
+    SystemdUnit {
+        name: String,
+        unit_path: String,
+    },
+    /// Service registered with an init system other than systemd
+    InitService {
+        init: InitSystem,
+        name: String,
+        path: String,
+    },
+    WindowsService {
+        name: String,
+    },
+    ScheduledTask {
+        name: String,
+    },
+    MsiPackage {
+        product_code: Option<String>,
+        installer_path: String,
+        /// Properties passed to `msiexec /i`
+        #[serde(default)]
+        properties: Vec<String>,
+    },
+}
+
+impl ArtifactSource {
+    /// Short description used in plans and status output
+    pub fn describe(&self) -> String {
+        match self {
+            ArtifactSource::Embedded => "embedded".to_string(),
+            ArtifactSource::Remote { url } => url.clone(),
+        }
+    }
+}
+
+impl InstallManifest {
+    /// Load the manifest, returning an empty one when the file does not exist
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        if !Path::new(path).exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)?;
+        let manifest = serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid install manifest {path}: {e}"))?;
+        Ok(manifest)
+    }
+
+    /// Write the manifest as pretty-printed JSON
+    pub fn save(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(parent) = downloader::get_parent_directory(path) {
+            downloader::ensure_directory_exists(&parent)?;
+        }
+        self.tool_version = env!("CARGO_PKG_VERSION").to_string();
+        let content = serde_json::to_string_pretty(self)?;
+        downloader::write_file(path, content.as_bytes())
+    }
+
+    pub fn get(&self, component: Component) -> Option<&ComponentRecord> {
+        self.components.iter().find(|r| r.component == component)
+    }
+
+    /// Insert or replace the record for a component
+    pub fn record(&mut self, record: ComponentRecord) {
+        self.remove(record.component);
+        self.components.push(record);
+    }
+
+    pub fn remove(&mut self, component: Component) -> Option<ComponentRecord> {
+        let index = self
+            .components
+            .iter()
+            .position(|r| r.component == component)?;
+        Some(self.components.remove(index))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
+    }
+}
+
+impl ComponentRecord {
+    pub fn new(component: Component, version: Option<String>, source: ArtifactSource) -> Self {
+        let installed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Self {
+            component,
+            version,
+            source,
+            installed_at,
+            files: Vec::new(),
+            services: Vec::new(),
+        }
+    }
+
+    /// Record a file with its current hash. Missing files are skipped.
+    pub fn add_file(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if downloader::path_exists(path) {
+            self.files.push(FileRecord {
+                path: path.to_string(),
+                sha256: downloader::sha256_file(path)?,
+                config: false,
+            });
+        }
+        Ok(())
+    }
+
+    /// Record a configuration file with the hash of the content the installer
+    /// shipped, so later runs can tell local edits apart. Missing files are skipped.
+    pub fn add_config_file(&mut self, path: &str, shipped: &[u8]) {
+        if downloader::path_exists(path) {
+            self.files.push(FileRecord {
+                path: path.to_string(),
+                sha256: downloader::sha256_hex(shipped),
+                config: true,
+            });
+        }
+    }
+
+    /// Recorded hash of a file, if it is part of this record
+    pub fn file_sha256(&self, path: &str) -> Option<&str> {
+        self.files
+            .iter()
+            .find(|f| f.path == path)
+            .map(|f| f.sha256.as_str())
+    }
+
+    /// Whether `path` is recorded and still has the recorded content
+    pub fn file_unmodified(&self, path: &str) -> bool {
+        self.file_sha256(path).is_some_and(|recorded| {
+            downloader::sha256_file(path).is_ok_and(|actual| actual == recorded)
+        })
+    }
+
+    pub fn add_service(&mut self, service: ServiceRecord) {
+        self.services.push(service);
+    }
+
+    /// MSI product code recorded for the component, if any
+    pub fn msi_product_code(&self) -> Option<&str> {
+        self.services.iter().find_map(|s| match s {
+            ServiceRecord::MsiPackage { product_code, .. } => product_code.as_deref(),
+            _ => None,
+        })
+    }
+
+    /// Init system the component's service was registered with, if any
+    pub fn init_system(&self) -> Option<InitSystem> {
+        self.services.iter().find_map(|s| match s {
+            ServiceRecord::SystemdUnit { .. } => Some(InitSystem::Systemd),
+            ServiceRecord::InitService { init, .. } => Some(*init),
+            _ => None,
+        })
+    }
+
+    /// MSI properties the package was installed with, if recorded
+    pub fn msi_properties(&self) -> Option<&[String]> {
+        self.services.iter().find_map(|s| match s {
+            ServiceRecord::MsiPackage { properties, .. } => Some(properties.as_slice()),
+            _ => None,
+        })
+    }
+
+    /// Files that are missing or whose content no longer matches the recorded hash.
+    /// Edited configuration files are expected and only reported when missing.
+    pub fn modified_files(&self) -> Vec<&FileRecord> {
+        self.files
+            .iter()
+            .filter(|f| match downloader::sha256_file(&f.path) {
+                Ok(_) if f.config => false,
+                Ok(hash) => hash != f.sha256,
+                Err(_) => true,
+            })
+            .collect()
+    }
+}
+
+/// Default manifest location under the install root
+pub fn default_manifest_path() -> String {
+    #[cfg(windows)]
+    return format!("C:\\ProgramData\\prometheus\\{MANIFEST_FILE_NAME}");
+
+    #[cfg(not(windows))]
+    return format!("/opt/prometheus/{MANIFEST_FILE_NAME}");
+}
+
+/// Manifest location of a rootless install
+pub fn user_manifest_path() -> String {
+    format!("{}/{MANIFEST_FILE_NAME}", user_install_root())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn sample_record(temp_dir: &TempDir) -> ComponentRecord {
+        let binary = temp_dir.path().join("node_exporter");
+        downloader::write_file(binary.to_str().unwrap(), b"binary").unwrap();
+
+        let mut record = ComponentRecord::new(
+            Component::NodeExporter,
+            Some("1.7.0".to_string()),
+            ArtifactSource::Embedded,
+        );
+        record.add_file(binary.to_str().unwrap()).unwrap();
+        record.add_service(ServiceRecord::SystemdUnit {
+            name: "node_exporter".to_string(),
+            unit_path: "/etc/systemd/system/node_exporter.service".to_string(),
+        });
+        record
+    }
+
+    #[test]
+    fn test_load_missing_manifest_is_empty() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join(MANIFEST_FILE_NAME);
+
+        let manifest = InstallManifest::load(path.to_str().unwrap()).unwrap();
+        assert!(manifest.is_empty());
+    }
+
+    #[test]
+    fn test_save_and_load_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("nested").join(MANIFEST_FILE_NAME);
+
+        let mut manifest = InstallManifest::default();
+        manifest.record(sample_record(&temp_dir));
+        manifest.save(path.to_str().unwrap()).unwrap();
+
+        let loaded = InstallManifest::load(path.to_str().unwrap()).unwrap();
+        assert_eq!(loaded, manifest);
+        assert_eq!(loaded.tool_version, env!("CARGO_PKG_VERSION"));
+
+        let json = fs::read_to_string(&path).unwrap();
+        assert!(json.contains("\"component\": \"node_exporter\""));
+        assert!(json.contains("\"kind\": \"systemd_unit\""));
+        assert!(json.contains("\"kind\": \"embedded\""));
+    }
+
+    #[test]
+    fn test_init_system_of_record() {
+        let temp_dir = TempDir::new().unwrap();
+        assert_eq!(
+            sample_record(&temp_dir).init_system(),
+            Some(InitSystem::Systemd)
+        );
+
+        let mut record =
+            ComponentRecord::new(Component::ProcessCpuAgent, None, ArtifactSource::Embedded);
+        assert_eq!(record.init_system(), None);
+        record.add_service(ServiceRecord::InitService {
+            init: InitSystem::OpenRc,
+            name: "process-cpu-agent".to_string(),
+            path: "/etc/init.d/process-cpu-agent".to_string(),
+        });
+        assert_eq!(record.init_system(), Some(InitSystem::OpenRc));
+        let json = serde_json::to_string(&record).unwrap();
+        assert!(json.contains(r#""kind":"init_service","init":"openrc""#));
+    }
+
+    #[test]
+    fn test_load_invalid_manifest() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join(MANIFEST_FILE_NAME);
+        downloader::write_file(path.to_str().unwrap(), b"not json").unwrap();
+
+        assert!(InstallManifest::load(path.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_record_replaces_existing_component() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manifest = InstallManifest::default();
+        manifest.record(sample_record(&temp_dir));
+
+        let mut newer = sample_record(&temp_dir);
+        newer.version = Some("1.8.0".to_string());
+        manifest.record(newer);
+
+        assert_eq!(manifest.components.len(), 1);
+        let record = manifest.get(Component::NodeExporter).unwrap();
+        assert_eq!(record.version.as_deref(), Some("1.8.0"));
+
+        assert!(manifest.remove(Component::NodeExporter).is_some());
+        assert!(manifest.remove(Component::NodeExporter).is_none());
+        assert!(manifest.is_empty());
+    }
+
+    #[test]
+    fn test_add_file_skips_missing() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut record =
+            ComponentRecord::new(Component::ProcessCpuAgent, None, ArtifactSource::Embedded);
+        let missing = temp_dir.path().join("missing");
+        record.add_file(missing.to_str().unwrap()).unwrap();
+        assert!(record.files.is_empty());
+    }
+
+    #[test]
+    fn test_msi_product_code() {
+        let mut record = ComponentRecord::new(
+            Component::WindowsExporter,
+            Some("0.25.1".to_string()),
+            ArtifactSource::Remote {
+                url: "https://example.com/windows_exporter.msi".to_string(),
+            },
+        );
+        assert_eq!(record.msi_product_code(), None);
+
+        record.add_service(ServiceRecord::MsiPackage {
+            product_code: Some("{1234}".to_string()),
+            installer_path: "C:\\Program Files\\prometheus\\windows_exporter.msi".to_string(),
+            properties: vec!["LISTEN_PORT=31415".to_string()],
+        });
+        assert_eq!(record.msi_product_code(), Some("{1234}"));
+        assert_eq!(
+            record.msi_properties(),
+            Some(&["LISTEN_PORT=31415".to_string()][..])
+        );
+        assert_eq!(
+            record.source.describe(),
+            "https://example.com/windows_exporter.msi"
+        );
+    }
+
+    #[test]
+    fn test_modified_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let record = sample_record(&temp_dir);
+        assert!(record.modified_files().is_empty());
+
+        let binary = temp_dir.path().join("node_exporter");
+        downloader::write_file(binary.to_str().unwrap(), b"tampered").unwrap();
+        assert_eq!(record.modified_files().len(), 1);
+
+        fs::remove_file(&binary).unwrap();
+        assert_eq!(record.modified_files().len(), 1);
+    }
+
+    #[test]
+    fn test_config_files_may_be_edited() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut record = sample_record(&temp_dir);
+        let config = temp_dir.path().join("config.toml");
+        let config = config.to_str().unwrap();
+        downloader::write_file(config, b"port = 9").unwrap();
+
+        record.add_config_file(config, b"port = 1");
+        assert_eq!(
+            record.file_sha256(config),
+            Some(downloader::sha256_hex(b"port = 1").as_str())
+        );
+        assert!(!record.file_unmodified(config));
+        assert!(record.modified_files().is_empty());
+
+        let binary = temp_dir.path().join("node_exporter");
+        assert!(record.file_unmodified(binary.to_str().unwrap()));
+        assert!(!record.file_unmodified("/nonexistent/file"));
+
+        fs::remove_file(config).unwrap();
+        assert_eq!(record.modified_files().len(), 1);
+    }
+
+    #[test]
+    fn test_default_manifest_path() {
+        assert!(default_manifest_path().ends_with(MANIFEST_FILE_NAME));
+    }
+}
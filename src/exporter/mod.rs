@@ -1,4 +1,146 @@
+pub mod account;
 pub mod downloader;
+pub mod health;
+pub mod init_system;
+pub mod listen;
+pub mod manifest;
+pub mod node_collectors;
 pub mod node_exporter;
+pub mod ports;
+pub mod preflight;
 pub mod process_exporter;
+pub mod recorder;
+pub mod registration;
+pub mod scrape;
+pub mod service;
+pub mod service_host;
+pub mod textfile;
+pub mod web_config;
+pub mod windows_config;
 pub mod windows_exporter;
+
+use crate::os_detector::{self, OsType};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// `value` as a double-quoted YAML scalar, which every JSON string is
+pub fn yaml_quote(value: &str) -> String {
+    serde_json::Value::from(value).to_string()
+}
+
+/// Install root of rootless installs, holding every component and the install manifest
+pub fn user_install_root() -> String {
+    format!("{}/prometheus-agents", os_detector::user_data_dir())
+}
+
+/// Installable components managed by this tool
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum Component {
+    #[value(name = "node_exporter", alias = "node-exporter")]
+    #[serde(rename = "node_exporter")]
+    NodeExporter,
+    #[value(name = "windows_exporter", alias = "windows-exporter")]
+    #[serde(rename = "windows_exporter")]
+    WindowsExporter,
+    #[value(name = "process-cpu-agent", alias = "process_cpu_agent")]
+    #[serde(rename = "process-cpu-agent")]
+    ProcessCpuAgent,
+}
+
+impl Component {
+    /// Name used for the component's service and on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Component::NodeExporter => "node_exporter",
+            Component::WindowsExporter => "windows_exporter",
+            Component::ProcessCpuAgent => "process-cpu-agent",
+        }
+    }
+
+    /// Human readable name used in progress output
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Component::NodeExporter => "Node Exporter",
+            Component::WindowsExporter => "Windows Exporter",
+            Component::ProcessCpuAgent => "Process CPU Agent",
+        }
+    }
+
+    /// Whether the component serves metrics with the basic auth and TLS of
+    /// `web-config.yml`; the Process CPU Agent has no such setting and
+    /// always serves plain HTTP
+    pub fn serves_web_config(&self) -> bool {
+        !matches!(self, Component::ProcessCpuAgent)
+    }
+
+    /// Components supported on the given OS, in installation order
+    pub fn available_for(os: &OsType) -> Vec<Component> {
+        match os {
+            OsType::Linux | OsType::MacOs => {
+                vec![Component::NodeExporter, Component::ProcessCpuAgent]
+            }
+            OsType::Windows => vec![Component::WindowsExporter, Component::ProcessCpuAgent],
+            OsType::Unknown => Vec::new(),
+        }
+    }
+}
+
+/// Installation state of a single component as reported by `status`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComponentStatus {
+    pub component: Component,
+    pub installed: bool,
+    /// `None` when the service manager could not be queried
+    pub running: Option<bool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_yaml_quote() {
+        assert_eq!(yaml_quote("plain"), "\"plain\"");
+        assert_eq!(
+            yaml_quote("C:\\dir \"x\": #1"),
+            "\"C:\\\\dir \\\"x\\\": #1\""
+        );
+    }
+
+    #[test]
+    fn test_component_names() {
+        assert_eq!(Component::NodeExporter.name(), "node_exporter");
+        assert_eq!(Component::WindowsExporter.name(), "windows_exporter");
+        assert_eq!(Component::ProcessCpuAgent.name(), "process-cpu-agent");
+    }
+
+    #[test]
+    fn test_component_parsing_accepts_both_separators() {
+        assert_eq!(
+            Component::from_str("node_exporter", true),
+            Ok(Component::NodeExporter)
+        );
+        assert_eq!(
+            Component::from_str("node-exporter", true),
+            Ok(Component::NodeExporter)
+        );
+        assert_eq!(
+            Component::from_str("process_cpu_agent", true),
+            Ok(Component::ProcessCpuAgent)
+        );
+        assert!(Component::from_str("blackbox_exporter", true).is_err());
+    }
+
+    #[test]
+    fn test_available_components_per_os() {
+        assert_eq!(
+            Component::available_for(&OsType::Linux),
+            vec![Component::NodeExporter, Component::ProcessCpuAgent]
+        );
+        assert_eq!(
+            Component::available_for(&OsType::Windows),
+            vec![Component::WindowsExporter, Component::ProcessCpuAgent]
+        );
+        assert!(Component::available_for(&OsType::Unknown).is_empty());
+    }
+}
@@ -0,0 +1,275 @@
+use crate::exporter::node_exporter::NODE_EXPORTER_VERSION;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+
+/// Linux and macOS collectors of node_exporter 1.7.0, the bundled version
+const KNOWN_COLLECTORS: &[&str] = &[
+    "arp",
+    "bcache",
+    "bonding",
+    "boottime",
+    "btrfs",
+    "buddyinfo",
+    "cgroups",
+    "conntrack",
+    "cpu",
+    "cpu_vulnerabilities",
+    "cpufreq",
+    "diskstats",
+    "dmi",
+    "drbd",
+    "drm",
+    "edac",
+    "entropy",
+    "ethtool",
+    "fibrechannel",
+    "filefd",
+    "filesystem",
+    "hwmon",
+    "infiniband",
+    "interrupts",
+    "ipvs",
+    "ksmd",
+    "lnstat",
+    "loadavg",
+    "logind",
+    "mdadm",
+    "meminfo",
+    "meminfo_numa",
+    "mountstats",
+    "netclass",
+    "netdev",
+    "netstat",
+    "network_route",
+    "nfs",
+    "nfsd",
+    "ntp",
+    "nvme",
+    "os",
+    "perf",
+    "powersupplyclass",
+    "pressure",
+    "processes",
+    "qdisc",
+    "rapl",
+    "runit",
+    "schedstat",
+    "selinux",
+    "slabinfo",
+    "sockstat",
+    "softirqs",
+    "softnet",
+    "stat",
+    "supervisord",
+    "sysctl",
+    "systemd",
+    "tapestats",
+    "tcpstat",
+    "textfile",
+    "thermal",
+    "thermal_zone",
+    "time",
+    "timex",
+    "udp_queues",
+    "uname",
+    "vmstat",
+    "wifi",
+    "xfs",
+    "zfs",
+    "zoneinfo",
+];
+
+/// Collectors node_exporter runs on top of or instead of its defaults, as
+/// set in `[node_exporter.collectors]`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CollectorProfile {
+    /// Run only the collectors in `enable`
+    pub disable_defaults: bool,
+    pub enable: Vec<String>,
+    pub disable: Vec<String>,
+    /// Collector flags without the `--collector.` prefix, such as
+    /// `filesystem.mount-points-exclude`
+    pub flags: BTreeMap<String, String>,
+}
+
+impl CollectorProfile {
+    /// Whether node_exporter runs the default collector `name` with this profile
+    pub fn runs_default(&self, name: &str) -> bool {
+        let listed = |names: &[String]| names.iter().any(|listed| listed == name);
+        !listed(&self.disable) && (!self.disable_defaults || listed(&self.enable))
+    }
+
+    /// node_exporter command line arguments selecting and configuring the collectors
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.disable_defaults {
+            args.push("--collector.disable-defaults".to_string());
+        }
+        args.extend(self.enable.iter().map(|name| format!("--collector.{name}")));
+        args.extend(
+            self.disable
+                .iter()
+                .map(|name| format!("--no-collector.{name}")),
+        );
+        args.extend(
+            self.flags
+                .iter()
+                .map(|(flag, value)| format!("--collector.{flag}={value}")),
+        );
+        args
+    }
+
+    /// Problems with the profile, each prefixed with `key`. Collector names
+    /// are only checked against the known ones for the bundled `version`.
+    pub fn validate(&self, key: &str, version: &str) -> Vec<String> {
+        let known = (version == NODE_EXPORTER_VERSION).then_some(KNOWN_COLLECTORS);
+        let mut errors = Vec::new();
+        let mut names: Vec<(&str, &str)> = Vec::new();
+        names.extend(self.enable.iter().map(|name| ("enable", name.as_str())));
+        names.extend(self.disable.iter().map(|name| ("disable", name.as_str())));
+        for flag in self.flags.keys() {
+            match flag.split_once('.') {
+                Some((name, option)) if is_flag_name(option) => names.push(("flags", name)),
+                _ => errors.push(format!(
+                    "{key}.flags contains {flag:?}, which is not a collector.option flag"
+                )),
+            }
+        }
+        for (list, name) in names {
+            if !is_collector_name(name) {
+                errors.push(format!(
+                    "{key}.{list} contains an invalid collector name {name:?}"
+                ));
+            } else if known.is_some_and(|known| !known.contains(&name)) {
+                errors.push(format!(
+                    "{key}.{list} contains {name:?}, which node_exporter {version} does not have"
+                ));
+            }
+        }
+
+        let mut seen = HashSet::new();
+        for name in self.enable.iter().chain(&self.disable) {
+            if !seen.insert(name) {
+                errors.push(format!(
+                    "{key} lists {name:?} more than once in enable and disable"
+                ));
+            }
+        }
+        for (flag, value) in &self.flags {
+            if let Some((name, _)) = flag.split_once('.')
+                && self.disable.iter().any(|disabled| disabled == name)
+            {
+                errors.push(format!("{key}.flags sets {flag:?} of a disabled collector"));
+            }
+            if value.chars().any(char::is_control) {
+                errors.push(format!(
+                    "{key}.flags.{flag:?} must not contain control characters"
+                ));
+            }
+        }
+        if self.disable_defaults && self.enable.is_empty() {
+            errors.push(format!(
+                "{key}.disable_defaults requires collectors in {key}.enable"
+            ));
+        }
+        errors
+    }
+}
+
+fn is_collector_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+fn is_flag_name(option: &str) -> bool {
+    !option.is_empty()
+        && option
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '_' | '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile() -> CollectorProfile {
+        CollectorProfile {
+            disable_defaults: false,
+            enable: vec!["systemd".to_string(), "processes".to_string()],
+            disable: vec!["wifi".to_string(), "infiniband".to_string()],
+            flags: BTreeMap::from([(
+                "filesystem.mount-points-exclude".to_string(),
+                "^/(dev|proc|sys)($|/)".to_string(),
+            )]),
+        }
+    }
+
+    #[test]
+    fn test_args() {
+        assert!(CollectorProfile::default().args().is_empty());
+        assert_eq!(
+            profile().args(),
+            vec![
+                "--collector.systemd",
+                "--collector.processes",
+                "--no-collector.wifi",
+                "--no-collector.infiniband",
+                "--collector.filesystem.mount-points-exclude=^/(dev|proc|sys)($|/)",
+            ]
+        );
+
+        let only = CollectorProfile {
+            disable_defaults: true,
+            enable: vec!["cpu".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            only.args(),
+            vec!["--collector.disable-defaults", "--collector.cpu"]
+        );
+        assert!(only.runs_default("cpu"));
+        assert!(!only.runs_default("textfile"));
+        assert!(!profile().runs_default("wifi"));
+        assert!(profile().runs_default("textfile"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let key = "node_exporter.collectors";
+        assert!(profile().validate(key, NODE_EXPORTER_VERSION).is_empty());
+
+        let invalid = CollectorProfile {
+            disable_defaults: true,
+            enable: vec!["systemd".to_string(), "gpu".to_string()],
+            disable: vec!["systemd".to_string(), "Wifi".to_string()],
+            flags: BTreeMap::from([
+                (
+                    "systemd.unit-include".to_string(),
+                    "ssh\\.service".to_string(),
+                ),
+                ("textfile".to_string(), "/tmp".to_string()),
+            ]),
+        };
+        assert_eq!(
+            invalid.validate(key, NODE_EXPORTER_VERSION),
+            vec![
+                "node_exporter.collectors.flags contains \"textfile\", which is not a collector.option flag",
+                "node_exporter.collectors.enable contains \"gpu\", which node_exporter 1.7.0 does not have",
+                "node_exporter.collectors.disable contains an invalid collector name \"Wifi\"",
+                "node_exporter.collectors lists \"systemd\" more than once in enable and disable",
+                "node_exporter.collectors.flags sets \"systemd.unit-include\" of a disabled collector",
+            ]
+        );
+
+        // Other versions may have other collectors
+        let newer = CollectorProfile {
+            enable: vec!["gpu".to_string()],
+            ..Default::default()
+        };
+        assert!(newer.validate(key, "1.9.0").is_empty());
+        assert_eq!(newer.validate(key, NODE_EXPORTER_VERSION).len(), 1);
+    }
+}
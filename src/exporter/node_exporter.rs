@@ -1,21 +1,43 @@
-use crate::exporter::downloader;
-use std::path::Path;
-use std::process::Command;
-
-const NODE_EXPORTER_VERSION: &str = "1.7.0";
-const NODE_EXPORTER_PORT: u16 = 31415;
-
-#[cfg(target_os = "linux")]
+use crate::config::NodeExporterSettings;
+use crate::exporter::init_system::{self, InitSystem, ServiceDefinition};
+use crate::exporter::manifest::{ArtifactSource, ComponentRecord};
+use crate::exporter::node_collectors::CollectorProfile;
+use crate::exporter::preflight::Requirements;
+use crate::exporter::recorder::progress;
+use crate::exporter::service::HardeningProfile;
+use crate::exporter::textfile::{self, TextfileProducer};
+use crate::exporter::web_config::WebConfig;
+use crate::exporter::{Component, ComponentStatus, account, downloader, listen, user_install_root};
+use std::net::IpAddr;
+
+pub const NODE_EXPORTER_VERSION: &str = "1.7.0";
+pub const NODE_EXPORTER_PORT: u16 = 31415;
+const NODE_EXPORTER_HARDENING: HardeningProfile = HardeningProfile::Strict;
+const SERVICE_NAME: &str = "node_exporter";
+/// Collector flag setting the directory read by the textfile collector
+const TEXTFILE_DIRECTORY_FLAG: &str = "textfile.directory";
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
 const EMBEDDED_NODE_EXPORTER_ARCHIVE: Option<&[u8]> = Some(include_bytes!(concat!(
     env!("OUT_DIR"),
     "/node_exporter.tar.gz"
 )));
-#[cfg(not(target_os = "linux"))]
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
 const EMBEDDED_NODE_EXPORTER_ARCHIVE: Option<&[u8]> = None;
 
 pub struct NodeExporterSetup {
     version: String,
     install_path: String,
+    port: u16,
+    listen_address: Option<IpAddr>,
+    os: &'static str,
+    arch: Option<&'static str>,
+    hardening: HardeningProfile,
+    init_system: Option<InitSystem>,
+    web_config: Option<WebConfig>,
+    collectors: CollectorProfile,
+    textfile_directory: String,
+    textfile_producers: Vec<TextfileProducer>,
 }
 
 impl NodeExporterSetup {
@@ -23,36 +45,294 @@ impl NodeExporterSetup {
         Self {
             version: NODE_EXPORTER_VERSION.to_string(),
             install_path: "/opt/prometheus".to_string(),
+            port: NODE_EXPORTER_PORT,
+            listen_address: None,
+            os: release_os(),
+            arch: get_node_exporter_arch(),
+            hardening: NODE_EXPORTER_HARDENING,
+            init_system: InitSystem::detect(),
+            web_config: None,
+            collectors: CollectorProfile::default(),
+            textfile_directory: textfile::DEFAULT_TEXTFILE_DIR.to_string(),
+            textfile_producers: Vec::new(),
+        }
+    }
+
+    /// Override the defaults with values from the installer config
+    pub fn with_settings(mut self, settings: &NodeExporterSettings) -> Self {
+        if let Some(version) = &settings.version {
+            self.version = version.clone();
         }
+        let rootless = self.init_system == Some(InitSystem::SystemdUser);
+        match &settings.install_path {
+            Some(install_path) => self.install_path = install_path.clone(),
+            // A rootless install cannot write to /opt, so keep it in the user's home
+            None if rootless => self.install_path = user_install_root(),
+            None => {}
+        }
+        match &settings.textfile_directory {
+            Some(dir) => self.textfile_directory = dir.clone(),
+            None if rootless => {
+                self.textfile_directory = format!("{}/textfile_collector", user_install_root());
+            }
+            None => {}
+        }
+        if let Some(producers) = &settings.textfile_producers {
+            self.textfile_producers = producers.clone();
+        }
+        if let Some(port) = settings.port {
+            self.port = port;
+        }
+        if let Some(hardening) = settings.hardening {
+            self.hardening = hardening;
+        }
+        if let Some(collectors) = &settings.collectors {
+            self.collectors = collectors.clone();
+        }
+        self
+    }
+
+    /// Register the service with `init` instead of the detected init system.
+    /// Call before `with_settings` so systemd --user installs into the user's home.
+    pub fn with_init_system(mut self, init: InitSystem) -> Self {
+        self.init_system = Some(init);
+        self
+    }
+
+    pub fn init_system(&self) -> Option<InitSystem> {
+        self.init_system
+    }
+
+    /// Listen on `port` instead, such as a free port replacing a taken one
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Listen on `ip` only, or on every interface with `None`
+    pub fn with_listen_address(mut self, ip: Option<IpAddr>) -> Self {
+        self.listen_address = ip;
+        self
+    }
+
+    pub fn listen_address(&self) -> Option<IpAddr> {
+        self.listen_address
+    }
+
+    /// Protect the metrics endpoint with basic auth and TLS
+    pub fn with_web_config(mut self, web_config: Option<WebConfig>) -> Self {
+        self.web_config = web_config;
+        self
     }
 
     pub fn download_url(&self, arch: &str) -> String {
-        format!(
-            "https://github.com/prometheus/node_exporter/releases/download/v{}/node_exporter-{}.linux-{}.tar.gz",
-            self.version, self.version, arch
-        )
+        generate_download_url(&self.version, self.os, arch)
     }
 
-    pub fn setup(&self) -> Result<(), Box<dyn std::error::Error>> {
-        println!("Setting up Node Exporter v{}", self.version);
+    /// Install or repair Node Exporter. A binary that still matches the
+    /// `previous` install is not extracted again and the service is only
+    /// restarted when something changed.
+    /// Returns `true` if anything on the system was changed.
+    pub fn setup(
+        &self,
+        previous: Option<&ComponentRecord>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        progress!("Setting up Node Exporter v{}", self.version);
+
+        let arch = self.arch()?;
+        let binary_path = self.binary_path(arch);
 
-        let arch = if crate::os_detector::is_64bit() {
-            "amd64"
+        self.create_directories()?;
+        let extracted = if previous.is_some_and(|r| r.file_unmodified(&binary_path)) {
+            progress!("Node Exporter binary is up to date: {binary_path}");
+            false
         } else {
-            "386"
+            self.download_and_extract(arch)?;
+            true
         };
+        let service_changed = self.create_service(arch, extracted)?;
 
-        self.create_directories()?;
-        self.download_and_extract(arch)?;
-        self.create_systemd_service(arch)?;
+        Ok(extracted || service_changed)
+    }
+
+    /// Where the archive comes from
+    pub fn source(&self) -> ArtifactSource {
+        if self.version == NODE_EXPORTER_VERSION && EMBEDDED_NODE_EXPORTER_ARCHIVE.is_some() {
+            ArtifactSource::Embedded
+        } else {
+            let url = match self.arch() {
+                Ok(arch) => self.download_url(arch),
+                Err(_) => release_base_url(&self.version),
+            };
+            ArtifactSource::Remote { url }
+        }
+    }
+
+    /// Describe what `setup` installed, for the install manifest
+    pub fn manifest_record(&self) -> Result<ComponentRecord, Box<dyn std::error::Error>> {
+        let mut record = ComponentRecord::new(
+            Component::NodeExporter,
+            Some(self.version.clone()),
+            self.source(),
+        );
+        record.add_file(&self.binary_path(self.arch()?))?;
+
+        if let Some(init) = self.init_system {
+            let definition_path = init.definition_path(SERVICE_NAME);
+            if downloader::path_exists(&definition_path) {
+                record.add_file(&definition_path)?;
+                record.add_service(init.service_record(SERVICE_NAME));
+            }
+        }
+        Ok(record)
+    }
+
+    /// What `setup` needs from the host, for the preflight check
+    pub fn requirements(&self) -> Requirements {
+        let mut paths = vec![self.install_path.clone()];
+        paths.extend(self.textfile_dir());
+        let mut tools = Vec::new();
+        let mut unsupported = Vec::new();
+        if let Some(init) = self.init_system {
+            paths.extend(downloader::get_parent_directory(
+                &init.definition_path(SERVICE_NAME),
+            ));
+            tools = init.required_tools();
+            if init.systemd_scope().is_some() {
+                tools.extend(
+                    self.textfile_producers
+                        .iter()
+                        .filter_map(|producer| producer.required_tool()),
+                );
+            }
+            if init == InitSystem::SystemdUser {
+                unsupported.extend(
+                    self.textfile_producers
+                        .iter()
+                        .filter(|producer| producer.needs_root())
+                        .map(|producer| {
+                            format!(
+                                "textfile producer {} needs root, which systemd --user services do not have",
+                                producer.name()
+                            )
+                        }),
+                );
+            }
+        }
+        Requirements {
+            privileged: self.init_system.is_none_or(InitSystem::needs_root),
+            unsupported,
+            ..Requirements::privileged(paths, tools)
+        }
+    }
+
+    pub fn status(&self) -> ComponentStatus {
+        ComponentStatus {
+            component: Component::NodeExporter,
+            installed: self
+                .arch()
+                .is_ok_and(|arch| downloader::path_exists(&self.binary_path(arch))),
+            running: self
+                .init_system
+                .and_then(|init| init_system::service_running(init, SERVICE_NAME)),
+        }
+    }
 
-        Ok(())
+    /// Stop and remove the service and files created by `setup`. The service
+    /// is removed from the init system it was `recorded` with, if known.
+    /// Returns a description of everything that was removed.
+    pub fn uninstall(
+        &self,
+        recorded: Option<InitSystem>,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let init = recorded.or(self.init_system);
+        let mut removed = match init {
+            Some(init) => init_system::remove_service(init, SERVICE_NAME)?,
+            None => Vec::new(),
+        };
+        let textfile_dir = self.textfile_output_dir();
+        if let Some(scope) = init.and_then(InitSystem::systemd_scope) {
+            removed.extend(textfile::remove_producers(
+                scope,
+                &self.producer_dir(),
+                &textfile_dir,
+            )?);
+        }
+        removed.extend(self.remove_files()?);
+        // Other jobs may still write metrics there
+        if downloader::remove_dir_if_empty(&textfile_dir) {
+            removed.push(textfile_dir);
+        }
+        Ok(removed)
+    }
+
+    fn remove_files(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut removed = Vec::new();
+        let extract_path = format!("{}/node_exporter", self.install_path);
+        if downloader::remove_path(&extract_path)? {
+            removed.push(extract_path);
+        }
+        if downloader::remove_dir_if_empty(&self.install_path) {
+            removed.push(self.install_path.clone());
+        }
+        Ok(removed)
+    }
+
+    fn arch(&self) -> Result<&'static str, String> {
+        self.arch.ok_or_else(|| {
+            format!(
+                "node_exporter is not published for the {} architecture",
+                crate::os_detector::get_arch()
+            )
+        })
+    }
+
+    fn binary_path(&self, arch: &str) -> String {
+        generate_binary_path(&self.install_path, &self.version, self.os, arch)
     }
 
     fn create_directories(&self) -> Result<(), Box<dyn std::error::Error>> {
         downloader::ensure_directory_exists(&self.install_path)
     }
 
+    /// Removed together with the extracted archive on uninstall
+    fn web_config_dir(&self) -> String {
+        format!("{}/node_exporter", self.install_path)
+    }
+
+    /// Where the textfile producer scripts are installed, also removed on uninstall
+    fn producer_dir(&self) -> String {
+        format!("{}/node_exporter/textfile-producers", self.install_path)
+    }
+
+    /// Directory the textfile collector reads and the producers write to,
+    /// a `textfile.directory` collector flag taking precedence over the setting
+    fn textfile_output_dir(&self) -> String {
+        self.collectors
+            .flags
+            .get(TEXTFILE_DIRECTORY_FLAG)
+            .unwrap_or(&self.textfile_directory)
+            .clone()
+    }
+
+    /// Directory read by the textfile collector, `None` when it is disabled
+    fn textfile_dir(&self) -> Option<String> {
+        self.collectors
+            .runs_default("textfile")
+            .then(|| self.textfile_output_dir())
+    }
+
+    /// Directory to pass with `--collector.textfile.directory`, `None` when
+    /// the collector flags already pass it
+    fn textfile_dir_arg(&self) -> Option<String> {
+        self.textfile_dir()
+            .filter(|_| !self.collectors.flags.contains_key(TEXTFILE_DIRECTORY_FLAG))
+    }
+
     fn download_and_extract(&self, arch: &str) -> Result<(), Box<dyn std::error::Error>> {
         let extract_path = format!("{}/node_exporter", self.install_path);
 
@@ -63,128 +343,192 @@ impl NodeExporterSetup {
             return Ok(());
         }
 
-        let url = self.download_url(arch);
-        downloader::download_and_extract_tar_gz(&url, &extract_path)?;
-
-        Ok(())
+        download_verified_archive(&self.version, self.os, arch, &extract_path)
     }
 
-    fn create_systemd_service(&self, arch: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let service_content = create_systemd_service_content(
-            &self.install_path,
-            &self.version,
-            arch,
-            NODE_EXPORTER_PORT,
-        );
-        let service_path = "/etc/systemd/system/node_exporter.service";
-
-        if Path::new("/etc/systemd/system").exists() {
-            downloader::write_file(service_path, service_content.as_bytes())?;
-            println!("Systemd service created at: {service_path}");
-
-            Command::new("systemctl").args(["daemon-reload"]).output()?;
-            // 자동으로 서비스 활성화 및 시작까지 시도
-            Command::new("systemctl")
-                .args(["enable", "--now", "node_exporter"])
-                .output()?;
-            println!("Node Exporter service enabled and started");
-        } else {
-            println!("Systemd not found. Please manually configure the service.");
+    /// Returns `true` when the service definition was written or the service (re)started
+    fn create_service(
+        &self,
+        arch: &str,
+        binary_changed: bool,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let Some(init) = self.init_system else {
+            progress!("No supported init system found. Please manually configure the service.");
+            return Ok(false);
+        };
+
+        // macOS has no prometheus account; node_exporter needs no privileges there
+        let mut web_changed = false;
+        let (user, account_created) = match init {
+            InitSystem::Launchd => (init_system::LAUNCHD_UNPRIVILEGED_USER, false),
+            // The user manager runs it as the installing user
+            InitSystem::SystemdUser => (account::SERVICE_ACCOUNT, false),
+            _ => (
+                account::SERVICE_ACCOUNT,
+                account::ensure_system_account(account::SERVICE_ACCOUNT)?,
+            ),
+        };
+        let web_config_file = match &self.web_config {
+            // Written once the account exists, since its group may read the TLS key
+            Some(web_config) => {
+                let group = (init != InitSystem::SystemdUser).then_some(user);
+                web_changed = web_config.write(&self.web_config_dir(), group)?;
+                Some(WebConfig::path(&self.web_config_dir()))
+            }
+            None => None,
+        };
+        if let Some(dir) = self.textfile_dir() {
+            textfile::ensure_directory(&dir)?;
+        }
+        let definition = ServiceDefinition {
+            user,
+            ..service_definition(
+                &self.install_path,
+                &self.version,
+                self.os,
+                arch,
+                self.hardening,
+                service_args(
+                    &listen::bind_address(self.listen_address, self.port),
+                    web_config_file.as_deref(),
+                    self.textfile_dir_arg().as_deref(),
+                    &self.collectors,
+                ),
+            )
+        };
+        // 자동으로 서비스 활성화 및 시작까지 시도
+        let changed = init_system::install_service(
+            init,
+            &definition,
+            binary_changed || account_created || web_changed,
+        )?;
+        if changed {
+            progress!("Node Exporter service enabled and started");
         }
 
-        Ok(())
+        let producers_changed = match init.systemd_scope() {
+            Some(scope) => textfile::install_producers(
+                &self.textfile_producers,
+                scope,
+                &self.producer_dir(),
+                &self.textfile_output_dir(),
+            )?,
+            None => {
+                if !self.textfile_producers.is_empty() {
+                    progress!(
+                        "Textfile producers run on systemd timers; skipped with {}",
+                        init.name()
+                    );
+                }
+                false
+            }
+        };
+
+        Ok(changed || producers_changed)
     }
 }
 
-/// Create systemd service content for Node Exporter
-pub fn create_systemd_service_content(
+/// Service running Node Exporter from the extracted release archive with `args`
+pub fn service_definition(
     install_path: &str,
     version: &str,
+    os: &str,
     arch: &str,
-    port: u16,
-) -> String {
-    format!(
-        r#"[Unit]
-Description=Prometheus Node Exporter
-After=network.target
-
-[Service]
-Type=simple
-ExecStart={install_path}/node_exporter/node_exporter-{version}.linux-{arch}/node_exporter --web.listen-address=:{port}
-Restart=always
-RestartSec=10
-
-[Install]
-WantedBy=multi-user.target
-"#
-    )
+    hardening: HardeningProfile,
+    args: Vec<String>,
+) -> ServiceDefinition {
+    ServiceDefinition {
+        name: SERVICE_NAME,
+        description: "Prometheus Node Exporter",
+        command: generate_binary_path(install_path, version, os, arch),
+        args,
+        user: account::SERVICE_ACCOUNT,
+        hardening,
+        read_only_paths: vec![format!("{install_path}/node_exporter")],
+        working_directory: None,
+    }
+}
+
+/// Arguments listening on `bind_address` such as `:9100`, serving metrics as
+/// set up in `web_config_file` and running the `collectors`, with the
+/// textfile collector reading `textfile_dir`
+pub fn service_args(
+    bind_address: &str,
+    web_config_file: Option<&str>,
+    textfile_dir: Option<&str>,
+    collectors: &CollectorProfile,
+) -> Vec<String> {
+    let mut args = vec![format!("--web.listen-address={bind_address}")];
+    args.extend(web_config_file.map(|file| format!("--web.config.file={file}")));
+    args.extend(textfile_dir.map(|dir| format!("--collector.textfile.directory={dir}")));
+    args.extend(collectors.args());
+    args
+}
+
+/// Path of the node_exporter binary inside the extracted release archive
+pub fn generate_binary_path(install_path: &str, version: &str, os: &str, arch: &str) -> String {
+    format!("{install_path}/node_exporter/node_exporter-{version}.{os}-{arch}/node_exporter")
 }
 
 /// Generate download URL for Node Exporter
-pub fn generate_download_url(version: &str, arch: &str) -> String {
+pub fn generate_download_url(version: &str, os: &str, arch: &str) -> String {
     format!(
-        "https://github.com/prometheus/node_exporter/releases/download/v{version}/node_exporter-{version}.linux-{arch}.tar.gz"
+        "{}/{}",
+        release_base_url(version),
+        generate_archive_name(version, os, arch)
     )
 }
 
-/// Get architecture string for Node Exporter
-pub fn get_node_exporter_arch() -> &'static str {
-    match std::env::consts::ARCH {
-        "x86_64" => "amd64",
-        "aarch64" => "arm64",
-        "arm" | "armv7l" => "armv7",
-        "i686" | "i586" | "x86" => "386",
-        _ => {
-            if crate::os_detector::is_64bit() {
-                "amd64"
-            } else {
-                "386"
-            }
-        }
-    }
+/// Generate the URL of the upstream `sha256sums.txt` for a release
+pub fn generate_checksums_url(version: &str) -> String {
+    format!("{}/sha256sums.txt", release_base_url(version))
 }
 
-/// Setup Node Exporter with custom parameters
-pub fn setup_node_exporter(
-    version: &str,
-    install_path: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Setting up Node Exporter v{version}");
+/// Generate the release archive file name for Node Exporter
+pub fn generate_archive_name(version: &str, os: &str, arch: &str) -> String {
+    format!("node_exporter-{version}.{os}-{arch}.tar.gz")
+}
 
-    let arch = get_node_exporter_arch();
-    let extract_path = format!("{install_path}/node_exporter");
+fn release_base_url(version: &str) -> String {
+    format!("https://github.com/prometheus/node_exporter/releases/download/v{version}")
+}
 
-    // Create directories
-    downloader::ensure_directory_exists(install_path)?;
+/// Download a release archive, verify it against the upstream
+/// `sha256sums.txt` and extract it
+fn download_verified_archive(
+    version: &str,
+    os: &str,
+    arch: &str,
+    extract_path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let expected = downloader::fetch_sha256_from_sums(
+        &generate_checksums_url(version),
+        &generate_archive_name(version, os, arch),
+    )?;
+    downloader::download_and_extract_tar_gz(
+        &generate_download_url(version, os, arch),
+        extract_path,
+        &expected,
+    )
+}
 
-    if version == NODE_EXPORTER_VERSION {
-        if let Some(bytes) = EMBEDDED_NODE_EXPORTER_ARCHIVE {
-            downloader::extract_tar_gz(bytes, &extract_path)?;
-        } else {
-            let url = generate_download_url(version, arch);
-            downloader::download_and_extract_tar_gz(&url, &extract_path)?;
-        }
+/// OS name used in release archive names for the platform this binary was built for
+pub fn release_os() -> &'static str {
+    if cfg!(target_os = "macos") {
+        "darwin"
     } else {
-        let url = generate_download_url(version, arch);
-        downloader::download_and_extract_tar_gz(&url, &extract_path)?;
+        "linux"
     }
+}
 
-    // Create systemd service
-    let service_content =
-        create_systemd_service_content(install_path, version, arch, NODE_EXPORTER_PORT);
-    let service_path = "/etc/systemd/system/node_exporter.service";
-
-    if Path::new("/etc/systemd/system").exists() {
-        downloader::write_file(service_path, service_content.as_bytes())?;
-        println!("Systemd service created at: {service_path}");
-
-        Command::new("systemctl").args(["daemon-reload"]).output()?;
-        println!("Run 'systemctl enable --now node_exporter' to start the service");
+/// Get architecture string for Node Exporter, `None` if upstream has no build for it
+pub fn get_node_exporter_arch() -> Option<&'static str> {
+    let arch = crate::os_detector::detect_arch()?;
+    if cfg!(target_os = "macos") {
+        arch.darwin_name()
     } else {
-        println!("Systemd not found. Please manually configure the service.");
+        Some(arch.node_exporter_name())
     }
-
-    Ok(())
 }
 
 #[cfg(test)]
@@ -192,6 +536,77 @@ mod tests {
     use super::*;
     use tempfile::TempDir;
 
+    /// systemd unit of the default collectors, as `setup` renders it
+    fn systemd_unit(
+        install_path: &str,
+        version: &str,
+        arch: &str,
+        port: u16,
+        listen_address: Option<IpAddr>,
+        hardening: HardeningProfile,
+        web_config_file: Option<&str>,
+    ) -> String {
+        service_definition(
+            install_path,
+            version,
+            "linux",
+            arch,
+            hardening,
+            service_args(
+                &listen::bind_address(listen_address, port),
+                web_config_file,
+                None,
+                &CollectorProfile::default(),
+            ),
+        )
+        .render(InitSystem::Systemd)
+    }
+
+    #[test]
+    fn test_with_settings() {
+        let settings = NodeExporterSettings {
+            version: Some("1.8.2".to_string()),
+            port: Some(9100),
+            ..Default::default()
+        };
+        let setup = NodeExporterSetup::new().with_settings(&settings);
+        assert_eq!(setup.version, "1.8.2");
+        assert_eq!(setup.port(), 9100);
+        assert_eq!(setup.install_path, "/opt/prometheus");
+        assert_eq!(
+            setup.source(),
+            ArtifactSource::Remote {
+                url: generate_download_url("1.8.2", "linux", setup.arch().unwrap())
+            }
+        );
+    }
+
+    #[test]
+    fn test_systemd_user_installs_into_home() {
+        let setup = NodeExporterSetup::new()
+            .with_init_system(InitSystem::SystemdUser)
+            .with_settings(&NodeExporterSettings::default());
+        assert_eq!(setup.install_path, user_install_root());
+        let requirements = setup.requirements();
+        assert!(!requirements.privileged);
+        assert_eq!(requirements.writable_paths[0], user_install_root());
+
+        let settings = NodeExporterSettings {
+            install_path: Some("/srv/prometheus".to_string()),
+            ..Default::default()
+        };
+        let setup = NodeExporterSetup::new()
+            .with_init_system(InitSystem::SystemdUser)
+            .with_settings(&settings);
+        assert_eq!(setup.install_path, "/srv/prometheus");
+        assert!(
+            NodeExporterSetup::new()
+                .with_init_system(InitSystem::Systemd)
+                .requirements()
+                .privileged
+        );
+    }
+
     #[test]
     fn test_node_exporter_creation() {
         let setup = NodeExporterSetup::new();
@@ -215,26 +630,92 @@ mod tests {
 
     #[test]
     fn test_generate_download_url_function() {
-        let url = generate_download_url("1.7.0", "amd64");
+        let url = generate_download_url("1.7.0", "linux", "amd64");
         assert!(url.contains("node_exporter-1.7.0.linux-amd64.tar.gz"));
         assert!(url.starts_with("https://github.com/prometheus/node_exporter/releases/download/"));
     }
 
+    #[test]
+    fn test_checksum_urls() {
+        assert_eq!(
+            generate_checksums_url("1.7.0"),
+            "https://github.com/prometheus/node_exporter/releases/download/v1.7.0/sha256sums.txt"
+        );
+        assert_eq!(
+            generate_archive_name("1.7.0", "linux", "arm64"),
+            "node_exporter-1.7.0.linux-arm64.tar.gz"
+        );
+        assert!(
+            generate_download_url("1.7.0", "linux", "arm64")
+                .ends_with(&generate_archive_name("1.7.0", "linux", "arm64"))
+        );
+    }
+
+    #[test]
+    fn test_darwin_archive_names() {
+        assert_eq!(
+            generate_archive_name("1.7.0", "darwin", "arm64"),
+            "node_exporter-1.7.0.darwin-arm64.tar.gz"
+        );
+        assert_eq!(
+            generate_download_url("1.7.0", "darwin", "amd64"),
+            "https://github.com/prometheus/node_exporter/releases/download/v1.7.0/\
+             node_exporter-1.7.0.darwin-amd64.tar.gz"
+        );
+        assert_eq!(
+            generate_binary_path("/opt/prometheus", "1.7.0", "darwin", "arm64"),
+            "/opt/prometheus/node_exporter/node_exporter-1.7.0.darwin-arm64/node_exporter"
+        );
+    }
+
     #[test]
     fn test_arch_selection() {
         let arch = get_node_exporter_arch();
 
         match std::env::consts::ARCH {
-            "x86_64" => assert_eq!(arch, "amd64"),
-            "aarch64" => assert_eq!(arch, "arm64"),
-            "arm" | "armv7l" => assert_eq!(arch, "armv7"),
-            _ => {
-                if crate::os_detector::is_64bit() {
-                    assert_eq!(arch, "amd64");
-                } else {
-                    assert_eq!(arch, "386");
-                }
-            }
+            "x86_64" => assert_eq!(arch, Some("amd64")),
+            "aarch64" => assert_eq!(arch, Some("arm64")),
+            "x86" => assert_eq!(arch, Some("386")),
+            "s390x" => assert_eq!(arch, Some("s390x")),
+            "riscv64" => assert_eq!(arch, Some("riscv64")),
+            _ => {}
+        }
+        assert_eq!(NodeExporterSetup::new().arch().ok(), arch);
+    }
+
+    #[test]
+    fn test_unsupported_arch_is_reported() {
+        let mut setup = NodeExporterSetup::new();
+        setup.arch = None;
+        assert!(
+            setup
+                .setup(None)
+                .unwrap_err()
+                .to_string()
+                .contains("not published")
+        );
+        assert!(!setup.status().installed);
+    }
+
+    #[test]
+    fn test_exec_start_matches_extracted_binary() {
+        for arch in ["arm64", "armv7", "ppc64le", "s390x", "riscv64", "mips64le"] {
+            let content = systemd_unit(
+                "/opt/prometheus",
+                "1.7.0",
+                arch,
+                31415,
+                None,
+                HardeningProfile::Strict,
+                None,
+            );
+            let binary = generate_binary_path("/opt/prometheus", "1.7.0", "linux", arch);
+            assert!(content.contains(&format!("ExecStart={binary} ")));
+            assert!(binary.contains(&format!("node_exporter-1.7.0.linux-{arch}/node_exporter")));
+            assert!(
+                generate_archive_name("1.7.0", "linux", arch)
+                    .starts_with(&format!("node_exporter-1.7.0.linux-{arch}"))
+            );
         }
     }
 
@@ -251,6 +732,45 @@ mod tests {
         assert!(test_path.exists());
     }
 
+    #[test]
+    fn test_manifest_record() {
+        let temp_dir = TempDir::new().unwrap();
+        let test_path = temp_dir.path().join("test_prometheus");
+
+        let mut setup = NodeExporterSetup::new();
+        setup.install_path = test_path.to_str().unwrap().to_string();
+        let binary = setup.binary_path(setup.arch().unwrap());
+        downloader::ensure_directory_exists(&downloader::get_parent_directory(&binary).unwrap())
+            .unwrap();
+        downloader::write_file(&binary, b"binary").unwrap();
+
+        let record = setup.manifest_record().unwrap();
+        assert_eq!(record.component, Component::NodeExporter);
+        assert_eq!(record.version.as_deref(), Some("1.7.0"));
+        assert_eq!(record.files[0].path, binary);
+        assert_eq!(record.files[0].sha256, downloader::sha256_hex(b"binary"));
+    }
+
+    #[test]
+    fn test_remove_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let test_path = temp_dir.path().join("test_prometheus");
+
+        let mut setup = NodeExporterSetup::new();
+        setup.install_path = test_path.to_str().unwrap().to_string();
+        let binary = setup.binary_path("amd64");
+        downloader::ensure_directory_exists(&downloader::get_parent_directory(&binary).unwrap())
+            .unwrap();
+        downloader::write_file(&binary, b"binary").unwrap();
+
+        let removed = setup.remove_files().unwrap();
+        assert_eq!(removed.len(), 2);
+        assert!(removed[0].ends_with("node_exporter"));
+        assert!(!test_path.exists());
+
+        assert!(setup.remove_files().unwrap().is_empty());
+    }
+
     #[test]
     fn test_version_constant() {
         assert_eq!(NODE_EXPORTER_VERSION, "1.7.0");
@@ -263,32 +783,228 @@ mod tests {
 
     #[test]
     fn test_systemd_service_content_function() {
-        let content =
-            create_systemd_service_content("/opt/prometheus", "1.7.0", "amd64", NODE_EXPORTER_PORT);
+        let content = systemd_unit(
+            "/opt/prometheus",
+            "1.7.0",
+            "amd64",
+            NODE_EXPORTER_PORT,
+            None,
+            HardeningProfile::None,
+            None,
+        );
 
         assert!(content.contains("Description=Prometheus Node Exporter"));
         assert!(content.contains("1.7.0"));
         assert!(content.contains("WantedBy=multi-user.target"));
         assert!(content.contains("/opt/prometheus"));
+        assert!(!content.contains("ProtectSystem"));
     }
 
     #[test]
-    fn test_setup_node_exporter_invalid_version() {
-        let temp_dir = TempDir::new().unwrap();
-        let test_path = temp_dir.path().join("test_prometheus");
+    fn test_systemd_service_content_web_config() {
+        let content = systemd_unit(
+            "/opt/prometheus",
+            "1.7.0",
+            "amd64",
+            NODE_EXPORTER_PORT,
+            None,
+            HardeningProfile::Strict,
+            Some("/opt/prometheus/node_exporter/web-config.yml"),
+        );
+        assert!(content.contains(
+            " --web.listen-address=:31415 \
+             --web.config.file=/opt/prometheus/node_exporter/web-config.yml\n"
+        ));
+    }
+
+    #[test]
+    fn test_systemd_service_content_listen_address() {
+        let content = systemd_unit(
+            "/opt/prometheus",
+            "1.7.0",
+            "amd64",
+            NODE_EXPORTER_PORT,
+            Some("10.0.0.5".parse().unwrap()),
+            HardeningProfile::Strict,
+            None,
+        );
+        assert!(content.contains(" --web.listen-address=10.0.0.5:31415\n"));
+    }
+
+    #[test]
+    fn test_systemd_service_content_hardening() {
+        let content = systemd_unit(
+            "/opt/prometheus",
+            "1.7.0",
+            "amd64",
+            NODE_EXPORTER_PORT,
+            None,
+            HardeningProfile::Strict,
+            None,
+        );
+        let expected = "User=prometheus\n\
+                        Group=prometheus\n\
+                        NoNewPrivileges=yes\n\
+                        ProtectSystem=strict\n\
+                        ProtectHome=read-only\n\
+                        PrivateTmp=yes\n\
+                        PrivateDevices=yes\n\
+                        ProtectKernelTunables=yes\n\
+                        ProtectKernelModules=yes\n\
+                        ProtectControlGroups=yes\n\
+                        CapabilityBoundingSet=\n\
+                        ReadOnlyPaths=/opt/prometheus/node_exporter\n\
+                        \n\
+                        [Install]\n";
+        assert!(content.contains(expected), "{content}");
+    }
+
+    #[test]
+    fn test_service_args_with_collectors() {
+        let setup = NodeExporterSetup::new().with_settings(&NodeExporterSettings {
+            collectors: Some(CollectorProfile {
+                enable: vec!["systemd".to_string()],
+                disable: vec!["wifi".to_string()],
+                flags: [(
+                    "filesystem.mount-points-exclude".to_string(),
+                    "^/(sys|proc)($|/)".to_string(),
+                )]
+                .into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let args = service_args(
+            ":31415",
+            None,
+            setup.textfile_dir().as_deref(),
+            &setup.collectors,
+        );
+        assert_eq!(
+            args,
+            vec![
+                "--web.listen-address=:31415",
+                "--collector.textfile.directory=/var/lib/node_exporter/textfile_collector",
+                "--collector.systemd",
+                "--no-collector.wifi",
+                "--collector.filesystem.mount-points-exclude=^/(sys|proc)($|/)",
+            ]
+        );
+
+        let content = service_definition(
+            &setup.install_path,
+            &setup.version,
+            "linux",
+            "amd64",
+            setup.hardening,
+            args,
+        )
+        .render(InitSystem::Systemd);
+        assert!(content.contains(
+            " --no-collector.wifi --collector.filesystem.mount-points-exclude=^/(sys|proc)($$|/)\n"
+        ));
+    }
+
+    #[test]
+    fn test_textfile_directory() {
+        let setup = NodeExporterSetup::new().with_settings(&NodeExporterSettings {
+            textfile_directory: Some("/srv/textfile".to_string()),
+            textfile_producers: Some(vec![TextfileProducer::SmartSummary]),
+            ..Default::default()
+        });
+        assert_eq!(setup.textfile_dir().as_deref(), Some("/srv/textfile"));
+        assert!(
+            setup
+                .requirements()
+                .writable_paths
+                .contains(&"/srv/textfile".to_string())
+        );
+
+        let rootless = NodeExporterSetup::new()
+            .with_init_system(InitSystem::SystemdUser)
+            .with_settings(&NodeExporterSettings::default());
+        assert_eq!(
+            rootless.textfile_directory,
+            format!("{}/textfile_collector", user_install_root())
+        );
+
+        // Disabled collectors get no directory
+        let disabled = NodeExporterSetup::new().with_settings(&NodeExporterSettings {
+            collectors: Some(CollectorProfile {
+                disable: vec!["textfile".to_string()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        assert_eq!(disabled.textfile_dir(), None);
+
+        // A flag takes precedence, and is only passed once
+        let flagged = NodeExporterSetup::new().with_settings(&NodeExporterSettings {
+            collectors: Some(CollectorProfile {
+                flags: [("textfile.directory".to_string(), "/srv/prom".to_string())].into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        assert_eq!(flagged.textfile_dir().as_deref(), Some("/srv/prom"));
+        assert_eq!(flagged.textfile_output_dir(), "/srv/prom");
+        assert_eq!(flagged.textfile_dir_arg(), None);
+        let args = service_args(
+            ":9100",
+            None,
+            flagged.textfile_dir_arg().as_deref(),
+            &flagged.collectors,
+        );
+        assert_eq!(
+            args.iter()
+                .filter(|arg| arg.starts_with("--collector.textfile.directory="))
+                .collect::<Vec<_>>(),
+            ["--collector.textfile.directory=/srv/prom"]
+        );
+    }
+
+    #[test]
+    fn test_smart_summary_is_unsupported_for_user_services() {
+        let settings = NodeExporterSettings {
+            textfile_producers: Some(vec![TextfileProducer::SmartSummary]),
+            ..Default::default()
+        };
+        let rootless = NodeExporterSetup::new()
+            .with_init_system(InitSystem::SystemdUser)
+            .with_settings(&settings);
+        let unsupported = rootless.requirements().unsupported;
+        assert_eq!(unsupported.len(), 1);
+        assert!(unsupported[0].contains("smart-summary"));
+
+        let system = NodeExporterSetup::new()
+            .with_init_system(InitSystem::Systemd)
+            .with_settings(&settings);
+        assert!(system.requirements().unsupported.is_empty());
+    }
+
+    #[test]
+    fn test_with_settings_hardening() {
+        let setup = NodeExporterSetup::new();
+        assert_eq!(setup.hardening, HardeningProfile::Strict);
 
-        let result = setup_node_exporter("99.99.99", test_path.to_str().unwrap());
-        assert!(result.is_err());
+        let setup = setup.with_settings(&NodeExporterSettings {
+            hardening: Some(HardeningProfile::None),
+            ..Default::default()
+        });
+        assert_eq!(setup.hardening, HardeningProfile::None);
     }
 
     #[test]
     fn test_systemd_service_content() {
         let setup = NodeExporterSetup::new();
-        let service_content = create_systemd_service_content(
+        let service_content = systemd_unit(
             &setup.install_path,
             &setup.version,
             "amd64",
             NODE_EXPORTER_PORT,
+            None,
+            setup.hardening,
+            None,
         );
 
         assert!(service_content.contains("Description=Prometheus Node Exporter"));
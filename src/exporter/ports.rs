@@ -0,0 +1,251 @@
+use crate::exporter::Component;
+use clap::ValueEnum;
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, TcpListener};
+use std::process::Command;
+
+/// Ports tried after a component's configured port before giving up
+const PORT_SEARCH_LIMIT: u16 = 100;
+
+/// What the installer does when another program listens on a component's port
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum PortConflictPolicy {
+    /// Configure the component with the next free port
+    #[default]
+    NextFree,
+    /// Stop before anything is installed
+    Fail,
+}
+
+/// Process listening on a port, as far as it could be identified
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Listener {
+    pub pid: Option<u32>,
+    pub name: Option<String>,
+}
+
+impl Listener {
+    /// Whether this is `component`'s own exporter, left running by an earlier install
+    pub fn is_component(&self, component: Component) -> bool {
+        let Some(name) = &self.name else {
+            return false;
+        };
+        let name = name.strip_suffix(".exe").unwrap_or(name);
+        let expected = component.name();
+        // Linux reports process names cut to 15 characters
+        name == expected || (name.len() == 15 && expected.starts_with(name))
+    }
+
+    pub fn describe(&self) -> String {
+        match (&self.name, self.pid) {
+            (Some(name), Some(pid)) => format!("{name} (pid {pid})"),
+            (Some(name), None) => name.clone(),
+            (None, Some(pid)) => format!("pid {pid}"),
+            (None, None) => "an unidentified process".to_string(),
+        }
+    }
+}
+
+/// The process listening on TCP `port`, `None` when the port is free
+pub fn listener(port: u16) -> Option<Listener> {
+    match TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)) {
+        Ok(_) => None,
+        Err(e) if e.kind() == ErrorKind::AddrInUse => {
+            Some(identify_listener(port).unwrap_or_default())
+        }
+        // Ports below 1024 refuse regular users whether or not they are taken
+        Err(_) => None,
+    }
+}
+
+/// First port after `port` that is free or already held by `component`,
+/// skipping the `reserved` ports of the other components being installed
+pub fn next_free_port(
+    port: u16,
+    component: Component,
+    reserved: &[u16],
+    listener: impl Fn(u16) -> Option<Listener>,
+) -> Option<u16> {
+    (port.checked_add(1)?..=port.saturating_add(PORT_SEARCH_LIMIT))
+        .filter(|candidate| !reserved.contains(candidate))
+        .find(|&candidate| listener(candidate).is_none_or(|owner| owner.is_component(component)))
+}
+
+fn identify_listener(port: u16) -> Option<Listener> {
+    if cfg!(windows) {
+        let output = Command::new("netstat")
+            .args(["-ano", "-p", "TCP"])
+            .output()
+            .ok()?;
+        let pid = parse_netstat(&String::from_utf8_lossy(&output.stdout), port)?;
+        let name = Command::new("tasklist")
+            .args(["/FI", &format!("PID eq {pid}"), "/FO", "CSV", "/NH"])
+            .output()
+            .ok()
+            .and_then(|output| parse_tasklist(&String::from_utf8_lossy(&output.stdout)));
+        Some(Listener {
+            pid: Some(pid),
+            name,
+        })
+    } else if cfg!(target_os = "macos") {
+        let output = Command::new("lsof")
+            .args(["-nP", &format!("-iTCP:{port}"), "-sTCP:LISTEN", "-Fpc"])
+            .output()
+            .ok()?;
+        parse_lsof(&String::from_utf8_lossy(&output.stdout))
+    } else {
+        let output = Command::new("ss")
+            .args(["-Htlnp", "sport", "=", &format!(":{port}")])
+            .output()
+            .ok()?;
+        parse_ss(&String::from_utf8_lossy(&output.stdout))
+    }
+}
+
+/// First process in the `users:` column of `ss -tlnp`, only shown for
+/// sockets the caller may inspect
+fn parse_ss(output: &str) -> Option<Listener> {
+    let users = output
+        .lines()
+        .find_map(|line| line.split_once("users:((").map(|(_, users)| users))?;
+    let mut fields = users.split(',');
+    let name = fields.next()?.trim_matches('"').to_string();
+    let pid = fields
+        .find_map(|field| field.strip_prefix("pid="))
+        .and_then(|pid| pid.parse().ok());
+    Some(Listener {
+        pid,
+        name: Some(name),
+    })
+}
+
+/// `lsof -F pc` output: one field per line, prefixed by its identifier
+fn parse_lsof(output: &str) -> Option<Listener> {
+    let mut listener = Listener::default();
+    for line in output.lines() {
+        if let Some(pid) = line.strip_prefix('p') {
+            if listener.pid.is_some() {
+                break;
+            }
+            listener.pid = pid.parse().ok();
+        } else if let Some(name) = line.strip_prefix('c') {
+            listener.name = Some(name.to_string());
+        }
+    }
+    listener.pid.map(|_| listener)
+}
+
+/// PID of the process listening on `port` in `netstat -ano` output
+fn parse_netstat(output: &str, port: u16) -> Option<u32> {
+    let suffix = format!(":{port}");
+    output.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            ["TCP", local, _, "LISTENING", pid] if local.ends_with(&suffix) => pid.parse().ok(),
+            _ => None,
+        }
+    })
+}
+
+/// Image name from `tasklist /FO CSV /NH`, which prints an INFO line when nothing matches
+fn parse_tasklist(output: &str) -> Option<String> {
+    let line = output.lines().next()?.strip_prefix('"')?;
+    Some(line.split('"').next()?.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listener_named(name: &str) -> Listener {
+        Listener {
+            pid: Some(42),
+            name: Some(name.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_is_component() {
+        assert!(listener_named("node_exporter").is_component(Component::NodeExporter));
+        assert!(listener_named("windows_exporter.exe").is_component(Component::WindowsExporter));
+        assert!(listener_named("process-cpu-age").is_component(Component::ProcessCpuAgent));
+        assert!(!listener_named("process").is_component(Component::ProcessCpuAgent));
+        assert!(!listener_named("nginx").is_component(Component::NodeExporter));
+        assert!(!Listener::default().is_component(Component::NodeExporter));
+        assert_eq!(listener_named("nginx").describe(), "nginx (pid 42)");
+    }
+
+    #[test]
+    fn test_next_free_port() {
+        let taken = |port: u16| match port {
+            31416 | 31417 => Some(listener_named("nginx")),
+            31419 => Some(listener_named("node_exporter")),
+            _ => None,
+        };
+        assert_eq!(
+            next_free_port(31415, Component::NodeExporter, &[31418], taken),
+            Some(31419)
+        );
+        assert_eq!(
+            next_free_port(31415, Component::ProcessCpuAgent, &[31418], taken),
+            Some(31420)
+        );
+        assert_eq!(
+            next_free_port(u16::MAX, Component::NodeExporter, &[], taken),
+            None
+        );
+        assert_eq!(
+            next_free_port(9100, Component::NodeExporter, &[], |_| Some(
+                Listener::default()
+            )),
+            None
+        );
+    }
+
+    #[test]
+    fn test_listener_finds_bound_port() {
+        let socket = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        assert!(listener(port).is_some());
+        drop(socket);
+        assert!(listener(port).is_none());
+    }
+
+    #[test]
+    fn test_parse_ss() {
+        let output = "LISTEN 0      4096         *:31415      *:*    \
+                      users:((\"nginx\",pid=42,fd=6),(\"nginx\",pid=811,fd=6))\n";
+        assert_eq!(parse_ss(output), Some(listener_named("nginx")));
+        assert_eq!(parse_ss("LISTEN 0 4096 *:31415 *:*\n"), None);
+        assert_eq!(parse_ss(""), None);
+    }
+
+    #[test]
+    fn test_parse_lsof() {
+        assert_eq!(
+            parse_lsof("p42\ncnode_exporter\nf3\np43\ncother\n"),
+            Some(listener_named("node_exporter"))
+        );
+        assert_eq!(parse_lsof(""), None);
+    }
+
+    #[test]
+    fn test_parse_netstat_and_tasklist() {
+        let netstat = "\r\nActive Connections\r\n\r\n  Proto  Local Address          Foreign Address        State           PID\r\n\
+                       \x20 TCP    0.0.0.0:135            0.0.0.0:0              LISTENING       964\r\n\
+                       \x20 TCP    0.0.0.0:31415          0.0.0.0:0              LISTENING       4242\r\n\
+                       \x20 TCP    10.0.0.5:31415         10.0.0.9:51000         ESTABLISHED     4242\r\n";
+        assert_eq!(parse_netstat(netstat, 31415), Some(4242));
+        assert_eq!(parse_netstat(netstat, 3141), None);
+
+        assert_eq!(
+            parse_tasklist("\"windows_exporter.exe\",\"4242\",\"Services\",\"0\",\"20,480 K\"\r\n")
+                .as_deref(),
+            Some("windows_exporter.exe")
+        );
+        assert_eq!(
+            parse_tasklist("INFO: No tasks are running which match the specified criteria.\r\n"),
+            None
+        );
+    }
+}
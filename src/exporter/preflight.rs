@@ -0,0 +1,314 @@
+use crate::exporter::recorder;
+use crate::os_detector;
+use std::fs;
+use std::path::Path;
+
+/// What installing a component needs from the host
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Requirements {
+    /// Must run as root or from an elevated Administrator prompt
+    pub privileged: bool,
+    /// Directories the installation creates files in
+    pub writable_paths: Vec<String>,
+    /// Programs run during the installation
+    pub tools: Vec<&'static str>,
+    /// Why configured settings cannot work the way the component is installed
+    pub unsupported: Vec<String>,
+}
+
+/// Something that would make the installation fail midway
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    NotPrivileged,
+    NotWritable {
+        path: String,
+    },
+    MissingTool {
+        tool: &'static str,
+    },
+    /// Another program already listens on the component's port
+    PortInUse {
+        port: u16,
+        owner: String,
+    },
+    /// The configured listen address cannot be bound on this host
+    ListenAddressUnavailable {
+        reason: String,
+    },
+    /// A configured setting cannot work the way the component is installed
+    Unsupported {
+        reason: String,
+    },
+}
+
+/// A problem found for one subject, usually a component name
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub subject: &'static str,
+    pub problem: Problem,
+}
+
+impl Requirements {
+    /// Requirements of a system-wide installation writing to `paths`
+    pub fn privileged(paths: Vec<String>, tools: Vec<&'static str>) -> Self {
+        Self {
+            privileged: true,
+            writable_paths: paths,
+            tools,
+            unsupported: Vec::new(),
+        }
+    }
+}
+
+impl Finding {
+    pub fn describe(&self) -> String {
+        let problem = match &self.problem {
+            Problem::NotPrivileged => {
+                if cfg!(windows) {
+                    "needs an elevated Administrator prompt".to_string()
+                } else {
+                    "needs root privileges".to_string()
+                }
+            }
+            Problem::NotWritable { path } => format!("cannot write to {path}"),
+            Problem::MissingTool { tool } => format!("{tool} was not found in PATH"),
+            Problem::PortInUse { port, owner } => format!("port {port} is in use by {owner}"),
+            Problem::ListenAddressUnavailable { reason } => {
+                format!("cannot listen as configured: {reason}")
+            }
+            Problem::Unsupported { reason } => reason.clone(),
+        };
+        format!("{}: {problem}", self.subject)
+    }
+}
+
+/// Check each subject's requirements against this host
+pub fn check(requirements: &[(&'static str, Requirements)], privileged: bool) -> Vec<Finding> {
+    evaluate(requirements, privileged, is_writable, |tool| {
+        os_detector::find_program(tool).is_some()
+    })
+}
+
+fn evaluate(
+    requirements: &[(&'static str, Requirements)],
+    privileged: bool,
+    writable: impl Fn(&str) -> bool,
+    has_tool: impl Fn(&str) -> bool,
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for (subject, requirement) in requirements {
+        let mut add = |problem| findings.push(Finding { subject, problem });
+        if requirement.privileged && !privileged {
+            add(Problem::NotPrivileged);
+        }
+        for path in &requirement.writable_paths {
+            if !writable(path) {
+                add(Problem::NotWritable { path: path.clone() });
+            }
+        }
+        for &tool in &requirement.tools {
+            if !has_tool(tool) {
+                add(Problem::MissingTool { tool });
+            }
+        }
+        for reason in &requirement.unsupported {
+            add(Problem::Unsupported {
+                reason: reason.clone(),
+            });
+        }
+    }
+    findings
+}
+
+/// Whether files can be created in `path`, or in the nearest existing
+/// directory above it when it does not exist yet.
+/// During a dry run only the permission bits are checked, so nothing is written.
+pub fn is_writable(path: &str) -> bool {
+    let mut dir = Path::new(path);
+    while !dir.exists() {
+        match dir.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => dir = parent,
+            _ => return false,
+        }
+    }
+
+    if recorder::is_dry_run() {
+        return permits_write(dir);
+    }
+    // Permission bits do not account for ACLs, read-only mounts or root, so try it
+    let probe = dir.join(format!(
+        ".prometheus-agents-setup-preflight-{}",
+        std::process::id()
+    ));
+    match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+    {
+        Ok(_) => {
+            let _ = fs::remove_file(&probe);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Whether the permission bits of the directory `dir` let this user create
+/// files in it. Misses ACLs and read-only mounts, but changes nothing.
+fn permits_write(dir: &Path) -> bool {
+    let Ok(metadata) = fs::metadata(dir) else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let mode = metadata.mode();
+        match os_detector::effective_uid() {
+            Some(0) => true,
+            Some(uid) if uid == metadata.uid() => mode & 0o200 != 0,
+            // Group membership is not looked up, so either bit may grant it
+            _ => mode & 0o022 != 0,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        !metadata.permissions().readonly()
+    }
+}
+
+/// One line per finding, indented for the installer output
+pub fn format_findings(findings: &[Finding]) -> String {
+    findings
+        .iter()
+        .map(|finding| format!("   ✗ {}", finding.describe()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn requirements() -> Vec<(&'static str, Requirements)> {
+        vec![
+            (
+                "node_exporter",
+                Requirements::privileged(
+                    vec![
+                        "/opt/prometheus".to_string(),
+                        "/etc/systemd/system".to_string(),
+                    ],
+                    vec!["systemctl"],
+                ),
+            ),
+            (
+                "install manifest",
+                Requirements {
+                    writable_paths: vec!["/var/lib/prometheus-agents".to_string()],
+                    ..Default::default()
+                },
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_evaluate_passes_when_everything_is_available() {
+        assert!(evaluate(&requirements(), true, |_| true, |_| true).is_empty());
+    }
+
+    #[test]
+    fn test_evaluate_reports_every_problem() {
+        let findings = evaluate(
+            &requirements(),
+            false,
+            |path| path == "/opt/prometheus",
+            |_| false,
+        );
+        assert_eq!(
+            findings,
+            vec![
+                Finding {
+                    subject: "node_exporter",
+                    problem: Problem::NotPrivileged,
+                },
+                Finding {
+                    subject: "node_exporter",
+                    problem: Problem::NotWritable {
+                        path: "/etc/systemd/system".to_string()
+                    },
+                },
+                Finding {
+                    subject: "node_exporter",
+                    problem: Problem::MissingTool { tool: "systemctl" },
+                },
+                Finding {
+                    subject: "install manifest",
+                    problem: Problem::NotWritable {
+                        path: "/var/lib/prometheus-agents".to_string()
+                    },
+                },
+            ]
+        );
+        assert_eq!(
+            findings[2].describe(),
+            "node_exporter: systemctl was not found in PATH"
+        );
+    }
+
+    #[test]
+    fn test_evaluate_reports_unsupported_settings() {
+        let requirements = [(
+            "node_exporter",
+            Requirements {
+                unsupported: vec!["smart-summary needs root".to_string()],
+                ..Default::default()
+            },
+        )];
+        let findings = evaluate(&requirements, true, |_| true, |_| true);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(
+            findings[0].describe(),
+            "node_exporter: smart-summary needs root"
+        );
+    }
+
+    #[test]
+    fn test_is_writable_checks_nearest_existing_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let missing = temp_dir.path().join("a").join("b");
+        assert!(is_writable(missing.to_str().unwrap()));
+        assert!(!missing.exists());
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_is_writable_does_not_probe_during_dry_run() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().to_str().unwrap().to_string();
+        let (writable, actions) = recorder::dry_run(|| is_writable(&path));
+        assert!(writable);
+        assert!(actions.is_empty());
+
+        #[cfg(unix)]
+        if os_detector::effective_uid() != Some(0) {
+            crate::exporter::downloader::set_permissions(&path, 0o500).unwrap();
+            assert!(!recorder::dry_run(|| is_writable(&path)).0);
+            crate::exporter::downloader::set_permissions(&path, 0o700).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_format_findings() {
+        let findings = [Finding {
+            subject: "process-cpu-agent",
+            problem: Problem::NotWritable {
+                path: "/opt/prometheus".to_string(),
+            },
+        }];
+        assert_eq!(
+            format_findings(&findings),
+            "   ✗ process-cpu-agent: cannot write to /opt/prometheus"
+        );
+    }
+}
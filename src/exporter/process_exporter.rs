@@ -1,14 +1,44 @@
-use crate::exporter::downloader;
-use std::fs;
+use crate::config::ProcessCpuAgentSettings;
+use crate::exporter::account;
+use crate::exporter::downloader::ConfigWrite;
+use crate::exporter::init_system::InitSystem;
+#[cfg(not(windows))]
+use crate::exporter::init_system::{self, ServiceDefinition};
+#[cfg(windows)]
+use crate::exporter::manifest::ServiceRecord;
+use crate::exporter::manifest::{ArtifactSource, ComponentRecord};
+use crate::exporter::preflight::Requirements;
+#[cfg(any(windows, test))]
+use crate::exporter::recorder;
+use crate::exporter::recorder::progress;
+#[cfg(windows)]
+use crate::exporter::service;
+#[cfg(any(windows, test))]
+use crate::exporter::service::WindowsServiceDefinition;
+use crate::exporter::service::{HardeningProfile, WindowsServiceAccount, WindowsStartMode};
+#[cfg(any(windows, test))]
+use crate::exporter::service_host;
+#[cfg(not(windows))]
+use crate::exporter::user_install_root;
+use crate::exporter::{Component, ComponentStatus, downloader};
+#[cfg(windows)]
 use std::process::Command;
 
 #[cfg(windows)]
 use std::os::windows::process::CommandExt;
-const PROCESS_CPU_AGENT_PORT: u16 = 31416;
+pub const PROCESS_CPU_AGENT_PORT: u16 = 31416;
+/// The agent reads `/proc` of other users' processes, so it cannot use the strict profile
+const PROCESS_CPU_AGENT_HARDENING: HardeningProfile = HardeningProfile::Default;
+#[cfg(not(windows))]
+const SERVICE_NAME: &str = "process-cpu-agent";
 const EMBEDDED_PROCESS_AGENT: &[u8] =
     include_bytes!(concat!(env!("OUT_DIR"), "/process_cpu_agent.bin"));
 const EMBEDDED_PROCESS_AGENT_CONFIG: &str = include_str!("../../lib/process-cpu-agent-config.toml");
 
+#[cfg(any(windows, test))]
+const WINDOWS_SERVICE_NAME: &str = "ProcessCpuAgent";
+#[cfg(any(windows, test))]
+const SCHEDULED_TASK_NAME: &str = "ProcessCpuAgent";
 #[cfg(windows)]
 const DETACHED_PROCESS: u32 = 0x00000008;
 #[cfg(windows)]
@@ -24,7 +54,13 @@ enum AgentSource {
 
 pub struct ProcessCpuAgentSetup {
     install_path: String,
+    port: u16,
     source: AgentSource,
+    expected_sha256: Option<String>,
+    hardening: HardeningProfile,
+    init_system: Option<InitSystem>,
+    windows_mode: WindowsStartMode,
+    windows_account: WindowsServiceAccount,
 }
 impl ProcessCpuAgentSetup {
     pub fn new(download_url: Option<String>) -> Self {
@@ -35,92 +71,477 @@ impl ProcessCpuAgentSetup {
 
         Self {
             install_path: get_default_install_path(),
+            port: PROCESS_CPU_AGENT_PORT,
             source,
+            expected_sha256: None,
+            hardening: PROCESS_CPU_AGENT_HARDENING,
+            init_system: InitSystem::detect(),
+            windows_mode: WindowsStartMode::default(),
+            windows_account: WindowsServiceAccount::default(),
+        }
+    }
+
+    /// Expected SHA-256 of the binary downloaded from a custom URL
+    pub fn with_expected_sha256(mut self, sha256: Option<String>) -> Self {
+        self.expected_sha256 = sha256;
+        self
+    }
+
+    /// Override the install path and port with values from the installer config.
+    /// The download source is passed to `new` so the command line can take precedence.
+    pub fn with_settings(mut self, settings: &ProcessCpuAgentSettings) -> Self {
+        if let Some(install_path) = &settings.install_path {
+            self.install_path = install_path.clone();
+        }
+        if let Some(port) = settings.port {
+            self.port = port;
+        }
+        if let Some(hardening) = settings.hardening {
+            self.hardening = hardening;
+        }
+        if let Some(mode) = settings.windows_mode {
+            self.windows_mode = mode;
+        }
+        if let Some(account) = settings.windows_service_account {
+            self.windows_account = account;
+        }
+        // Only the installing user runs the scheduled task or user unit, so keep it in their profile
+        let per_user = if cfg!(windows) {
+            self.windows_mode == WindowsStartMode::ScheduledTask
+        } else {
+            self.init_system == Some(InitSystem::SystemdUser)
+        };
+        if per_user && settings.install_path.is_none() {
+            self.install_path = get_user_install_path();
+        }
+        self
+    }
+
+    /// Register the service with `init` instead of the detected init system.
+    /// Call before `with_settings` so systemd --user installs into the user's home.
+    pub fn with_init_system(mut self, init: InitSystem) -> Self {
+        self.init_system = Some(init);
+        self
+    }
+
+    pub fn init_system(&self) -> Option<InitSystem> {
+        self.init_system
+    }
+
+    pub fn windows_mode(&self) -> WindowsStartMode {
+        self.windows_mode
+    }
+
+    pub fn windows_service_account(&self) -> WindowsServiceAccount {
+        self.windows_account
+    }
+
+    /// Listen on `port` instead, such as a free port replacing a taken one
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Where the agent binary comes from
+    pub fn source(&self) -> ArtifactSource {
+        match &self.source {
+            AgentSource::Embedded => ArtifactSource::Embedded,
+            AgentSource::Remote(url) => ArtifactSource::Remote { url: url.clone() },
+        }
+    }
+
+    /// Describe what `setup` installed, for the install manifest
+    pub fn manifest_record(&self) -> Result<ComponentRecord, Box<dyn std::error::Error>> {
+        let mut record = ComponentRecord::new(Component::ProcessCpuAgent, None, self.source());
+        record.add_file(&get_binary_path(&self.install_path))?;
+        let config_content = create_config_content(self.port)?;
+        record.add_config_file(
+            &get_config_path(&self.install_path),
+            config_content.as_bytes(),
+        );
+
+        #[cfg(windows)]
+        record.add_service(match self.windows_mode {
+            WindowsStartMode::Service => ServiceRecord::WindowsService {
+                name: WINDOWS_SERVICE_NAME.to_string(),
+            },
+            WindowsStartMode::ScheduledTask => ServiceRecord::ScheduledTask {
+                name: SCHEDULED_TASK_NAME.to_string(),
+            },
+        });
+
+        #[cfg(not(windows))]
+        if let Some(init) = self.init_system {
+            let definition_path = init.definition_path(SERVICE_NAME);
+            if downloader::path_exists(&definition_path) {
+                record.add_file(&definition_path)?;
+                record.add_service(init.service_record(SERVICE_NAME));
+            }
+        }
+
+        Ok(record)
+    }
+
+    /// What `setup` needs from the host, for the preflight check
+    pub fn requirements(&self) -> Requirements {
+        let paths = vec![self.install_path.clone()];
+
+        #[cfg(windows)]
+        let requirements = match self.windows_mode {
+            WindowsStartMode::Service => Requirements::privileged(paths, vec!["sc", "net"]),
+            // A logon task of the current user needs no elevation
+            WindowsStartMode::ScheduledTask => Requirements {
+                privileged: false,
+                writable_paths: paths,
+                tools: vec!["schtasks", "taskkill"],
+            },
+        };
+
+        #[cfg(not(windows))]
+        let requirements = {
+            let mut paths = paths;
+            let mut tools = Vec::new();
+            if let Some(init) = self.init_system {
+                paths.extend(downloader::get_parent_directory(
+                    &init.definition_path(SERVICE_NAME),
+                ));
+                tools = init.required_tools();
+            }
+            Requirements {
+                privileged: self.init_system.is_none_or(InitSystem::needs_root),
+                ..Requirements::privileged(paths, tools)
+            }
+        };
+
+        requirements
+    }
+
+    pub fn status(&self) -> ComponentStatus {
+        #[cfg(windows)]
+        let running = match self.windows_mode {
+            WindowsStartMode::Service => service::windows_service_running(WINDOWS_SERVICE_NAME),
+            WindowsStartMode::ScheduledTask => service::scheduled_task_running(SCHEDULED_TASK_NAME),
+        };
+        #[cfg(not(windows))]
+        let running = self
+            .init_system
+            .and_then(|init| init_system::service_running(init, SERVICE_NAME));
+
+        ComponentStatus {
+            component: Component::ProcessCpuAgent,
+            installed: downloader::path_exists(&get_binary_path(&self.install_path)),
+            running,
+        }
+    }
+
+    /// Stop and remove the service registration and files created by `setup`.
+    /// On Linux the service is removed from the init system it was `recorded`
+    /// with, if known.
+    /// Returns a description of everything that was removed.
+    pub fn uninstall(
+        &self,
+        #[cfg_attr(windows, allow(unused_variables))] recorded: Option<InitSystem>,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        #[cfg(windows)]
+        let mut removed = service::remove_windows_service(WINDOWS_SERVICE_NAME)?;
+        #[cfg(windows)]
+        if self.windows_mode == WindowsStartMode::ScheduledTask
+            || service::scheduled_task_registered(SCHEDULED_TASK_NAME) == Some(true)
+        {
+            removed.extend(remove_scheduled_task()?);
+        }
+        #[cfg(not(windows))]
+        let mut removed = match recorded.or(self.init_system) {
+            Some(init) => init_system::remove_service(init, SERVICE_NAME)?,
+            None => Vec::new(),
+        };
+
+        removed.extend(self.remove_files()?);
+        Ok(removed)
+    }
+
+    fn remove_files(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut removed = Vec::new();
+        if downloader::remove_path(&self.install_path)? {
+            removed.push(self.install_path.clone());
+        }
+        if let Some(parent) = downloader::get_parent_directory(&self.install_path)
+            && downloader::remove_dir_if_empty(&parent)
+        {
+            removed.push(parent);
         }
+        Ok(removed)
     }
-    pub fn setup(&self) -> Result<(), Box<dyn std::error::Error>> {
-        println!("Setting up Process CPU Agent...");
+
+    /// Install or repair the agent. Unchanged files are not rewritten, a
+    /// locally edited `config.toml` is preserved, and the service is only
+    /// restarted when something changed.
+    /// Returns `true` if anything on the system was changed.
+    pub fn setup(
+        &self,
+        previous: Option<&ComponentRecord>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        progress!("Setting up Process CPU Agent...");
         match &self.source {
-            AgentSource::Embedded => println!("Using embedded Process CPU Agent binary"),
-            AgentSource::Remote(url) => println!("Download URL: {url}"),
+            AgentSource::Embedded => progress!("Using embedded Process CPU Agent binary"),
+            AgentSource::Remote(url) => progress!("Download URL: {url}"),
         }
 
         self.create_directories()?;
-        self.write_binary()?;
+        #[cfg(windows)]
+        let binary_changed = self.replace_windows_binary()?;
+        #[cfg(not(windows))]
+        let binary_changed = self.write_binary()?;
         // Ensure configuration file exists before wiring services so that
         // the agent can start with a valid config on first run.
-        self.create_config_file()?;
+        let config_changed = self.create_config_file(previous)?;
+        let files_changed = binary_changed || config_changed;
+
+        // A replaced binary was already restarted around the replacement
         #[cfg(windows)]
-        {
-            self.setup_windows_service()?;
-        }
+        let service_changed = self.setup_windows_service(config_changed)?;
 
         #[cfg(not(windows))]
-        {
-            self.setup_linux_service()?;
+        let service_changed = self.setup_unix_service(files_changed)?;
+
+        Ok(files_changed || service_changed)
+    }
+
+    fn create_directories(&self) -> Result<(), Box<dyn std::error::Error>> {
+        downloader::ensure_directory_exists(&self.install_path)?;
+        // ProgramData lets every user create files, which the service would load
+        #[cfg(windows)]
+        if self.windows_mode == WindowsStartMode::Service {
+            account::restrict_windows_acl(&self.install_path, Some(self.windows_account.sid()))?;
+        }
+        Ok(())
+    }
+    /// Whether the installed binary already is the one `write_binary` writes
+    #[cfg(any(windows, test))]
+    fn binary_up_to_date(&self) -> bool {
+        let expected = match &self.source {
+            AgentSource::Embedded => Some(downloader::sha256_hex(EMBEDDED_PROCESS_AGENT)),
+            AgentSource::Remote(_) => self.expected_sha256.clone(),
+        };
+        downloader::sha256_file(&get_binary_path(&self.install_path))
+            .is_ok_and(|hash| Some(hash) == expected)
+    }
+
+    /// Write the binary like `write_binary`. Windows cannot replace a running
+    /// executable, so an installed agent is stopped for the replacement and
+    /// started again afterwards, also when the replacement failed.
+    /// Returns `true` when the binary was replaced
+    #[cfg(any(windows, test))]
+    fn replace_windows_binary(&self) -> Result<bool, Box<dyn std::error::Error>> {
+        let replacing = downloader::path_exists(&get_binary_path(&self.install_path))
+            && !self.binary_up_to_date();
+        if replacing {
+            self.stop_windows_agent()?;
         }
+        let written = self.write_binary();
+        if replacing {
+            self.start_windows_agent()?;
+        }
+        written
+    }
 
+    /// Stop the agent in the configured start mode; not running is no error
+    #[cfg(any(windows, test))]
+    fn stop_windows_agent(&self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.windows_mode {
+            // `net stop` waits for the service to stop, unlike `sc stop`
+            WindowsStartMode::Service => {
+                recorder::run_command("net", &["stop", WINDOWS_SERVICE_NAME])?;
+            }
+            WindowsStartMode::ScheduledTask => {
+                recorder::run_command("schtasks", &["/End", "/TN", SCHEDULED_TASK_NAME])?;
+                // Also spawned detached right after installation
+                recorder::run_command("taskkill", &["/IM", "process-cpu-agent.exe", "/F"])?;
+            }
+        }
         Ok(())
     }
 
-    fn create_directories(&self) -> Result<(), Box<dyn std::error::Error>> {
-        downloader::ensure_directory_exists(&self.install_path)
+    #[cfg(any(windows, test))]
+    fn start_windows_agent(&self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.windows_mode {
+            WindowsStartMode::Service => {
+                recorder::run_command("sc", &["start", WINDOWS_SERVICE_NAME])?;
+            }
+            WindowsStartMode::ScheduledTask => {
+                recorder::run_command("schtasks", &["/Run", "/TN", SCHEDULED_TASK_NAME])?;
+            }
+        }
+        Ok(())
     }
-    fn write_binary(&self) -> Result<(), Box<dyn std::error::Error>> {
+
+    /// Returns `true` when the binary was replaced
+    fn write_binary(&self) -> Result<bool, Box<dyn std::error::Error>> {
         let target_binary = get_binary_path(&self.install_path);
-        match &self.source {
+        if let Some(parent) = downloader::get_parent_directory(&target_binary) {
+            downloader::ensure_directory_exists(&parent)?;
+        }
+
+        let written = match &self.source {
             AgentSource::Embedded => {
-                if let Some(parent) = std::path::Path::new(&target_binary).parent() {
-                    fs::create_dir_all(parent)?;
+                let written =
+                    downloader::write_file_if_changed(&target_binary, EMBEDDED_PROCESS_AGENT)?;
+                if written {
+                    progress!(
+                        "Process CPU Agent binary written from embedded artifact: {target_binary}"
+                    );
                 }
-                fs::write(&target_binary, EMBEDDED_PROCESS_AGENT)?;
-                println!(
-                    "Process CPU Agent binary written from embedded artifact: {target_binary}"
-                );
+                written
             }
             AgentSource::Remote(url) => {
-                downloader::download_file(url, &target_binary)?;
-                println!("Process CPU Agent binary downloaded to: {target_binary}");
+                let expected = self.expected_sha256.as_deref().ok_or(
+                    "A SHA-256 digest is required for custom Process CPU Agent downloads \
+                     (use --process-cpu-agent-sha256 or PROCESS_CPU_AGENT_SHA256)",
+                )?;
+                if downloader::sha256_file(&target_binary).is_ok_and(|hash| hash == expected) {
+                    false
+                } else {
+                    downloader::download_file(url, &target_binary, expected)?;
+                    progress!("Process CPU Agent binary downloaded to: {target_binary}");
+                    return Ok(true);
+                }
             }
+        };
+
+        if !written {
+            progress!("Process CPU Agent binary is up to date: {target_binary}");
+            return Ok(false);
         }
-        Ok(())
+        #[cfg(unix)]
+        downloader::set_executable_permissions(&target_binary)?;
+        Ok(true)
     }
-    #[cfg(not(windows))]
-    fn setup_linux_service(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let service_content =
-            create_linux_service_content(&self.install_path, PROCESS_CPU_AGENT_PORT);
-        let service_path = "/etc/systemd/system/process-cpu-agent.service";
 
-        downloader::write_file(service_path, service_content.as_bytes())?;
-        println!("Systemd service created at: {service_path}");
+    /// Returns `true` when the service definition was written or the service (re)started
+    #[cfg(not(windows))]
+    fn setup_unix_service(&self, files_changed: bool) -> Result<bool, Box<dyn std::error::Error>> {
+        let Some(init) = self.init_system else {
+            progress!("No supported init system found. Please manually configure the service.");
+            return Ok(false);
+        };
 
-        Command::new("systemctl").args(["daemon-reload"]).output()?;
-        Command::new("systemctl")
-            .args(["enable", "--now", "process-cpu-agent"])
-            .output()?;
-        println!("Process CPU Agent service enabled and started");
+        let mut definition = service_definition(&self.install_path, self.port, self.hardening);
+        let mut account_changed = false;
+        if init == InitSystem::Launchd {
+            // macOS only lets root read the CPU usage of other users' processes
+            definition.user = "root";
+        } else if init.needs_root() {
+            account_changed |= account::ensure_system_account(account::SERVICE_ACCOUNT)?;
+            account_changed |=
+                account::restrict_to_group(&self.install_path, account::SERVICE_ACCOUNT)?;
+        }
+        let changed =
+            init_system::install_service(init, &definition, files_changed || account_changed)?;
+        if changed {
+            progress!("Process CPU Agent service enabled and started");
+        }
 
-        Ok(())
+        Ok(changed)
     }
 
+    /// Register the agent in the configured start mode, removing a
+    /// registration left in the other mode by an earlier install.
+    /// Returns `true` when the registration changed or the agent was (re)started.
     #[cfg(windows)]
-    fn setup_windows_service(&self) -> Result<(), Box<dyn std::error::Error>> {
-        setup_windows_service(&self.install_path, PROCESS_CPU_AGENT_PORT)
+    fn setup_windows_service(
+        &self,
+        files_changed: bool,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        match self.windows_mode {
+            WindowsStartMode::Service => {
+                let task_removed = service::scheduled_task_registered(SCHEDULED_TASK_NAME)
+                    == Some(true)
+                    && !remove_scheduled_task()?.is_empty();
+                let host_changed = service_host::install(&self.install_path)?;
+                let definition =
+                    windows_service_definition(&self.install_path, self.port, self.windows_account);
+                let changed =
+                    service::install_windows_service(&definition, files_changed || host_changed)?;
+                if changed {
+                    progress!(
+                        "Windows service {WINDOWS_SERVICE_NAME} running as {}",
+                        self.windows_account.object_name()
+                    );
+                }
+                // Only once the service no longer runs the agent from there
+                let per_user_removed = self.remove_per_user_install();
+                Ok(task_removed || changed || per_user_removed)
+            }
+            WindowsStartMode::ScheduledTask => {
+                let service_removed =
+                    !service::remove_windows_service(WINDOWS_SERVICE_NAME)?.is_empty();
+                if !files_changed
+                    && !service_removed
+                    && service::scheduled_task_running(SCHEDULED_TASK_NAME) == Some(true)
+                {
+                    return Ok(false);
+                }
+                setup_scheduled_task(&self.install_path, self.port)?;
+                Ok(true)
+            }
+        }
     }
 }
 
 impl ProcessCpuAgentSetup {
-    pub fn create_config_file(&self) -> Result<(), Box<dyn std::error::Error>> {
+    /// Remove the agent installed under `%LOCALAPPDATA%`, where services ran
+    /// it from before they moved to ProgramData and where the scheduled task
+    /// mode runs it.
+    /// Returns `true` when it was removed.
+    #[cfg(windows)]
+    fn remove_per_user_install(&self) -> bool {
+        let user_path = get_user_install_path();
+        if user_path == self.install_path || !downloader::path_exists(&get_binary_path(&user_path))
+        {
+            return false;
+        }
+        if let Err(e) = downloader::remove_path(&user_path) {
+            progress!("Warning: Failed to remove the per-user install at {user_path}: {e}");
+            return false;
+        }
+        if let Some(parent) = downloader::get_parent_directory(&user_path) {
+            downloader::remove_dir_if_empty(&parent);
+        }
+        progress!("Removed the per-user install at {user_path}");
+        true
+    }
+
+    /// Write `config.toml`, keeping local edits made since the `previous` install.
+    /// Returns `true` when the active configuration changed.
+    pub fn create_config_file(
+        &self,
+        previous: Option<&ComponentRecord>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
         let config_path = get_config_path(&self.install_path);
 
-        if let Some(parent) = std::path::Path::new(&config_path).parent() {
-            fs::create_dir_all(parent)?;
+        if let Some(parent) = downloader::get_parent_directory(&config_path) {
+            downloader::ensure_directory_exists(&parent)?;
         }
 
-        downloader::write_file(&config_path, EMBEDDED_PROCESS_AGENT_CONFIG.as_bytes())?;
-        println!("Configuration file created at: {config_path}");
-
-        Ok(())
+        let config_content = create_config_content(self.port)?;
+        let shipped = previous.and_then(|record| record.file_sha256(&config_path));
+        match downloader::write_config_file(&config_path, config_content.as_bytes(), shipped)? {
+            ConfigWrite::Unchanged => Ok(false),
+            ConfigWrite::Written => {
+                progress!("Configuration file created at: {config_path}");
+                Ok(true)
+            }
+            ConfigWrite::Preserved { new_path } => {
+                progress!(
+                    "Keeping locally edited {config_path}; new defaults written to {new_path}"
+                );
+                Ok(false)
+            }
+        }
     }
 }
 
@@ -128,12 +549,7 @@ impl ProcessCpuAgentSetup {
 pub fn get_default_install_path() -> String {
     #[cfg(windows)]
     {
-        // Prefer per-user install under LOCALAPPDATA to avoid Program Files write restrictions.
-        if let Ok(local_app_data) = std::env::var("LOCALAPPDATA") {
-            return format!("{local_app_data}\\prometheus\\process-cpu-agent");
-        }
-
-        // Fallback to a machine-wide writable location if LOCALAPPDATA is unavailable.
+        // Machine-wide location the service account can read
         "C:\\ProgramData\\prometheus\\process-cpu-agent".to_string()
     }
 
@@ -143,6 +559,23 @@ pub fn get_default_install_path() -> String {
     }
 }
 
+/// Per-user install path for the scheduled task mode and systemd --user
+pub fn get_user_install_path() -> String {
+    #[cfg(windows)]
+    {
+        // Prefer per-user install under LOCALAPPDATA to avoid Program Files write restrictions.
+        match std::env::var("LOCALAPPDATA") {
+            Ok(local_app_data) => format!("{local_app_data}\\prometheus\\process-cpu-agent"),
+            Err(_) => get_default_install_path(),
+        }
+    }
+
+    #[cfg(not(windows))]
+    {
+        format!("{}/process-cpu-agent", user_install_root())
+    }
+}
+
 /// Get binary path based on install path and OS
 pub fn get_binary_path(install_path: &str) -> String {
     #[cfg(windows)]
@@ -161,54 +594,100 @@ pub fn get_config_path(install_path: &str) -> String {
     return format!("{install_path}/config.toml");
 }
 
-/// Create Linux systemd service content
+/// Create the agent configuration from the embedded template, listening on
+/// `port` of every interface
+pub fn create_config_content(port: u16) -> Result<String, Box<dyn std::error::Error>> {
+    if port == PROCESS_CPU_AGENT_PORT {
+        return Ok(EMBEDDED_PROCESS_AGENT_CONFIG.to_string());
+    }
+    let mut config: toml::Table = EMBEDDED_PROCESS_AGENT_CONFIG.parse()?;
+    let server = config
+        .entry("server")
+        .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+    if let toml::Value::Table(server) = server {
+        server.insert("port".to_string(), toml::Value::Integer(port.into()));
+    }
+    Ok(toml::to_string(&config)?)
+}
+
+/// Service running the agent from `install_path`, which holds the
+/// `config.toml` it reads from its working directory
 #[cfg(not(windows))]
-pub fn create_linux_service_content(install_path: &str, port: u16) -> String {
-    format!(
-        r#"[Unit]
-Description=Process CPU Agent for Prometheus
-After=network.target
-
-[Service]
-Type=simple
-ExecStart={install_path}/process-cpu-agent --port {port}
-Restart=always
-RestartSec=10
-User=prometheus
-Group=prometheus
-
-[Install]
-WantedBy=multi-user.target"#
-    )
+pub fn service_definition(
+    install_path: &str,
+    port: u16,
+    hardening: HardeningProfile,
+) -> ServiceDefinition {
+    ServiceDefinition {
+        name: SERVICE_NAME,
+        description: "Process CPU Agent for Prometheus",
+        command: get_binary_path(install_path),
+        args: vec!["--port".to_string(), port.to_string()],
+        user: account::SERVICE_ACCOUNT,
+        hardening,
+        read_only_paths: vec![install_path.to_string()],
+        working_directory: Some(install_path.to_string()),
+    }
+}
+
+/// Windows service running the agent from `install_path`.
+/// The agent is a console program that cannot answer the service control
+/// manager, so the service runs it under the service host, which also
+/// makes `install_path` the working directory holding `config.toml`.
+#[cfg(any(windows, test))]
+pub fn windows_service_definition(
+    install_path: &str,
+    port: u16,
+    account: WindowsServiceAccount,
+) -> WindowsServiceDefinition {
+    WindowsServiceDefinition {
+        name: WINDOWS_SERVICE_NAME,
+        display_name: "Process CPU Agent",
+        description: "Process CPU Agent for Prometheus",
+        command_line: service_host::command_line(
+            WINDOWS_SERVICE_NAME,
+            install_path,
+            &get_binary_path(install_path),
+            &["--port".to_string(), port.to_string()],
+        ),
+        account,
+    }
 }
 
 /// Setup Windows scheduled task (Windows Task Scheduler)
 #[cfg(windows)]
-pub fn setup_windows_service(
+pub fn setup_scheduled_task(
     install_path: &str,
     port: u16,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let _ = port; // port is configured via config.toml; CLI arg is not needed on Windows
     let binary_path = get_binary_path(install_path);
-    println!("Creating Windows scheduled task...");
+    progress!("Creating Windows scheduled task...");
 
     // Register a Task Scheduler job that runs the agent at user logon
     // under the current user account.
-    let task_name = "ProcessCpuAgent";
+    let task_name = SCHEDULED_TASK_NAME;
 
     let task_run = format!("cmd.exe /C cd /d {} && {}", install_path, binary_path);
 
-    let output = Command::new("schtasks")
-        .args([
+    let output = recorder::run_command(
+        "schtasks",
+        &[
             "/Create", "/TN", task_name, "/SC", "ONLOGON", "/F", "/TR", &task_run,
-        ])
-        .output()?;
+        ],
+    )?;
 
     if output.status.success() {
-        println!("Windows scheduled task registered successfully");
+        progress!("Windows scheduled task registered successfully");
 
         // 설치 직후 한 번 바로 실행 시도: 작업 스케줄러 정의는 그대로 두고,
         // 바이너리를 현재 콘솔/프로세스와 완전히 분리된(detached) 프로세스로 실행한다.
+        if recorder::record(recorder::Action::RunCommand {
+            program: binary_path.clone(),
+            args: Vec::new(),
+        }) {
+            return Ok(());
+        }
         let spawn_result = Command::new(&binary_path)
             .current_dir(install_path)
             .creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP | CREATE_NO_WINDOW)
@@ -216,10 +695,10 @@ pub fn setup_windows_service(
 
         match spawn_result {
             Ok(_) => {
-                println!("ProcessCpuAgent started immediately after installation");
+                progress!("ProcessCpuAgent started immediately after installation");
             }
             Err(e) => {
-                println!("Warning: Failed to start ProcessCpuAgent immediately: {e}");
+                progress!("Warning: Failed to start ProcessCpuAgent immediately: {e}");
             }
         }
     } else {
@@ -236,24 +715,31 @@ pub fn setup_windows_service(
     Ok(())
 }
 
-/// Setup Process CPU Agent with custom parameters
-pub fn setup_process_cpu_agent(
-    download_url: Option<String>,
-    install_path: Option<String>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut setup = ProcessCpuAgentSetup::new(download_url);
-    if let Some(path) = install_path {
-        setup.install_path = path;
+/// Remove the Windows scheduled task and stop the running agent
+#[cfg(windows)]
+pub fn remove_scheduled_task() -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut removed = Vec::new();
+
+    recorder::run_command("schtasks", &["/End", "/TN", SCHEDULED_TASK_NAME])?;
+    let output = recorder::run_command("schtasks", &["/Delete", "/TN", SCHEDULED_TASK_NAME, "/F"])?;
+    if output.status.success() {
+        removed.push(format!("scheduled task {SCHEDULED_TASK_NAME}"));
     }
 
-    setup.create_config_file()?;
-    setup.setup()?;
-    Ok(())
+    // The agent is also spawned detached right after installation, so it may
+    // be running without the scheduled task.
+    let output = recorder::run_command("taskkill", &["/IM", "process-cpu-agent.exe", "/F"])?;
+    if output.status.success() {
+        removed.push("running process-cpu-agent.exe (terminated)".to_string());
+    }
+
+    Ok(removed)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::exporter::recorder::Action;
     use std::fs;
     use std::path::PathBuf;
     use tempfile::TempDir;
@@ -265,6 +751,62 @@ mod tests {
         assert!(!setup.install_path.is_empty());
     }
 
+    #[test]
+    fn test_replace_windows_binary_stops_the_agent() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut setup = ProcessCpuAgentSetup::new(None);
+        setup.install_path = temp_dir.path().to_str().unwrap().to_string();
+        let binary = get_binary_path(&setup.install_path);
+        let run = |program: &str, args: &[&str]| Action::RunCommand {
+            program: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        };
+
+        fs::write(&binary, b"old agent").unwrap();
+        for (mode, stop, start) in [
+            (
+                WindowsStartMode::Service,
+                vec![run("net", &["stop", "ProcessCpuAgent"])],
+                run("sc", &["start", "ProcessCpuAgent"]),
+            ),
+            (
+                WindowsStartMode::ScheduledTask,
+                vec![
+                    run("schtasks", &["/End", "/TN", "ProcessCpuAgent"]),
+                    run("taskkill", &["/IM", "process-cpu-agent.exe", "/F"]),
+                ],
+                run("schtasks", &["/Run", "/TN", "ProcessCpuAgent"]),
+            ),
+        ] {
+            setup.windows_mode = mode;
+            let (written, actions) = recorder::dry_run(|| setup.replace_windows_binary());
+            assert!(written.unwrap());
+            let mut expected = stop;
+            expected.push(Action::WriteFile {
+                path: binary.clone(),
+            });
+            expected.push(start);
+            let actions: Vec<_> = actions
+                .into_iter()
+                .filter(|action| !matches!(action, Action::SetPermissions { .. }))
+                .collect();
+            assert_eq!(actions, expected);
+        }
+
+        // An up to date or first install has no agent to stop
+        fs::write(&binary, EMBEDDED_PROCESS_AGENT).unwrap();
+        let (written, actions) = recorder::dry_run(|| setup.replace_windows_binary());
+        assert!(!written.unwrap());
+        assert!(actions.is_empty());
+        fs::remove_file(&binary).unwrap();
+        let (_, actions) = recorder::dry_run(|| setup.replace_windows_binary());
+        assert!(
+            actions
+                .iter()
+                .all(|action| !matches!(action, Action::RunCommand { .. }))
+        );
+    }
+
     #[test]
     fn test_write_binary_from_embedded() {
         let temp_dir = TempDir::new().unwrap();
@@ -319,6 +861,22 @@ mod tests {
         assert!(config_path.contains("config.toml"));
     }
 
+    #[test]
+    fn test_windows_service_runs_agent_under_host() {
+        let install_path = "C:\\ProgramData\\prometheus\\process-cpu-agent";
+        let definition =
+            windows_service_definition(install_path, 9256, WindowsServiceAccount::LocalService);
+        assert_eq!(definition.name, "ProcessCpuAgent");
+        assert_eq!(
+            definition.command_line,
+            format!(
+                "\"{install_path}\\service-host.exe\" service-host --name ProcessCpuAgent \
+                 --working-dir {install_path} -- {} --port 9256",
+                get_binary_path(install_path)
+            )
+        );
+    }
+
     #[test]
     fn test_create_directories() {
         let temp_dir = TempDir::new().unwrap();
@@ -332,6 +890,48 @@ mod tests {
         assert!(test_path.exists());
     }
 
+    #[test]
+    fn test_manifest_record() {
+        let temp_dir = TempDir::new().unwrap();
+        let test_path = temp_dir.path().join("test_prometheus");
+
+        let mut setup = ProcessCpuAgentSetup::new(None);
+        setup.install_path = test_path.to_str().unwrap().to_string();
+        setup.write_binary().unwrap();
+        setup.create_config_file(None).unwrap();
+
+        let record = setup.manifest_record().unwrap();
+        assert_eq!(record.component, Component::ProcessCpuAgent);
+        assert_eq!(record.source, ArtifactSource::Embedded);
+        assert_eq!(record.files.len(), 2);
+        assert_eq!(
+            record.files[0].sha256,
+            downloader::sha256_hex(EMBEDDED_PROCESS_AGENT)
+        );
+    }
+
+    #[test]
+    fn test_remove_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("test_prometheus");
+        let test_path = root.join("process-cpu-agent");
+
+        let mut setup = ProcessCpuAgentSetup::new(None);
+        setup.install_path = test_path.to_str().unwrap().to_string();
+        setup.write_binary().unwrap();
+        setup.create_config_file(None).unwrap();
+
+        let removed = setup.remove_files().unwrap();
+        assert_eq!(
+            removed,
+            vec![
+                test_path.to_str().unwrap().to_string(),
+                root.to_str().unwrap().to_string()
+            ]
+        );
+        assert!(!root.exists());
+    }
+
     #[test]
     fn test_create_config_file() {
         let temp_dir = TempDir::new().unwrap();
@@ -341,7 +941,7 @@ mod tests {
         let mut setup = ProcessCpuAgentSetup::new(None);
         setup.install_path = test_path.to_str().unwrap().to_string();
 
-        let result = setup.create_config_file();
+        let result = setup.create_config_file(None);
         assert!(result.is_ok());
 
         let config_path = test_path.join("config.toml");
@@ -353,42 +953,134 @@ mod tests {
         assert!(content.contains("[process]"));
     }
 
+    #[test]
+    fn test_create_config_file_keeps_local_edits() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut setup = ProcessCpuAgentSetup::new(None);
+        setup.install_path = temp_dir.path().to_str().unwrap().to_string();
+        let config_path = get_config_path(&setup.install_path);
+
+        assert!(setup.create_config_file(None).unwrap());
+        let record = setup.manifest_record().unwrap();
+        assert!(!setup.create_config_file(Some(&record)).unwrap());
+
+        fs::write(&config_path, "# edited\n").unwrap();
+        setup.port = 9256;
+        assert!(!setup.create_config_file(Some(&record)).unwrap());
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), "# edited\n");
+        let new_defaults = fs::read_to_string(format!("{config_path}.new")).unwrap();
+        assert!(new_defaults.contains("port = 9256"));
+    }
+
+    #[test]
+    fn test_create_config_content_with_custom_port() {
+        assert_eq!(
+            create_config_content(PROCESS_CPU_AGENT_PORT).unwrap(),
+            EMBEDDED_PROCESS_AGENT_CONFIG
+        );
+
+        let content = create_config_content(9256).unwrap();
+        let config: toml::Table = content.parse().unwrap();
+        assert_eq!(config["server"]["port"].as_integer(), Some(9256));
+        assert_eq!(
+            config["process"]["cpu_total_desc"].as_str(),
+            Some("process_cpu_percent")
+        );
+    }
+
+    #[test]
+    fn test_with_settings() {
+        let settings = ProcessCpuAgentSettings {
+            port: Some(9256),
+            install_path: Some("/srv/agent".to_string()),
+            ..Default::default()
+        };
+        let setup = ProcessCpuAgentSetup::new(None).with_settings(&settings);
+        assert_eq!(setup.port(), 9256);
+        assert_eq!(setup.install_path, "/srv/agent");
+        assert_eq!(setup.source, AgentSource::Embedded);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_systemd_user_installs_into_home() {
+        let setup = ProcessCpuAgentSetup::new(None)
+            .with_init_system(InitSystem::SystemdUser)
+            .with_settings(&ProcessCpuAgentSettings::default());
+        assert_eq!(setup.install_path, get_user_install_path());
+        assert!(setup.install_path.starts_with(&user_install_root()));
+        assert!(!setup.requirements().privileged);
+    }
+
     #[cfg(not(windows))]
     #[test]
-    fn test_create_linux_service_content() {
-        let content = create_linux_service_content("/opt/prometheus", 31416);
+    fn test_systemd_unit() {
+        let content = service_definition("/opt/prometheus", 31416, HardeningProfile::None)
+            .render(InitSystem::Systemd);
 
         assert!(content.contains("Description=Process CPU Agent for Prometheus"));
         assert!(content.contains("/opt/prometheus"));
         assert!(content.contains("--port 31416"));
+        // The agent reads config.toml from its working directory
+        assert!(content.contains("WorkingDirectory=/opt/prometheus\n"));
         assert!(content.contains("WantedBy=multi-user.target"));
+        assert!(!content.contains("NoNewPrivileges"));
     }
+
+    #[cfg(not(windows))]
     #[test]
-    fn test_write_binary_with_invalid_url() {
+    fn test_systemd_unit_hardening() {
+        let setup = ProcessCpuAgentSetup::new(None);
+        assert_eq!(setup.hardening, HardeningProfile::Default);
+
+        let content =
+            service_definition("/opt/prometheus/process-cpu-agent", 31416, setup.hardening)
+                .render(InitSystem::Systemd);
+        let expected = "Group=prometheus\n\
+                        NoNewPrivileges=yes\n\
+                        ProtectSystem=full\n\
+                        ProtectHome=read-only\n\
+                        PrivateTmp=yes\n\
+                        CapabilityBoundingSet=CAP_SYS_PTRACE\n\
+                        AmbientCapabilities=CAP_SYS_PTRACE\n\
+                        ReadOnlyPaths=/opt/prometheus/process-cpu-agent\n\
+                        \n\
+                        [Install]";
+        assert!(content.contains(expected), "{content}");
+
+        let strict = service_definition("/opt/prometheus", 31416, HardeningProfile::Strict)
+            .render(InitSystem::Systemd);
+        assert!(strict.contains("ProtectSystem=strict"));
+        assert!(strict.contains("CapabilityBoundingSet=\n"));
+    }
+    #[test]
+    fn test_write_binary_requires_checksum_for_remote() {
         let temp_dir = TempDir::new().unwrap();
         let test_path = temp_dir.path().join("test_prometheus");
-        fs::create_dir_all(&test_path).unwrap();
 
         let mut setup = ProcessCpuAgentSetup::new(Some("http://192.0.2.1:9999/agent".to_string()));
         setup.install_path = test_path.to_str().unwrap().to_string();
 
-        let result = setup.write_binary();
-        assert!(result.is_err());
-    }
-    #[test]
-    fn test_port_constant() {
-        assert_eq!(PROCESS_CPU_AGENT_PORT, 31416);
+        let err = setup.write_binary().unwrap_err();
+        assert!(err.to_string().contains("SHA-256 digest is required"));
+        assert!(!PathBuf::from(get_binary_path(&setup.install_path)).exists());
     }
 
     #[test]
-    fn test_setup_process_cpu_agent_invalid_url() {
+    fn test_write_binary_with_invalid_url() {
         let temp_dir = TempDir::new().unwrap();
         let test_path = temp_dir.path().join("test_prometheus");
+        fs::create_dir_all(&test_path).unwrap();
 
-        let result = setup_process_cpu_agent(
-            Some("http://192.0.2.1:9999/agent".to_string()),
-            Some(test_path.to_str().unwrap().to_string()),
-        );
+        let mut setup = ProcessCpuAgentSetup::new(Some("http://192.0.2.1:9999/agent".to_string()))
+            .with_expected_sha256(Some(downloader::sha256_hex(b"agent")));
+        setup.install_path = test_path.to_str().unwrap().to_string();
+
+        let result = setup.write_binary();
         assert!(result.is_err());
     }
+    #[test]
+    fn test_port_constant() {
+        assert_eq!(PROCESS_CPU_AGENT_PORT, 31416);
+    }
 }
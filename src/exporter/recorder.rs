@@ -0,0 +1,207 @@
+use serde::Serialize;
+use std::cell::RefCell;
+use std::io;
+use std::process::{Command, ExitStatus, Output};
+
+/// A side effect performed (or, in dry-run mode, planned) by the installer
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    CreateDirectory {
+        path: String,
+    },
+    WriteFile {
+        path: String,
+    },
+    #[cfg_attr(not(unix), allow(dead_code))]
+    SetPermissions {
+        path: String,
+        mode: String,
+    },
+    Remove {
+        path: String,
+    },
+    Download {
+        url: String,
+    },
+    Extract {
+        destination: String,
+    },
+    RunCommand {
+        program: String,
+        args: Vec<String>,
+    },
+    HttpRequest {
+        method: String,
+        url: String,
+    },
+}
+
+impl Action {
+    /// One-line human readable description
+    pub fn describe(&self) -> String {
+        match self {
+            Action::CreateDirectory { path } => format!("create directory {path}"),
+            Action::WriteFile { path } => format!("write file {path}"),
+            Action::SetPermissions { path, mode } => format!("chmod {mode} {path}"),
+            Action::Remove { path } => format!("remove {path}"),
+            Action::Download { url } => format!("download {url}"),
+            Action::Extract { destination } => format!("extract archive into {destination}"),
+            Action::RunCommand { program, args } => format!("run {program} {}", args.join(" "))
+                .trim_end()
+                .to_string(),
+            Action::HttpRequest { method, url } => format!("send {method} {url}"),
+        }
+    }
+}
+
+thread_local! {
+    static RECORDER: RefCell<Option<Vec<Action>>> = const { RefCell::new(None) };
+}
+
+/// Print progress output unless a dry run is being recorded
+macro_rules! progress {
+    ($($arg:tt)*) => {
+        if !$crate::exporter::recorder::is_dry_run() {
+            println!($($arg)*);
+        }
+    };
+}
+pub(crate) use progress;
+
+/// Whether side effects are currently being recorded instead of performed
+pub fn is_dry_run() -> bool {
+    RECORDER.with(|recorder| recorder.borrow().is_some())
+}
+
+/// Record an action if a dry run is active.
+/// Returns `true` when the caller must skip the real side effect.
+pub fn record(action: Action) -> bool {
+    RECORDER.with(|recorder| match recorder.borrow_mut().as_mut() {
+        Some(actions) => {
+            // A planned directory exists for the rest of the run
+            let repeated =
+                matches!(action, Action::CreateDirectory { .. }) && actions.contains(&action);
+            if !repeated {
+                actions.push(action);
+            }
+            true
+        }
+        None => false,
+    })
+}
+
+/// Run `f` with side effects recorded instead of performed
+pub fn dry_run<T>(f: impl FnOnce() -> T) -> (T, Vec<Action>) {
+    RECORDER.with(|recorder| *recorder.borrow_mut() = Some(Vec::new()));
+    let result = f();
+    let actions = RECORDER.with(|recorder| recorder.borrow_mut().take().unwrap_or_default());
+    (result, actions)
+}
+
+/// Run an external command that changes system state.
+/// In dry-run mode the command is recorded and reported as successful.
+pub fn run_command(program: &str, args: &[&str]) -> io::Result<Output> {
+    let planned = record(Action::RunCommand {
+        program: program.to_string(),
+        args: args.iter().map(|arg| arg.to_string()).collect(),
+    });
+    if planned {
+        return Ok(Output {
+            status: success_status(),
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        });
+    }
+    Command::new(program).args(args).output()
+}
+
+#[cfg(unix)]
+fn success_status() -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    ExitStatus::from_raw(0)
+}
+
+#[cfg(windows)]
+fn success_status() -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_outside_dry_run() {
+        assert!(!is_dry_run());
+        assert!(!record(Action::Remove {
+            path: "/tmp/none".to_string()
+        }));
+    }
+
+    #[test]
+    fn test_dry_run_collects_actions_in_order() {
+        let (result, actions) = dry_run(|| {
+            assert!(is_dry_run());
+            record(Action::CreateDirectory {
+                path: "/opt/prometheus".to_string(),
+            });
+            record(Action::CreateDirectory {
+                path: "/opt/prometheus".to_string(),
+            });
+            run_command("systemctl", &["daemon-reload"]).map(|output| output.status.success())
+        });
+
+        assert!(result.unwrap());
+        assert!(!is_dry_run());
+        assert_eq!(
+            actions,
+            vec![
+                Action::CreateDirectory {
+                    path: "/opt/prometheus".to_string()
+                },
+                Action::RunCommand {
+                    program: "systemctl".to_string(),
+                    args: vec!["daemon-reload".to_string()]
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_describe() {
+        let action = Action::RunCommand {
+            program: "systemctl".to_string(),
+            args: vec!["enable".to_string(), "--now".to_string()],
+        };
+        assert_eq!(action.describe(), "run systemctl enable --now");
+
+        let action = Action::SetPermissions {
+            path: "/opt/bin".to_string(),
+            mode: "755".to_string(),
+        };
+        assert_eq!(action.describe(), "chmod 755 /opt/bin");
+
+        let action = Action::HttpRequest {
+            method: "PUT".to_string(),
+            url: "http://127.0.0.1:8500/v1/agent/service/register".to_string(),
+        };
+        assert_eq!(
+            action.describe(),
+            "send PUT http://127.0.0.1:8500/v1/agent/service/register"
+        );
+    }
+
+    #[test]
+    fn test_action_json() {
+        let action = Action::Download {
+            url: "https://example.com/a.tar.gz".to_string(),
+        };
+        let json = serde_json::to_string(&action).unwrap();
+        assert_eq!(
+            json,
+            r#"{"action":"download","url":"https://example.com/a.tar.gz"}"#
+        );
+    }
+}
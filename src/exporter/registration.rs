@@ -0,0 +1,296 @@
+use crate::exporter::recorder::{self, Action};
+use crate::exporter::scrape::{self, Host, TargetGroup};
+use reqwest::Method;
+use reqwest::blocking::Client;
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often Consul scrapes a registered exporter to check its health
+const CONSUL_CHECK_INTERVAL: &str = "30s";
+const CONSUL_CHECK_TIMEOUT: &str = "5s";
+/// Tag marking the services Prometheus' `consul_sd_configs` should pick up
+const CONSUL_TAG: &str = "prometheus";
+
+/// Request body sent to the registration endpoint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RegistrationFormat {
+    /// One `PUT` per exporter with the body of Consul's `/v1/agent/service/register`
+    #[default]
+    Consul,
+    /// A single `POST` listing every exporter as `file_sd` target groups
+    FileSd,
+}
+
+/// Endpoint the installed exporters are announced to after a successful install
+#[derive(Debug, Clone, PartialEq)]
+pub struct Registration {
+    pub url: String,
+    pub format: RegistrationFormat,
+    /// Sent as a bearer token, which Consul accepts in place of `X-Consul-Token`
+    pub token: Option<String>,
+}
+
+/// Service definition accepted by Consul's `/v1/agent/service/register`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ConsulService {
+    #[serde(rename = "ID")]
+    id: String,
+    name: &'static str,
+    address: String,
+    port: u16,
+    tags: Vec<&'static str>,
+    meta: BTreeMap<&'static str, String>,
+    check: ConsulCheck,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ConsulCheck {
+    #[serde(rename = "HTTP", skip_serializing_if = "Option::is_none")]
+    http: Option<String>,
+    /// Used instead of `HTTP` when Consul cannot authenticate against the exporter
+    #[serde(rename = "TCP", skip_serializing_if = "Option::is_none")]
+    tcp: Option<String>,
+    #[serde(rename = "TLSSkipVerify")]
+    tls_skip_verify: bool,
+    interval: &'static str,
+    timeout: &'static str,
+}
+
+impl Registration {
+    /// Announce every exporter in `groups`, all running on `host`
+    pub fn register(
+        &self,
+        host: &Host,
+        groups: &[TargetGroup],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match self.format {
+            RegistrationFormat::Consul => {
+                for service in consul_services(host, groups) {
+                    // Consul only registers services with PUT
+                    self.send(Method::PUT, serde_json::to_string(&service)?)?;
+                }
+                Ok(())
+            }
+            RegistrationFormat::FileSd => self.send(Method::POST, scrape::file_sd_json(groups)?),
+        }
+    }
+
+    fn send(&self, method: Method, body: String) -> Result<(), Box<dyn std::error::Error>> {
+        if recorder::record(Action::HttpRequest {
+            method: method.to_string(),
+            url: self.url.clone(),
+        }) {
+            return Ok(());
+        }
+
+        let client = Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+        let mut request = client
+            .request(method.clone(), &self.url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body);
+        if let Some(token) = &self.token {
+            request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+        }
+        let response = request.send()?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().unwrap_or_default();
+            return Err(format!(
+                "{method} {} returned HTTP {status}: {}",
+                self.url,
+                text.trim()
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// One Consul service per exporter, named after its component and checked through its metrics endpoint
+fn consul_services(host: &Host, groups: &[TargetGroup]) -> Vec<ConsulService> {
+    groups
+        .iter()
+        .map(|group| ConsulService {
+            // Unique per host, so registering again replaces the earlier entry
+            id: format!("{}-{}", group.job, host.hostname),
+            name: group.job,
+            address: group.address.clone(),
+            port: group.port,
+            tags: vec![CONSUL_TAG],
+            // Consul reserves nothing for Prometheus' `__` labels
+            meta: group
+                .labels
+                .iter()
+                .filter(|(name, _)| !name.starts_with("__"))
+                .map(|(name, value)| (*name, value.clone()))
+                .collect(),
+            check: consul_check(group),
+        })
+        .collect()
+}
+
+/// Health check scraping the metrics endpoint, or just connecting when it requires basic auth
+fn consul_check(group: &TargetGroup) -> ConsulCheck {
+    let target = &group.targets[0];
+    let (http, tcp) = match group.username {
+        Some(_) => (None, Some(target.clone())),
+        None => (Some(format!("{}://{target}/metrics", group.scheme())), None),
+    };
+    ConsulCheck {
+        http,
+        tcp,
+        tls_skip_verify: group.insecure_tls,
+        interval: CONSUL_CHECK_INTERVAL,
+        timeout: CONSUL_CHECK_TIMEOUT,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WebSettings;
+    use crate::exporter::Component;
+    use crate::exporter::web_config::WebConfig;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn host() -> Host {
+        Host {
+            address: "10.0.0.5".to_string(),
+            hostname: "web-01".to_string(),
+        }
+    }
+
+    fn groups() -> Vec<TargetGroup> {
+        vec![
+            scrape::target_group(&host(), Component::NodeExporter, 31415, Some("1.7.0")),
+            scrape::target_group(&host(), Component::ProcessCpuAgent, 31416, None),
+        ]
+    }
+
+    /// Answer `count` requests with `status`, returning each request line and body
+    fn stub_server(
+        count: usize,
+        status: &'static str,
+    ) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/v1/agent/service/register",
+            listener.local_addr().unwrap()
+        );
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for _ in 0..count {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':')
+                        && name.eq_ignore_ascii_case("content-length")
+                    {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                requests.push(format!(
+                    "{}\n{}",
+                    request_line.trim(),
+                    String::from_utf8(body).unwrap()
+                ));
+                let response =
+                    format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn test_consul_services() {
+        let services = consul_services(&host(), &groups());
+        let json = serde_json::to_value(&services[0]).unwrap();
+        assert_eq!(json["ID"], "node_exporter-web-01");
+        assert_eq!(json["Name"], "node_exporter");
+        assert_eq!(json["Address"], "10.0.0.5");
+        assert_eq!(json["Port"], 31415);
+        assert_eq!(json["Tags"][0], "prometheus");
+        assert_eq!(json["Meta"]["version"], "1.7.0");
+        assert_eq!(json["Check"]["HTTP"], "http://10.0.0.5:31415/metrics");
+        assert_eq!(services[1].port, 31416);
+
+        let web = WebConfig::from_settings(&WebSettings {
+            basic_auth_users: Some([("prometheus".to_string(), "secret".to_string())].into()),
+            self_signed_cert: Some(true),
+            ..Default::default()
+        })
+        .unwrap();
+        let group = groups().remove(0).with_web_config(&web);
+        let json = serde_json::to_value(&consul_services(&host(), &[group])[0]).unwrap();
+        assert_eq!(json["Check"]["TCP"], "10.0.0.5:31415");
+        assert!(json["Check"].get("HTTP").is_none());
+        assert!(json["Meta"].get("__scheme__").is_none());
+    }
+
+    #[test]
+    fn test_register_with_consul_stub() {
+        let (url, server) = stub_server(2, "200 OK");
+        let registration = Registration {
+            url,
+            format: RegistrationFormat::Consul,
+            token: Some("secret".to_string()),
+        };
+        registration.register(&host(), &groups()).unwrap();
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("PUT /v1/agent/service/register HTTP/1.1\n"));
+        assert!(requests[1].contains("\"ID\":\"process-cpu-agent-web-01\""));
+    }
+
+    #[test]
+    fn test_register_file_sd_reports_http_errors() {
+        let (url, server) = stub_server(1, "503 Service Unavailable");
+        let registration = Registration {
+            url,
+            format: RegistrationFormat::FileSd,
+            token: None,
+        };
+        let error = registration.register(&host(), &groups()).unwrap_err();
+        assert!(error.to_string().contains("HTTP 503"));
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST "));
+        assert!(requests[0].contains("\"targets\""));
+    }
+
+    #[test]
+    fn test_register_dry_run() {
+        let registration = Registration {
+            url: "http://127.0.0.1:1/v1/agent/service/register".to_string(),
+            format: RegistrationFormat::Consul,
+            token: None,
+        };
+        let (result, actions) = recorder::dry_run(|| registration.register(&host(), &groups()));
+        result.unwrap();
+        assert_eq!(actions.len(), 2);
+        assert_eq!(
+            actions[0].describe(),
+            "send PUT http://127.0.0.1:1/v1/agent/service/register"
+        );
+    }
+}
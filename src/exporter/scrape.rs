@@ -0,0 +1,274 @@
+use crate::exporter::web_config::WebConfig;
+use crate::exporter::{Component, downloader, listen, yaml_quote};
+use crate::os_detector;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// This host as Prometheus sees it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Host {
+    /// Host name or IP address Prometheus scrapes
+    pub address: String,
+    pub hostname: String,
+}
+
+/// Targets of one exporter with their labels, in the `file_sd` format
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TargetGroup {
+    #[serde(skip)]
+    pub job: &'static str,
+    /// Host name or IP address of the exporter in `targets`
+    #[serde(skip)]
+    pub address: String,
+    /// Port of the exporter in `targets`
+    #[serde(skip)]
+    pub port: u16,
+    pub targets: Vec<String>,
+    pub labels: BTreeMap<&'static str, String>,
+    /// Basic auth user Prometheus has to scrape as
+    #[serde(skip)]
+    pub username: Option<String>,
+    /// The certificate is self-signed, so scrapers skip verifying it
+    #[serde(skip)]
+    pub insecure_tls: bool,
+}
+
+/// Label `file_sd` and static targets use to pick the scrape scheme
+const SCHEME_LABEL: &str = "__scheme__";
+
+impl TargetGroup {
+    /// Scrape over HTTPS and with basic auth as configured in `web`
+    pub fn with_web_config(mut self, web: &WebConfig) -> Self {
+        if web.tls_enabled() {
+            self.labels.insert(SCHEME_LABEL, "https".to_string());
+        }
+        self.username = web.username().map(str::to_string);
+        self.insecure_tls = web.self_signed();
+        self
+    }
+
+    pub fn scheme(&self) -> &str {
+        self.labels.get(SCHEME_LABEL).map_or("http", String::as_str)
+    }
+}
+
+impl Host {
+    /// Use `address` when given, otherwise the primary IP address, falling back to the host name
+    pub fn detect(address: Option<&str>) -> Self {
+        let hostname = os_detector::hostname().unwrap_or_else(|| "localhost".to_string());
+        let address = match address {
+            Some(address) => address.to_string(),
+            None => os_detector::primary_ip()
+                .map(|ip| ip.to_string())
+                .unwrap_or_else(|| hostname.clone()),
+        };
+        Self { address, hostname }
+    }
+
+    /// `host:port`, with IPv6 addresses in brackets
+    fn target(&self, port: u16) -> String {
+        listen::socket_address(&self.address, port)
+    }
+}
+
+/// Target group scraping `component` on `port` of `host`
+pub fn target_group(
+    host: &Host,
+    component: Component,
+    port: u16,
+    version: Option<&str>,
+) -> TargetGroup {
+    let arch = os_detector::detect_arch()
+        .map(|arch| arch.node_exporter_name())
+        .unwrap_or(os_detector::get_arch());
+    let mut labels = BTreeMap::from([
+        ("hostname", host.hostname.clone()),
+        ("os", std::env::consts::OS.to_string()),
+        ("arch", arch.to_string()),
+        ("component", component.name().to_string()),
+    ]);
+    if let Some(version) = version {
+        labels.insert("version", version.to_string());
+    }
+    TargetGroup {
+        job: component.name(),
+        address: host.address.clone(),
+        port,
+        targets: vec![host.target(port)],
+        labels,
+        username: None,
+        insecure_tls: false,
+    }
+}
+
+/// Target file for Prometheus `file_sd_configs`
+pub fn file_sd_json(groups: &[TargetGroup]) -> Result<String, serde_json::Error> {
+    Ok(format!("{}\n", serde_json::to_string_pretty(groups)?))
+}
+
+/// `scrape_configs` entries with one job per exporter, for `prometheus.yml`
+pub fn scrape_configs_yaml(groups: &[TargetGroup]) -> String {
+    let mut yaml = String::from("scrape_configs:\n");
+    for group in groups {
+        let targets: Vec<String> = group.targets.iter().map(|t| yaml_quote(t)).collect();
+        yaml.push_str(&format!("  - job_name: {}\n", yaml_quote(group.job)));
+        if group.scheme() != "http" {
+            yaml.push_str(&format!("    scheme: {}\n", yaml_quote(group.scheme())));
+        }
+        if group.insecure_tls {
+            yaml.push_str("    tls_config:\n      insecure_skip_verify: true\n");
+        }
+        if let Some(username) = &group.username {
+            yaml.push_str(&format!(
+                "    basic_auth:\n      username: {}\n      password: \"<password>\"\n",
+                yaml_quote(username)
+            ));
+        }
+        yaml.push_str(&format!(
+            "    static_configs:\n      - targets: [{}]\n        labels:\n",
+            targets.join(", ")
+        ));
+        // The scheme is set for the whole job above
+        for (name, value) in group
+            .labels
+            .iter()
+            .filter(|(name, _)| **name != SCHEME_LABEL)
+        {
+            yaml.push_str(&format!("          {name}: {}\n", yaml_quote(value)));
+        }
+    }
+    yaml
+}
+
+/// Write the `file_sd` target file to `output`, which may be a directory
+/// shared with the Prometheus server.
+/// Returns the path written, or `None` when it was unchanged.
+pub fn write_file_sd(
+    output: &str,
+    host: &Host,
+    groups: &[TargetGroup],
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let path = file_sd_path(output, &host.hostname);
+    if let Some(parent) = downloader::get_parent_directory(&path) {
+        downloader::ensure_directory_exists(&parent)?;
+    }
+    // Renamed into place, so Prometheus never reads a partial file
+    let changed = downloader::write_file_if_changed(&path, file_sd_json(groups)?.as_bytes())?;
+    Ok(changed.then_some(path))
+}
+
+/// `output` itself, or `<hostname>.json` inside it when it is a directory
+fn file_sd_path(output: &str, hostname: &str) -> String {
+    if Path::new(output).is_dir() {
+        Path::new(output)
+            .join(format!("{hostname}.json"))
+            .to_string_lossy()
+            .into_owned()
+    } else {
+        output.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WebSettings;
+    use tempfile::TempDir;
+
+    fn host() -> Host {
+        Host {
+            address: "10.0.0.5".to_string(),
+            hostname: "web-01".to_string(),
+        }
+    }
+
+    fn groups() -> Vec<TargetGroup> {
+        vec![
+            target_group(&host(), Component::NodeExporter, 31415, Some("1.7.0")),
+            target_group(&host(), Component::ProcessCpuAgent, 31418, None),
+        ]
+    }
+
+    #[test]
+    fn test_target_group() {
+        let group = &groups()[0];
+        assert_eq!(group.job, "node_exporter");
+        assert_eq!(group.targets, vec!["10.0.0.5:31415"]);
+        assert_eq!(group.labels["hostname"], "web-01");
+        assert_eq!(group.labels["component"], "node_exporter");
+        assert_eq!(group.labels["version"], "1.7.0");
+        assert_eq!(group.labels["os"], std::env::consts::OS);
+        assert!(!groups()[1].labels.contains_key("version"));
+
+        let ipv6 = Host {
+            address: "fd00::5".to_string(),
+            ..host()
+        };
+        assert_eq!(ipv6.target(9100), "[fd00::5]:9100");
+    }
+
+    #[test]
+    fn test_file_sd_json() {
+        let json = file_sd_json(&groups()).unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed[1]["targets"][0], "10.0.0.5:31418");
+        assert_eq!(parsed[1]["labels"]["component"], "process-cpu-agent");
+        assert!(parsed[0].get("job").is_none());
+    }
+
+    #[test]
+    fn test_scrape_configs_yaml() {
+        let yaml = scrape_configs_yaml(&groups());
+        assert!(yaml.starts_with("scrape_configs:\n  - job_name: \"node_exporter\"\n"));
+        assert!(yaml.contains(
+            "    static_configs:\n      - targets: [\"10.0.0.5:31415\"]\n        labels:\n"
+        ));
+        assert!(yaml.contains("          hostname: \"web-01\"\n"));
+        assert!(yaml.contains("  - job_name: \"process-cpu-agent\"\n"));
+    }
+
+    #[test]
+    fn test_secured_targets() {
+        let web = WebConfig::from_settings(&WebSettings {
+            basic_auth_users: Some([("prometheus".to_string(), "secret".to_string())].into()),
+            self_signed_cert: Some(true),
+            ..Default::default()
+        })
+        .unwrap();
+        let groups: Vec<TargetGroup> = groups()
+            .into_iter()
+            .map(|group| group.with_web_config(&web))
+            .collect();
+        assert_eq!(groups[0].scheme(), "https");
+
+        let parsed: serde_json::Value =
+            serde_json::from_str(&file_sd_json(&groups).unwrap()).unwrap();
+        assert_eq!(parsed[0]["labels"]["__scheme__"], "https");
+
+        let yaml = scrape_configs_yaml(&groups);
+        assert!(yaml.contains(
+            "  - job_name: \"node_exporter\"\n    scheme: \"https\"\n    tls_config:\n      \
+             insecure_skip_verify: true\n    basic_auth:\n      username: \"prometheus\"\n"
+        ));
+        assert!(!yaml.contains("__scheme__"));
+        assert!(!yaml.contains("secret"));
+    }
+
+    #[test]
+    fn test_write_file_sd_into_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().to_str().unwrap();
+
+        let written = write_file_sd(output, &host(), &groups()).unwrap();
+        let path = temp_dir.path().join("web-01.json");
+        assert_eq!(written.as_deref(), path.to_str());
+        assert!(std::fs::read_to_string(&path).unwrap().contains("31418"));
+
+        assert_eq!(write_file_sd(output, &host(), &groups()).unwrap(), None);
+
+        let file = temp_dir.path().join("targets").join("agents.json");
+        write_file_sd(file.to_str().unwrap(), &host(), &groups()).unwrap();
+        assert!(file.exists());
+    }
+}
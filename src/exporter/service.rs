@@ -0,0 +1,555 @@
+use crate::exporter::{downloader, recorder};
+use crate::os_detector;
+use serde::Deserialize;
+use std::path::Path;
+use std::process::{Command, Output};
+
+pub const SYSTEMD_UNIT_DIR: &str = "/etc/systemd/system";
+/// One file per user whose manager keeps running after they log out
+const SYSTEMD_LINGER_DIR: &str = "/var/lib/systemd/linger";
+
+/// Seconds without a failure after which Windows resets a service's failure count
+#[cfg(any(windows, test))]
+const WINDOWS_FAILURE_RESET_SECS: u32 = 86_400;
+/// Restart delays after the first, second and later failures, in milliseconds
+#[cfg(any(windows, test))]
+const WINDOWS_RESTART_DELAYS_MS: [u32; 3] = [5_000, 10_000, 60_000];
+
+/// Sandboxing applied to generated systemd units
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HardeningProfile {
+    /// Read-only system, no capabilities, no devices or kernel tunables
+    Strict,
+    /// Read-only system that still lets the service inspect other users' processes
+    Default,
+    /// No sandboxing directives
+    None,
+}
+
+impl HardeningProfile {
+    /// `[Service]` directives for this profile, one per line.
+    /// `read_only_paths` are the files the service runs from.
+    pub fn directives(self, read_only_paths: &[&str]) -> String {
+        let mut lines = match self {
+            HardeningProfile::Strict => vec![
+                "NoNewPrivileges=yes",
+                "ProtectSystem=strict",
+                // Read-only rather than hidden so filesystem metrics still cover /home
+                "ProtectHome=read-only",
+                "PrivateTmp=yes",
+                "PrivateDevices=yes",
+                "ProtectKernelTunables=yes",
+                "ProtectKernelModules=yes",
+                "ProtectControlGroups=yes",
+                "CapabilityBoundingSet=",
+            ],
+            HardeningProfile::Default => vec![
+                "NoNewPrivileges=yes",
+                "ProtectSystem=full",
+                "ProtectHome=read-only",
+                "PrivateTmp=yes",
+                // Needed to read /proc/<pid> of processes owned by other users
+                "CapabilityBoundingSet=CAP_SYS_PTRACE",
+                "AmbientCapabilities=CAP_SYS_PTRACE",
+            ],
+            HardeningProfile::None => return String::new(),
+        }
+        .into_iter()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+
+        if !read_only_paths.is_empty() {
+            lines.push(format!("ReadOnlyPaths={}", read_only_paths.join(" ")));
+        }
+        lines.into_iter().map(|line| format!("{line}\n")).collect()
+    }
+}
+
+/// systemd instance a unit is installed into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemdScope {
+    /// The system manager, PID 1
+    System,
+    /// The calling user's manager, controlled with `systemctl --user`
+    User,
+}
+
+impl SystemdScope {
+    /// Directory the scope's unit files are written to
+    pub fn unit_dir(self) -> String {
+        match self {
+            SystemdScope::System => SYSTEMD_UNIT_DIR.to_string(),
+            SystemdScope::User => format!("{}/systemd/user", os_detector::user_config_dir()),
+        }
+    }
+
+    pub fn unit_path(self, unit: &str) -> String {
+        format!("{}/{unit}.service", self.unit_dir())
+    }
+
+    /// `systemctl` arguments addressing this scope
+    pub fn systemctl_args<'a>(self, args: &[&'a str]) -> Vec<&'a str> {
+        match self {
+            SystemdScope::System => args.to_vec(),
+            SystemdScope::User => std::iter::once("--user")
+                .chain(args.iter().copied())
+                .collect(),
+        }
+    }
+
+    fn systemctl(self, args: &[&str]) -> std::io::Result<Output> {
+        recorder::run_command("systemctl", &self.systemctl_args(args))
+    }
+}
+
+/// How an agent without an MSI is started on Windows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WindowsStartMode {
+    /// Service started at boot by the service control manager; needs Administrator rights
+    #[default]
+    Service,
+    /// Task run at logon of the installing user; works without Administrator rights
+    ScheduledTask,
+}
+
+/// Built-in account a Windows service runs as
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowsServiceAccount {
+    /// Minimal local privileges, anonymous on the network
+    #[default]
+    LocalService,
+    /// Minimal local privileges, authenticates as the computer on the network
+    NetworkService,
+}
+
+impl WindowsServiceAccount {
+    /// Name passed to `sc obj=`
+    pub fn object_name(self) -> &'static str {
+        match self {
+            WindowsServiceAccount::LocalService => "NT AUTHORITY\\LocalService",
+            WindowsServiceAccount::NetworkService => "NT AUTHORITY\\NetworkService",
+        }
+    }
+
+    /// Security identifier of the account, the same in every Windows language
+    #[cfg(windows)]
+    pub fn sid(self) -> &'static str {
+        match self {
+            WindowsServiceAccount::LocalService => "S-1-5-19",
+            WindowsServiceAccount::NetworkService => "S-1-5-20",
+        }
+    }
+}
+
+/// A service registered with the Windows service control manager
+#[cfg(any(windows, test))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowsServiceDefinition {
+    pub name: &'static str,
+    pub display_name: &'static str,
+    pub description: &'static str,
+    /// Quoted binary path followed by its arguments
+    pub command_line: String,
+    pub account: WindowsServiceAccount,
+}
+
+#[cfg(any(windows, test))]
+impl WindowsServiceDefinition {
+    /// `sc create` or `sc config` arguments for an automatically started service
+    fn sc_config_args(&self, verb: &str) -> Vec<String> {
+        [
+            verb,
+            self.name,
+            "binPath=",
+            &self.command_line,
+            "start=",
+            "auto",
+            "obj=",
+            self.account.object_name(),
+            "DisplayName=",
+            self.display_name,
+        ]
+        .map(str::to_string)
+        .to_vec()
+    }
+
+    /// `sc failure` arguments restarting the service whenever it exits unexpectedly
+    fn sc_failure_args(&self) -> Vec<String> {
+        let actions: Vec<String> = WINDOWS_RESTART_DELAYS_MS
+            .iter()
+            .map(|delay| format!("restart/{delay}"))
+            .collect();
+        vec![
+            "failure".to_string(),
+            self.name.to_string(),
+            "reset=".to_string(),
+            WINDOWS_FAILURE_RESET_SECS.to_string(),
+            "actions=".to_string(),
+            actions.join("/"),
+        ]
+    }
+}
+
+/// Check whether a systemd unit is active.
+/// Returns `None` when `systemctl` is not available.
+pub fn systemd_unit_active(scope: SystemdScope, unit: &str) -> Option<bool> {
+    let output = Command::new("systemctl")
+        .args(scope.systemctl_args(&["is-active", unit]))
+        .output()
+        .ok()?;
+    Some(is_active_output(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Check whether a Windows service is registered.
+/// Returns `None` when `sc` is not available.
+pub fn windows_service_installed(name: &str) -> Option<bool> {
+    let output = Command::new("sc").args(["query", name]).output().ok()?;
+    Some(output.status.success())
+}
+
+/// Check whether a Windows service is in the RUNNING state.
+/// Returns `None` when `sc` is not available.
+pub fn windows_service_running(name: &str) -> Option<bool> {
+    let output = Command::new("sc").args(["query", name]).output().ok()?;
+    Some(output.status.success() && String::from_utf8_lossy(&output.stdout).contains("RUNNING"))
+}
+
+/// Command line of a registered Windows service, from `sc qc`
+#[cfg(windows)]
+fn windows_service_command_line(name: &str) -> Option<String> {
+    let output = Command::new("sc").args(["qc", name]).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_binary_path_name(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(any(windows, test))]
+fn parse_binary_path_name(sc_qc_output: &str) -> Option<String> {
+    sc_qc_output.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == "BINARY_PATH_NAME").then(|| value.trim().to_string())
+    })
+}
+
+/// Register the service, or update it when its command line changed, with
+/// automatic start and restart-on-failure actions, then make sure it runs.
+/// A running service is restarted when its definition or `files_changed`.
+/// Returns `true` when anything was changed.
+#[cfg(windows)]
+pub fn install_windows_service(
+    definition: &WindowsServiceDefinition,
+    files_changed: bool,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let name = definition.name;
+    let registered = windows_service_command_line(name);
+    let changed = registered.as_deref() != Some(definition.command_line.as_str());
+
+    if changed {
+        let verb = if registered.is_some() {
+            "config"
+        } else {
+            "create"
+        };
+        run_sc(&definition.sc_config_args(verb))?;
+        run_sc(&definition.sc_failure_args())?;
+        // Also restart a service that stopped itself with an error, as the
+        // service host does when the program it runs exits
+        run_sc(&["failureflag", name, "1"].map(str::to_string))?;
+        run_sc(&["description", name, definition.description].map(str::to_string))?;
+    }
+
+    if windows_service_running(name) != Some(true) {
+        run_sc(&["start", name].map(str::to_string))?;
+    } else if changed || files_changed {
+        // `net stop` waits for the service to stop, unlike `sc stop`
+        recorder::run_command("net", &["stop", name])?;
+        run_sc(&["start", name].map(str::to_string))?;
+    } else {
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Stop and delete a Windows service registered with `install_windows_service`.
+/// Returns a description of everything that was removed.
+#[cfg(windows)]
+pub fn remove_windows_service(name: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    if windows_service_installed(name) != Some(true) {
+        return Ok(Vec::new());
+    }
+    recorder::run_command("net", &["stop", name])?;
+    run_sc(&["delete", name].map(str::to_string))?;
+    Ok(vec![format!("Windows service {name}")])
+}
+
+#[cfg(windows)]
+fn run_sc(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let output = recorder::run_command("sc", &args)?;
+    if !output.status.success() {
+        // sc reports errors on stdout
+        let error = String::from_utf8_lossy(&output.stdout);
+        return Err(format!("sc {} failed: {}", args[0], error.trim()).into());
+    }
+    Ok(())
+}
+
+/// Check whether a Windows scheduled task is registered.
+/// Returns `None` when `schtasks` is not available.
+#[cfg(windows)]
+pub fn scheduled_task_registered(name: &str) -> Option<bool> {
+    let output = Command::new("schtasks")
+        .args(["/Query", "/TN", name])
+        .output()
+        .ok()?;
+    Some(output.status.success())
+}
+
+/// Check whether a Windows scheduled task is currently running.
+/// Returns `None` when `schtasks` is not available.
+#[cfg(windows)]
+pub fn scheduled_task_running(name: &str) -> Option<bool> {
+    let output = Command::new("schtasks")
+        .args(["/Query", "/TN", name, "/FO", "LIST"])
+        .output()
+        .ok()?;
+    Some(output.status.success() && String::from_utf8_lossy(&output.stdout).contains("Running"))
+}
+
+/// Bring an installed systemd unit into the desired state.
+/// A stopped unit is enabled and started; a running one is restarted when its
+/// unit file or the files it runs changed, and otherwise left alone.
+/// Returns `true` when the unit was started or restarted.
+pub fn apply_systemd_unit(
+    scope: SystemdScope,
+    unit: &str,
+    unit_changed: bool,
+    files_changed: bool,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    if unit_changed {
+        scope.systemctl(&["daemon-reload"])?;
+    }
+    if systemd_unit_active(scope, unit) != Some(true) {
+        scope.systemctl(&["enable", "--now", unit])?;
+    } else if unit_changed || files_changed {
+        if unit_changed {
+            scope.systemctl(&["enable", unit])?;
+        }
+        scope.systemctl(&["restart", unit])?;
+    } else {
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Stop and disable a systemd unit, then delete its unit file.
+/// Returns a description of everything that was removed.
+pub fn remove_systemd_unit(
+    scope: SystemdScope,
+    unit: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut removed = Vec::new();
+    let unit_path = scope.unit_path(unit);
+
+    if !Path::new(&unit_path).exists() {
+        return Ok(removed);
+    }
+
+    let output = scope.systemctl(&["disable", "--now", unit])?;
+    if output.status.success() {
+        removed.push(format!("systemd service {unit} (stopped and disabled)"));
+    }
+
+    downloader::remove_path(&unit_path)?;
+    removed.push(unit_path);
+    scope.systemctl(&["daemon-reload"])?;
+
+    Ok(removed)
+}
+
+/// Whether the current user's systemd manager keeps running after they log
+/// out, as set by `loginctl enable-linger`.
+/// Returns `None` when the user name is unknown.
+pub fn user_lingering() -> Option<bool> {
+    let user = std::env::var("USER").ok()?;
+    Some(Path::new(SYSTEMD_LINGER_DIR).join(user).exists())
+}
+
+fn is_active_output(stdout: &str) -> bool {
+    stdout.trim() == "active"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hardening_directives() {
+        let strict = HardeningProfile::Strict.directives(&["/opt/prometheus"]);
+        for directive in [
+            "NoNewPrivileges=yes\n",
+            "ProtectSystem=strict\n",
+            "ProtectHome=read-only\n",
+            "PrivateTmp=yes\n",
+            "CapabilityBoundingSet=\n",
+            "ReadOnlyPaths=/opt/prometheus\n",
+        ] {
+            assert!(strict.contains(directive), "{directive}");
+        }
+        assert!(!strict.contains("AmbientCapabilities"));
+
+        let relaxed = HardeningProfile::Default.directives(&["/opt/a", "/opt/b"]);
+        assert!(relaxed.contains("ProtectSystem=full\n"));
+        assert!(relaxed.contains("CapabilityBoundingSet=CAP_SYS_PTRACE\n"));
+        assert!(relaxed.contains("ReadOnlyPaths=/opt/a /opt/b\n"));
+        assert!(!relaxed.contains("PrivateDevices"));
+
+        assert_eq!(HardeningProfile::None.directives(&["/opt/prometheus"]), "");
+        assert!(
+            !HardeningProfile::Strict
+                .directives(&[])
+                .contains("ReadOnlyPaths")
+        );
+    }
+
+    fn agent_service() -> WindowsServiceDefinition {
+        WindowsServiceDefinition {
+            name: "ProcessCpuAgent",
+            display_name: "Process CPU Agent",
+            description: "Process CPU Agent for Prometheus",
+            command_line: "\"C:\\agent\\process-cpu-agent.exe\" --port 9256".to_string(),
+            account: WindowsServiceAccount::NetworkService,
+        }
+    }
+
+    #[test]
+    fn test_sc_config_args() {
+        assert_eq!(
+            agent_service().sc_config_args("create"),
+            vec![
+                "create",
+                "ProcessCpuAgent",
+                "binPath=",
+                "\"C:\\agent\\process-cpu-agent.exe\" --port 9256",
+                "start=",
+                "auto",
+                "obj=",
+                "NT AUTHORITY\\NetworkService",
+                "DisplayName=",
+                "Process CPU Agent",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sc_failure_args() {
+        assert_eq!(
+            agent_service().sc_failure_args(),
+            vec![
+                "failure",
+                "ProcessCpuAgent",
+                "reset=",
+                "86400",
+                "actions=",
+                "restart/5000/restart/10000/restart/60000",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_binary_path_name() {
+        let output = "[SC] QueryServiceConfig SUCCESS\r\n\r\n\
+                      SERVICE_NAME: ProcessCpuAgent\r\n\
+                      \x20       START_TYPE         : 2   AUTO_START\r\n\
+                      \x20       BINARY_PATH_NAME   : \"C:\\agent\\process-cpu-agent.exe\" --port 9256\r\n\
+                      \x20       SERVICE_START_NAME : NT AUTHORITY\\LocalService\r\n";
+        assert_eq!(
+            parse_binary_path_name(output).as_deref(),
+            Some("\"C:\\agent\\process-cpu-agent.exe\" --port 9256")
+        );
+        assert_eq!(parse_binary_path_name("[SC] OpenService FAILED 1060"), None);
+    }
+
+    #[test]
+    fn test_is_active_output() {
+        assert!(is_active_output("active\n"));
+        assert!(!is_active_output("inactive\n"));
+        assert!(!is_active_output("activating\n"));
+        assert!(!is_active_output(""));
+    }
+
+    #[test]
+    fn test_apply_systemd_unit_starts_stopped_unit() {
+        let (result, actions) = recorder::dry_run(|| {
+            apply_systemd_unit(
+                SystemdScope::System,
+                "prometheus-agents-setup-nonexistent",
+                true,
+                false,
+            )
+        });
+        assert!(result.unwrap());
+        let commands: Vec<String> = actions.iter().map(|a| a.describe()).collect();
+        assert_eq!(
+            commands,
+            vec![
+                "run systemctl daemon-reload",
+                "run systemctl enable --now prometheus-agents-setup-nonexistent",
+            ]
+        );
+
+        // Unit file unchanged: no reload, only start it
+        let (result, actions) = recorder::dry_run(|| {
+            apply_systemd_unit(
+                SystemdScope::System,
+                "prometheus-agents-setup-nonexistent",
+                false,
+                false,
+            )
+        });
+        assert!(result.unwrap());
+        assert_eq!(actions.len(), 1);
+        assert_eq!(
+            actions[0].describe(),
+            "run systemctl enable --now prometheus-agents-setup-nonexistent"
+        );
+    }
+
+    #[test]
+    fn test_missing_service_is_not_running() {
+        // Either the service manager is unavailable or the unit does not exist
+        let state =
+            systemd_unit_active(SystemdScope::System, "prometheus-agents-setup-nonexistent");
+        assert_ne!(state, Some(true));
+    }
+
+    #[test]
+    fn test_user_scope_addresses_user_manager() {
+        let (result, actions) = recorder::dry_run(|| {
+            apply_systemd_unit(
+                SystemdScope::User,
+                "prometheus-agents-setup-nonexistent",
+                true,
+                false,
+            )
+        });
+        assert!(result.unwrap());
+        let commands: Vec<String> = actions.iter().map(|a| a.describe()).collect();
+        assert_eq!(
+            commands,
+            vec![
+                "run systemctl --user daemon-reload",
+                "run systemctl --user enable --now prometheus-agents-setup-nonexistent",
+            ]
+        );
+        assert!(
+            SystemdScope::User
+                .unit_path("node_exporter")
+                .ends_with("/systemd/user/node_exporter.service")
+        );
+    }
+}
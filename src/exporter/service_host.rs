@@ -0,0 +1,263 @@
+//! Windows service host for programs that cannot talk to the service
+//! control manager themselves. A copy of this tool is registered as the
+//! service; it starts the program from its install directory, reports it as
+//! running and stops it when the service is stopped.
+
+#[cfg(windows)]
+use crate::exporter::downloader;
+#[cfg(windows)]
+use crate::exporter::recorder::progress;
+
+/// File name of the copy of this tool hosting services, next to the program it runs
+#[cfg(any(windows, test))]
+pub const HOST_FILE_NAME: &str = "service-host.exe";
+
+/// Subcommand running the host, hidden from `--help`
+pub const HOST_SUBCOMMAND: &str = "service-host";
+
+/// Path of the service host installed in `install_path`
+#[cfg(any(windows, test))]
+pub fn host_path(install_path: &str) -> String {
+    format!("{install_path}\\{HOST_FILE_NAME}")
+}
+
+/// Service command line running `program` with `args` under the host in
+/// `install_path`, with `install_path` as the working directory
+#[cfg(any(windows, test))]
+pub fn command_line(name: &str, install_path: &str, program: &str, args: &[String]) -> String {
+    let mut words = vec![
+        // Always quoted, so no directory of the path can be mistaken for the program
+        format!("\"{}\"", host_path(install_path)),
+        HOST_SUBCOMMAND.to_string(),
+        "--name".to_string(),
+        quote(name),
+        "--working-dir".to_string(),
+        quote(install_path),
+        "--".to_string(),
+        quote(program),
+    ];
+    words.extend(args.iter().map(|arg| quote(arg)));
+    words.join(" ")
+}
+
+/// Quote a command line argument containing blanks or quotes, as parsed by
+/// `CommandLineToArgvW`
+#[cfg(any(windows, test))]
+fn quote(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+        return arg.to_string();
+    }
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                // Backslashes before a quote are escaped, and so is the quote
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                backslashes = 0;
+            }
+            _ => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                backslashes = 0;
+            }
+        }
+        if c != '\\' {
+            quoted.push(c);
+        }
+    }
+    // Backslashes before the closing quote are escaped too
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+/// Copy this tool into `install_path` as the service host.
+/// Returns `true` when the host was written.
+#[cfg(windows)]
+pub fn install(install_path: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    let host = host_path(install_path);
+    let content = std::fs::read(std::env::current_exe()?)?;
+    let written = downloader::write_file_if_changed(&host, &content)?;
+    if written {
+        progress!("Service host written: {host}");
+    }
+    Ok(written)
+}
+
+/// Run `command` from `working_dir` as the Windows service `name` until the
+/// service is stopped. Only returns once the service stopped.
+#[cfg(windows)]
+pub fn run(
+    name: &str,
+    working_dir: &str,
+    command: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    scm::run(name, working_dir, command)
+}
+
+#[cfg(not(windows))]
+pub fn run(
+    _name: &str,
+    _working_dir: &str,
+    _command: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err("The service host only runs on Windows".into())
+}
+
+#[cfg(windows)]
+mod scm {
+    use std::ffi::OsString;
+    use std::process::{Child, Command};
+    use std::sync::OnceLock;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use windows_service::service::{
+        ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
+        ServiceType,
+    };
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+    use windows_service::{define_windows_service, service_dispatcher};
+
+    /// How often the hosted program is checked for having exited
+    const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+    struct Hosted {
+        name: String,
+        working_dir: String,
+        command: Vec<String>,
+    }
+
+    // The dispatcher calls the service entry point without arguments of ours
+    static HOSTED: OnceLock<Hosted> = OnceLock::new();
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    pub fn run(
+        name: &str,
+        working_dir: &str,
+        command: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if command.is_empty() {
+            return Err("No program to run as a service".into());
+        }
+        let _ = HOSTED.set(Hosted {
+            name: name.to_string(),
+            working_dir: working_dir.to_string(),
+            command: command.to_vec(),
+        });
+        service_dispatcher::start(name, ffi_service_main)?;
+        Ok(())
+    }
+
+    fn service_main(_arguments: Vec<OsString>) {
+        let Some(hosted) = HOSTED.get() else {
+            return;
+        };
+        // Without a console there is nowhere to report to but the exit code
+        let _ = run_service(hosted);
+    }
+
+    fn run_service(hosted: &Hosted) -> Result<(), Box<dyn std::error::Error>> {
+        let (stop_tx, stop_rx) = mpsc::channel();
+        let handler = move |control| match control {
+            ServiceControl::Stop => {
+                let _ = stop_tx.send(());
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        };
+        let status_handle = service_control_handler::register(&hosted.name, handler)?;
+        let set_state = |state: ServiceState, exit_code: ServiceExitCode| {
+            status_handle.set_service_status(ServiceStatus {
+                service_type: ServiceType::OWN_PROCESS,
+                current_state: state,
+                controls_accepted: if state == ServiceState::Running {
+                    ServiceControlAccept::STOP
+                } else {
+                    ServiceControlAccept::empty()
+                },
+                exit_code,
+                checkpoint: 0,
+                wait_hint: Duration::default(),
+                process_id: None,
+            })
+        };
+
+        let mut child = match spawn(hosted) {
+            Ok(child) => child,
+            Err(e) => {
+                let code = e.raw_os_error().unwrap_or(1) as u32;
+                set_state(ServiceState::Stopped, ServiceExitCode::Win32(code))?;
+                return Err(e.into());
+            }
+        };
+        set_state(ServiceState::Running, ServiceExitCode::Win32(0))?;
+
+        let exit_code = loop {
+            if stop_rx.recv_timeout(POLL_INTERVAL).is_ok() {
+                let _ = child.kill();
+                let _ = child.wait();
+                break ServiceExitCode::Win32(0);
+            }
+            if let Some(status) = child.try_wait()? {
+                // Reported as a failure so the recovery actions restart it
+                let code = status.code().filter(|code| *code != 0).unwrap_or(1);
+                break ServiceExitCode::ServiceSpecific(code as u32);
+            }
+        };
+        set_state(ServiceState::Stopped, exit_code)?;
+        Ok(())
+    }
+
+    fn spawn(hosted: &Hosted) -> std::io::Result<Child> {
+        Command::new(&hosted.command[0])
+            .args(&hosted.command[1..])
+            .current_dir(&hosted.working_dir)
+            .spawn()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_line() {
+        assert_eq!(
+            command_line(
+                "ProcessCpuAgent",
+                "C:\\ProgramData\\prometheus\\process-cpu-agent",
+                "C:\\ProgramData\\prometheus\\process-cpu-agent\\process-cpu-agent.exe",
+                &["--port".to_string(), "31416".to_string()],
+            ),
+            "\"C:\\ProgramData\\prometheus\\process-cpu-agent\\service-host.exe\" service-host \
+             --name ProcessCpuAgent --working-dir C:\\ProgramData\\prometheus\\process-cpu-agent \
+             -- C:\\ProgramData\\prometheus\\process-cpu-agent\\process-cpu-agent.exe --port 31416"
+        );
+    }
+
+    #[test]
+    fn test_command_line_quotes_paths_with_blanks() {
+        let line = command_line(
+            "Agent",
+            "C:\\Program Files\\agent",
+            "C:\\a b\\agent.exe",
+            &[],
+        );
+        assert_eq!(
+            line,
+            "\"C:\\Program Files\\agent\\service-host.exe\" service-host --name Agent \
+             --working-dir \"C:\\Program Files\\agent\" -- \"C:\\a b\\agent.exe\""
+        );
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("plain"), "plain");
+        assert_eq!(quote(""), "\"\"");
+        assert_eq!(quote("a \"b\""), "\"a \\\"b\\\"\"");
+        assert_eq!(quote("C:\\dir with space\\"), "\"C:\\dir with space\\\\\"");
+    }
+}
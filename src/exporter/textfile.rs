@@ -0,0 +1,363 @@
+use crate::exporter::downloader;
+use crate::exporter::init_system::systemd_quote;
+use crate::exporter::recorder::{self, progress};
+use crate::exporter::service::{self, SystemdScope};
+use serde::Deserialize;
+use std::path::Path;
+
+/// Directory node_exporter's textfile collector reads `.prom` files from
+pub const DEFAULT_TEXTFILE_DIR: &str = "/var/lib/node_exporter/textfile_collector";
+/// Prefix of the systemd units running the producers
+const UNIT_PREFIX: &str = "node-exporter-textfile-";
+
+/// Bundled job writing metrics for the textfile collector on a systemd timer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TextfileProducer {
+    /// Pending apt upgrades, security ones counted separately
+    AptUpdates,
+    /// Pending dnf or yum updates and security advisories
+    YumUpdates,
+    /// Whether installed updates wait for a reboot
+    RebootRequired,
+    /// SMART overall health of each disk
+    SmartSummary,
+}
+
+impl TextfileProducer {
+    pub const ALL: [Self; 4] = [
+        Self::AptUpdates,
+        Self::YumUpdates,
+        Self::RebootRequired,
+        Self::SmartSummary,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::AptUpdates => "apt-updates",
+            Self::YumUpdates => "yum-updates",
+            Self::RebootRequired => "reboot-required",
+            Self::SmartSummary => "smart-summary",
+        }
+    }
+
+    fn script(self) -> &'static str {
+        match self {
+            Self::AptUpdates => include_str!("../../lib/textfile/apt-updates.sh"),
+            Self::YumUpdates => include_str!("../../lib/textfile/yum-updates.sh"),
+            Self::RebootRequired => include_str!("../../lib/textfile/reboot-required.sh"),
+            Self::SmartSummary => include_str!("../../lib/textfile/smart-summary.sh"),
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Self::AptUpdates => "pending apt upgrades",
+            Self::YumUpdates => "pending yum updates",
+            Self::RebootRequired => "reboot required",
+            Self::SmartSummary => "SMART disk health",
+        }
+    }
+
+    /// How often the job runs, as a systemd time span
+    fn interval(self) -> &'static str {
+        match self {
+            Self::AptUpdates | Self::YumUpdates | Self::SmartSummary => "1h",
+            Self::RebootRequired => "15min",
+        }
+    }
+
+    /// Program the job cannot do without; dnf or yum are checked by the script itself
+    pub fn required_tool(self) -> Option<&'static str> {
+        match self {
+            Self::AptUpdates => Some("apt-get"),
+            Self::SmartSummary => Some("smartctl"),
+            Self::YumUpdates | Self::RebootRequired => None,
+        }
+    }
+
+    /// Whether the job only works as root; `smartctl -H` cannot open the
+    /// drives otherwise
+    pub fn needs_root(self) -> bool {
+        matches!(self, Self::SmartSummary)
+    }
+
+    /// File the job writes into the textfile directory
+    fn output_file(self) -> &'static str {
+        match self {
+            Self::AptUpdates => "apt_updates.prom",
+            Self::YumUpdates => "yum_updates.prom",
+            Self::RebootRequired => "reboot_required.prom",
+            Self::SmartSummary => "smart_summary.prom",
+        }
+    }
+
+    fn unit(self) -> String {
+        format!("{UNIT_PREFIX}{}", self.name())
+    }
+}
+
+/// Create the textfile directory, readable by node_exporter and writable by
+/// the producers running as its owner.
+/// Returns `true` when it was created.
+pub fn ensure_directory(dir: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    if Path::new(dir).is_dir() {
+        return Ok(false);
+    }
+    downloader::ensure_directory_exists(dir)?;
+    #[cfg(unix)]
+    downloader::set_permissions(dir, 0o755)?;
+    progress!("Textfile collector directory created at: {dir}");
+    Ok(true)
+}
+
+/// Oneshot service running the `producer` script at `script_path` against
+/// `dir`, both quoted since a home directory may contain blanks
+pub fn render_service(producer: TextfileProducer, script_path: &str, dir: &str) -> String {
+    format!(
+        r#"[Unit]
+Description=node_exporter textfile metrics: {description}
+
+[Service]
+Type=oneshot
+ExecStart=/bin/sh {script_path} {dir}
+Nice=10
+"#,
+        description = producer.description(),
+        script_path = systemd_quote(script_path),
+        dir = systemd_quote(dir)
+    )
+}
+
+/// Timer starting the `producer` service shortly after boot and then every interval
+pub fn render_timer(producer: TextfileProducer) -> String {
+    format!(
+        r#"[Unit]
+Description=Run {unit} every {interval}
+
+[Timer]
+OnBootSec=2min
+OnUnitActiveSec={interval}
+RandomizedDelaySec=1min
+
+[Install]
+WantedBy=timers.target
+"#,
+        unit = producer.unit(),
+        interval = producer.interval()
+    )
+}
+
+/// Install the `producers` into `script_dir` with their timers in `scope`,
+/// writing into the textfile directory `dir`. Producers installed earlier
+/// but no longer listed are removed.
+/// Returns `true` when anything was changed.
+pub fn install_producers(
+    producers: &[TextfileProducer],
+    scope: SystemdScope,
+    script_dir: &str,
+    dir: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut updated = Vec::new();
+    for &producer in producers {
+        downloader::ensure_directory_exists(script_dir)?;
+        let script_path = script_path(script_dir, producer);
+        let script_changed =
+            downloader::write_file_if_changed(&script_path, producer.script().as_bytes())?;
+        #[cfg(unix)]
+        if script_changed {
+            downloader::set_executable_permissions(&script_path)?;
+        }
+
+        let (service_path, timer_path) = unit_paths(scope, producer);
+        if let Some(parent) = downloader::get_parent_directory(&service_path) {
+            downloader::ensure_directory_exists(&parent)?;
+        }
+        let service = render_service(producer, &script_path, dir);
+        let service_changed = downloader::write_file_if_changed(&service_path, service.as_bytes())?;
+        let timer_changed =
+            downloader::write_file_if_changed(&timer_path, render_timer(producer).as_bytes())?;
+        if script_changed || service_changed || timer_changed {
+            progress!(
+                "Textfile producer {} installed with timer {timer_path}",
+                producer.name()
+            );
+        }
+        updated.push((producer, service_changed || timer_changed, script_changed));
+    }
+
+    let mut changed = updated.iter().any(|&(_, units, script)| units || script);
+    if updated.iter().any(|&(_, units, _)| units) {
+        systemctl(scope, &["daemon-reload"])?;
+    }
+    for (producer, _, files_changed) in updated {
+        let timer = format!("{}.timer", producer.unit());
+        changed |= service::apply_systemd_unit(scope, &timer, false, files_changed)?;
+    }
+
+    let unlisted: Vec<TextfileProducer> = TextfileProducer::ALL
+        .into_iter()
+        .filter(|producer| !producers.contains(producer))
+        .collect();
+    changed |= !remove(&unlisted, scope, script_dir, dir)?.is_empty();
+    Ok(changed)
+}
+
+/// Stop every producer installed in `scope` and delete its units, script
+/// and metrics file.
+/// Returns a description of everything that was removed.
+pub fn remove_producers(
+    scope: SystemdScope,
+    script_dir: &str,
+    dir: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    remove(&TextfileProducer::ALL, scope, script_dir, dir)
+}
+
+fn remove(
+    producers: &[TextfileProducer],
+    scope: SystemdScope,
+    script_dir: &str,
+    dir: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut removed = Vec::new();
+    for &producer in producers {
+        let (service_path, timer_path) = unit_paths(scope, producer);
+        if !downloader::path_exists(&timer_path) {
+            continue;
+        }
+        let timer = format!("{}.timer", producer.unit());
+        if systemctl(scope, &["disable", "--now", &timer]).is_ok() {
+            removed.push(format!("systemd timer {timer} (stopped and disabled)"));
+        }
+        for path in [
+            timer_path,
+            service_path,
+            script_path(script_dir, producer),
+            format!("{dir}/{}", producer.output_file()),
+        ] {
+            if downloader::remove_path(&path)? {
+                removed.push(path);
+            }
+        }
+    }
+    if !removed.is_empty() {
+        systemctl(scope, &["daemon-reload"])?;
+    }
+    Ok(removed)
+}
+
+fn script_path(script_dir: &str, producer: TextfileProducer) -> String {
+    format!("{script_dir}/{}.sh", producer.name())
+}
+
+/// Service and timer unit files of `producer`
+fn unit_paths(scope: SystemdScope, producer: TextfileProducer) -> (String, String) {
+    let unit = format!("{}/{}", scope.unit_dir(), producer.unit());
+    (format!("{unit}.service"), format!("{unit}.timer"))
+}
+
+fn systemctl(scope: SystemdScope, args: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+    let output = recorder::run_command("systemctl", &scope.systemctl_args(args))?;
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("systemctl {} failed: {}", args.join(" "), error.trim()).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::recorder::Action;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_producers_parse_and_write_atomically() {
+        let producers: Vec<TextfileProducer> =
+            serde_json::from_str(r#"["apt-updates", "smart-summary"]"#).unwrap();
+        assert_eq!(
+            producers,
+            vec![TextfileProducer::AptUpdates, TextfileProducer::SmartSummary]
+        );
+        for producer in TextfileProducer::ALL {
+            let script = producer.script();
+            assert!(script.starts_with("#!/bin/sh\n"), "{}", producer.name());
+            // Written next to the target and renamed, so scrapes never see partial files
+            assert!(script.contains(&format!("out=\"$dir/{}\"", producer.output_file())));
+            assert!(script.contains("mv \"$tmp\" \"$out\""));
+        }
+    }
+
+    #[test]
+    fn test_render_units() {
+        let service = render_service(
+            TextfileProducer::RebootRequired,
+            "/opt/prometheus/node_exporter/textfile-producers/reboot-required.sh",
+            DEFAULT_TEXTFILE_DIR,
+        );
+        assert!(service.contains("Type=oneshot\n"));
+        assert!(service.contains(
+            "ExecStart=/bin/sh /opt/prometheus/node_exporter/textfile-producers/reboot-required.sh \
+             /var/lib/node_exporter/textfile_collector\n"
+        ));
+
+        let service = render_service(
+            TextfileProducer::AptUpdates,
+            "/home/jo doe/.local/prometheus/producers/apt-updates.sh",
+            "/home/jo doe/100%/textfile",
+        );
+        assert!(service.contains(
+            "ExecStart=/bin/sh \"/home/jo doe/.local/prometheus/producers/apt-updates.sh\" \
+             \"/home/jo doe/100%%/textfile\"\n"
+        ));
+
+        let timer = render_timer(TextfileProducer::RebootRequired);
+        assert!(
+            timer.contains("Description=Run node-exporter-textfile-reboot-required every 15min\n")
+        );
+        assert!(timer.contains("OnUnitActiveSec=15min\n"));
+        assert!(timer.ends_with("WantedBy=timers.target\n"));
+    }
+
+    #[test]
+    fn test_ensure_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("textfile_collector");
+        let dir = dir.to_str().unwrap();
+        assert!(ensure_directory(dir).unwrap());
+        assert!(!ensure_directory(dir).unwrap());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(dir).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o755);
+        }
+    }
+
+    #[test]
+    fn test_install_producers_dry_run() {
+        let temp_dir = TempDir::new().unwrap();
+        let script_dir = temp_dir.path().to_str().unwrap();
+        let (result, actions) = recorder::dry_run(|| {
+            install_producers(
+                &[TextfileProducer::AptUpdates],
+                SystemdScope::System,
+                script_dir,
+                DEFAULT_TEXTFILE_DIR,
+            )
+        });
+        assert!(result.unwrap());
+        let timer = "/etc/systemd/system/node-exporter-textfile-apt-updates.timer";
+        assert!(actions.contains(&Action::WriteFile {
+            path: format!("{script_dir}/apt-updates.sh"),
+        }));
+        assert!(actions.contains(&Action::WriteFile {
+            path: timer.to_string(),
+        }));
+        assert!(actions.contains(&Action::RunCommand {
+            program: "systemctl".to_string(),
+            args: vec!["daemon-reload".to_string()],
+        }));
+    }
+}
@@ -0,0 +1,339 @@
+use crate::config::WebSettings;
+use crate::exporter::recorder::progress;
+use crate::exporter::{downloader, yaml_quote};
+use crate::os_detector;
+use pwhash::bcrypt::{self, BcryptSetup, BcryptVariant};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// File name of the exporter-toolkit web configuration passed with `--web.config.file`
+pub const WEB_CONFIG_FILE: &str = "web-config.yml";
+const SELF_SIGNED_CERT_FILE: &str = "web-tls.crt";
+const SELF_SIGNED_KEY_FILE: &str = "web-tls.key";
+/// Cost exporter-toolkit's documentation recommends; each scrape pays for it
+const BCRYPT_COST: u32 = 10;
+
+/// Basic auth users and TLS certificate of the exporters' metrics endpoints
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebConfig {
+    /// User names with plain passwords or bcrypt hashes
+    users: BTreeMap<String, String>,
+    cert_file: Option<String>,
+    key_file: Option<String>,
+    self_signed: bool,
+}
+
+impl WebConfig {
+    /// `None` when the settings neither add users nor enable TLS
+    pub fn from_settings(settings: &WebSettings) -> Option<Self> {
+        let config = Self {
+            users: settings.basic_auth_users.clone().unwrap_or_default(),
+            cert_file: settings.tls_cert_file.clone(),
+            key_file: settings.tls_key_file.clone(),
+            self_signed: settings.self_signed_cert.unwrap_or(false),
+        };
+        (!config.users.is_empty() || config.tls_enabled()).then_some(config)
+    }
+
+    pub fn tls_enabled(&self) -> bool {
+        self.self_signed || self.cert_file.is_some()
+    }
+
+    /// Whether the certificate may not be trusted by scrapers, so they have to skip verification
+    pub fn self_signed(&self) -> bool {
+        self.self_signed
+    }
+
+    /// User Prometheus scrapes as
+    pub fn username(&self) -> Option<&str> {
+        self.users.keys().next().map(String::as_str)
+    }
+
+    /// A user whose plain password is known, for checking the endpoints after install
+    pub fn credentials(&self) -> Option<(&str, &str)> {
+        self.users
+            .iter()
+            .find(|(_, secret)| !is_bcrypt_hash(secret))
+            .map(|(user, password)| (user.as_str(), password.as_str()))
+    }
+
+    /// Path of the web configuration of an exporter installed in `dir`
+    pub fn path(dir: &str) -> String {
+        join(dir, WEB_CONFIG_FILE)
+    }
+
+    /// Certificate and key files, generated into `dir` unless configured
+    fn tls_files(&self, dir: &str) -> Option<(String, String)> {
+        match (&self.cert_file, &self.key_file) {
+            (Some(cert), Some(key)) => Some((cert.clone(), key.clone())),
+            _ if self.self_signed => Some((
+                join(dir, SELF_SIGNED_CERT_FILE),
+                join(dir, SELF_SIGNED_KEY_FILE),
+            )),
+            _ => None,
+        }
+    }
+
+    /// Files `write` may create in `dir`, for uninstalling
+    pub fn generated_files(dir: &str) -> Vec<String> {
+        [WEB_CONFIG_FILE, SELF_SIGNED_CERT_FILE, SELF_SIGNED_KEY_FILE]
+            .iter()
+            .map(|file| join(dir, file))
+            .collect()
+    }
+
+    /// Write `web-config.yml` into `dir`, generating the self-signed
+    /// certificate first if it is missing. Hashes of unchanged passwords are
+    /// kept, so re-runs do not rewrite the file. The files holding secrets
+    /// are made readable by `group` only, which the exporter runs as.
+    /// Returns `true` when anything was written.
+    pub fn write(
+        &self,
+        dir: &str,
+        group: Option<&str>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        downloader::ensure_directory_exists(dir)?;
+        let tls_files = self.tls_files(dir);
+        let mut changed = false;
+        if self.self_signed
+            && let Some((cert, key)) = &tls_files
+            && !downloader::path_exists(cert)
+        {
+            generate_self_signed(cert, key)?;
+            restrict(key, group)?;
+            progress!("Self-signed TLS certificate generated at: {cert}");
+            changed = true;
+        }
+
+        let path = Self::path(dir);
+        let existing = fs::read_to_string(&path).unwrap_or_default();
+        let hashes = self.hash_passwords(&parse_users(&existing))?;
+        let content = render(&hashes, tls_files.as_ref());
+        if downloader::write_file_if_changed(&path, content.as_bytes())? {
+            restrict(&path, group)?;
+            progress!("Web configuration written to: {path}");
+            changed = true;
+        }
+        Ok(changed)
+    }
+
+    /// bcrypt hash of every user's password, reusing the `existing` hash when it still matches
+    fn hash_passwords(
+        &self,
+        existing: &BTreeMap<String, String>,
+    ) -> Result<BTreeMap<String, String>, pwhash::error::Error> {
+        let mut hashes = BTreeMap::new();
+        for (user, secret) in &self.users {
+            let hash = match existing.get(user) {
+                _ if is_bcrypt_hash(secret) => secret.clone(),
+                Some(hash) if bcrypt::verify(secret, hash) => hash.clone(),
+                _ => hash_password(secret)?,
+            };
+            hashes.insert(user.clone(), hash);
+        }
+        Ok(hashes)
+    }
+}
+
+/// Whether `secret` is already a bcrypt hash rather than a plain password
+pub fn is_bcrypt_hash(secret: &str) -> bool {
+    ["$2a$", "$2b$", "$2y$"]
+        .iter()
+        .any(|prefix| secret.starts_with(prefix))
+        && secret.len() == 60
+}
+
+fn hash_password(password: &str) -> Result<String, pwhash::error::Error> {
+    bcrypt::hash_with(
+        BcryptSetup {
+            salt: None,
+            cost: Some(BCRYPT_COST),
+            variant: Some(BcryptVariant::V2b),
+        },
+        password,
+    )
+}
+
+/// Web configuration with the bcrypt `hashes` of the basic auth users
+fn render(hashes: &BTreeMap<String, String>, tls_files: Option<&(String, String)>) -> String {
+    let mut yaml = String::from("# Generated by prometheus-agents-setup\n");
+    if let Some((cert, key)) = tls_files {
+        yaml.push_str(&format!(
+            "tls_server_config:\n  cert_file: {}\n  key_file: {}\n",
+            yaml_quote(cert),
+            yaml_quote(key)
+        ));
+    }
+    if !hashes.is_empty() {
+        yaml.push_str("basic_auth_users:\n");
+        for (user, hash) in hashes {
+            yaml.push_str(&format!("  {}: {}\n", yaml_quote(user), yaml_quote(hash)));
+        }
+    }
+    yaml
+}
+
+/// Users and hashes in a web configuration written by `render`
+fn parse_users(content: &str) -> BTreeMap<String, String> {
+    let unquote = |value: &str| serde_json::from_str::<String>(value.trim()).ok();
+    content
+        .lines()
+        .skip_while(|line| *line != "basic_auth_users:")
+        .skip(1)
+        .take_while(|line| line.starts_with("  "))
+        .filter_map(|line| {
+            let (user, hash) = line.trim().split_once("\": ")?;
+            Some((unquote(&format!("{user}\""))?, unquote(hash)?))
+        })
+        .collect()
+}
+
+/// Write a self-signed certificate for this host's name and addresses
+fn generate_self_signed(cert_path: &str, key_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+    names.extend(os_detector::hostname());
+    names.extend(os_detector::primary_ip().map(|ip| ip.to_string()));
+    let certified = rcgen::generate_simple_self_signed(names)?;
+
+    for path in [cert_path, key_path] {
+        if let Some(parent) = downloader::get_parent_directory(path) {
+            downloader::ensure_directory_exists(&parent)?;
+        }
+    }
+    // The key is restricted before it gets any content
+    downloader::write_file(key_path, b"")?;
+    restrict(key_path, None)?;
+    downloader::write_file(key_path, certified.key_pair.serialize_pem().as_bytes())?;
+    downloader::write_file(cert_path, certified.cert.pem().as_bytes())?;
+    Ok(())
+}
+
+/// Make a file holding secrets readable by its owner and `group` only
+#[cfg(unix)]
+fn restrict(path: &str, group: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    match group {
+        Some(group) => {
+            crate::exporter::account::share_with_group(path, group)?;
+        }
+        None => downloader::set_permissions(path, 0o600)?,
+    }
+    Ok(())
+}
+
+/// Make a file holding secrets accessible to SYSTEM, which windows_exporter
+/// runs as, and Administrators only, instead of inheriting read access for
+/// every user from the install directory
+#[cfg(windows)]
+fn restrict(path: &str, _group: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    crate::exporter::account::restrict_windows_acl(path, None)
+}
+
+fn join(dir: &str, file_name: &str) -> String {
+    Path::new(dir)
+        .join(file_name)
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const HASH: &str = "$2b$10$wUVOwnfLyjkI6cOg4SFoIebu/EDFQuBJv9M7z0wCeXHCX9bHhWV2O";
+
+    fn web_config(users: &[(&str, &str)], self_signed: bool) -> WebConfig {
+        WebConfig::from_settings(&WebSettings {
+            basic_auth_users: Some(
+                users
+                    .iter()
+                    .map(|(user, secret)| (user.to_string(), secret.to_string()))
+                    .collect(),
+            ),
+            self_signed_cert: Some(self_signed),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_from_settings() {
+        assert_eq!(WebConfig::from_settings(&WebSettings::default()), None);
+
+        let config = web_config(&[("alice", HASH), ("prometheus", "secret")], false);
+        assert!(!config.tls_enabled());
+        assert_eq!(config.username(), Some("alice"));
+        assert_eq!(config.credentials(), Some(("prometheus", "secret")));
+        assert!(is_bcrypt_hash(HASH));
+        assert!(!is_bcrypt_hash("$2b$10$short"));
+    }
+
+    #[test]
+    fn test_render_and_parse_users() {
+        let hashes = BTreeMap::from([("prometheus".to_string(), HASH.to_string())]);
+        let tls = (
+            "/etc/exporter.crt".to_string(),
+            "/etc/exporter.key".to_string(),
+        );
+        let yaml = render(&hashes, Some(&tls));
+        assert_eq!(
+            yaml,
+            format!(
+                "# Generated by prometheus-agents-setup\n\
+                 tls_server_config:\n  cert_file: \"/etc/exporter.crt\"\n  \
+                 key_file: \"/etc/exporter.key\"\n\
+                 basic_auth_users:\n  \"prometheus\": \"{HASH}\"\n"
+            )
+        );
+        assert_eq!(parse_users(&yaml), hashes);
+        assert!(parse_users("").is_empty());
+    }
+
+    #[test]
+    fn test_write_keeps_matching_hashes() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().to_str().unwrap();
+        let config = web_config(&[("prometheus", "secret")], false);
+
+        assert!(config.write(dir, None).unwrap());
+        let content = fs::read_to_string(WebConfig::path(dir)).unwrap();
+        let hash = &parse_users(&content)["prometheus"];
+        assert!(hash.starts_with("$2b$10$"));
+        assert!(bcrypt::verify("secret", hash));
+        assert!(!config.write(dir, None).unwrap());
+
+        let changed = web_config(&[("prometheus", "other")], false);
+        assert!(changed.write(dir, None).unwrap());
+    }
+
+    #[test]
+    fn test_write_generates_self_signed_certificate() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().to_str().unwrap();
+        let config = web_config(&[], true);
+
+        assert!(config.write(dir, None).unwrap());
+        let cert = fs::read_to_string(temp_dir.path().join(SELF_SIGNED_CERT_FILE)).unwrap();
+        assert!(cert.starts_with("-----BEGIN CERTIFICATE-----"));
+        let key_path = temp_dir.path().join(SELF_SIGNED_KEY_FILE);
+        assert!(
+            fs::read_to_string(&key_path)
+                .unwrap()
+                .contains("PRIVATE KEY")
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&key_path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let content = fs::read_to_string(WebConfig::path(dir)).unwrap();
+        assert!(content.contains("web-tls.key"));
+        assert!(!content.contains("basic_auth_users"));
+
+        // The existing certificate is kept on re-runs
+        assert!(!config.write(dir, None).unwrap());
+        assert_eq!(WebConfig::generated_files(dir).len(), 3);
+    }
+}
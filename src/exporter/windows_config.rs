@@ -0,0 +1,206 @@
+use crate::exporter::{listen, yaml_quote};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+/// Collectors enabled when the installer config names none
+pub const DEFAULT_COLLECTORS: &[&str] = &[
+    "cpu",
+    "cs",
+    "logical_disk",
+    "net",
+    "os",
+    "service",
+    "system",
+    "textfile",
+    "process",
+    "memory",
+    "thermalzone",
+];
+/// Collectors of windows_exporter 0.25 filtered with a WQL `WHERE` clause,
+/// and the option taking it
+const WHERE_OPTIONS: &[(&str, &str)] = &[("service", "services-where"), ("msmq", "msmq-where")];
+/// Services this installer manages, so the service collector skips the rest
+const DEFAULT_SERVICES_WHERE: &str = "Name='windows_exporter' OR Name='ProcessCpuAgent'";
+
+/// windows_exporter settings, written to its YAML config file and passed to
+/// the MSI as properties so both always agree
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowsExporterConfig {
+    pub collectors: Vec<String>,
+    /// WQL `WHERE` clauses keyed by collector, such as `service`
+    pub where_clauses: BTreeMap<String, String>,
+    pub port: u16,
+    /// Interface to listen on, or every interface with `None`
+    pub listen_address: Option<IpAddr>,
+    /// Directory the textfile collector reads `.prom` files from
+    pub textfile_dir: String,
+}
+
+impl WindowsExporterConfig {
+    /// Default collectors listening on `port`, with the textfile collector
+    /// reading `textfile_dir`
+    pub fn new(port: u16, textfile_dir: String) -> Self {
+        Self {
+            collectors: DEFAULT_COLLECTORS.iter().map(|c| c.to_string()).collect(),
+            where_clauses: [("service".to_string(), DEFAULT_SERVICES_WHERE.to_string())].into(),
+            port,
+            listen_address: None,
+            textfile_dir,
+        }
+    }
+
+    fn enabled(&self, collector: &str) -> bool {
+        self.collectors.iter().any(|enabled| enabled == collector)
+    }
+
+    /// Directory of the textfile collector, `None` when it is not enabled
+    pub fn textfile_dir(&self) -> Option<&str> {
+        self.enabled("textfile")
+            .then_some(self.textfile_dir.as_str())
+    }
+
+    /// `WHERE` clauses of enabled collectors with the option taking each
+    fn where_options(&self) -> impl Iterator<Item = (&str, &str, &str)> {
+        self.where_clauses
+            .iter()
+            .filter(|(collector, _)| self.enabled(collector))
+            .filter_map(|(collector, clause)| {
+                let option = where_option(collector)?;
+                Some((collector.as_str(), option, clause.as_str()))
+            })
+    }
+
+    /// The windows_exporter YAML configuration file
+    pub fn to_yaml(&self) -> String {
+        let mut yaml = String::from("# Generated by prometheus-agents-setup\n");
+        yaml.push_str(&format!(
+            "collectors:\n  enabled: {}\n",
+            yaml_quote(&self.collectors.join(","))
+        ));
+
+        let mut collector = String::new();
+        for (name, option, clause) in self.where_options() {
+            collector.push_str(&format!(
+                "  {name}:\n    {option}: {}\n",
+                yaml_quote(clause)
+            ));
+        }
+        if let Some(dir) = self.textfile_dir() {
+            collector.push_str(&format!(
+                "  textfile:\n    directories: {}\n",
+                yaml_quote(dir)
+            ));
+        }
+        if !collector.is_empty() {
+            yaml.push_str("collector:\n");
+            yaml.push_str(&collector);
+        }
+
+        yaml.push_str(&format!(
+            "web:\n  listen-address: {}\n",
+            yaml_quote(&listen::bind_address(self.listen_address, self.port))
+        ));
+        yaml
+    }
+
+    /// MSI properties matching the YAML, with the service pointed at it
+    /// through `CONFIG_FILE`
+    pub fn msi_properties(&self, config_file: &str) -> Vec<String> {
+        // Follow upstream MSI semantics: properties are passed as
+        //   ENABLED_COLLECTORS=...
+        //   LISTEN_PORT=...
+        // without extra quoting, matching README examples.
+        let mut properties = vec![
+            format!("LISTEN_PORT={}", self.port),
+            format!("ENABLED_COLLECTORS={}", self.collectors.join(",")),
+        ];
+        if let Some(ip) = self.listen_address {
+            properties.push(format!("LISTEN_ADDR={ip}"));
+        }
+        if let Some(dir) = self.textfile_dir() {
+            properties.push(format!("TEXTFILE_DIRS={dir}"));
+        }
+        properties.push(format!("CONFIG_FILE={config_file}"));
+        properties
+    }
+}
+
+/// Option of `collector` taking a `WHERE` clause, `None` when it has none
+pub fn where_option(collector: &str) -> Option<&'static str> {
+    WHERE_OPTIONS
+        .iter()
+        .find(|(name, _)| *name == collector)
+        .map(|(_, option)| *option)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> WindowsExporterConfig {
+        WindowsExporterConfig::new(31415, "C:\\prometheus\\textfile_inputs".to_string())
+    }
+
+    #[test]
+    fn test_default_yaml() {
+        assert_eq!(
+            config().to_yaml(),
+            r#"# Generated by prometheus-agents-setup
+collectors:
+  enabled: "cpu,cs,logical_disk,net,os,service,system,textfile,process,memory,thermalzone"
+collector:
+  service:
+    services-where: "Name='windows_exporter' OR Name='ProcessCpuAgent'"
+  textfile:
+    directories: "C:\\prometheus\\textfile_inputs"
+web:
+  listen-address: ":31415"
+"#
+        );
+    }
+
+    #[test]
+    fn test_disabled_collectors_are_left_out() {
+        let config = WindowsExporterConfig {
+            collectors: vec!["cpu".to_string(), "msmq".to_string()],
+            where_clauses: [
+                ("msmq".to_string(), "Name LIKE 'orders%'".to_string()),
+                ("service".to_string(), "StartMode='Auto'".to_string()),
+            ]
+            .into(),
+            listen_address: Some("10.0.0.5".parse().unwrap()),
+            ..config()
+        };
+        let yaml = config.to_yaml();
+        assert!(yaml.contains("collector:\n  msmq:\n    msmq-where: \"Name LIKE 'orders%'\"\n"));
+        assert!(!yaml.contains("services-where"));
+        assert!(!yaml.contains("textfile"));
+        assert!(yaml.ends_with("listen-address: \"10.0.0.5:31415\"\n"));
+        assert_eq!(config.textfile_dir(), None);
+    }
+
+    #[test]
+    fn test_msi_properties_match_yaml() {
+        let config = config();
+        assert_eq!(
+            config.msi_properties("C:\\prometheus\\windows_exporter.yml"),
+            vec![
+                "LISTEN_PORT=31415".to_string(),
+                format!("ENABLED_COLLECTORS={}", DEFAULT_COLLECTORS.join(",")),
+                "TEXTFILE_DIRS=C:\\prometheus\\textfile_inputs".to_string(),
+                "CONFIG_FILE=C:\\prometheus\\windows_exporter.yml".to_string(),
+            ]
+        );
+        assert!(
+            config
+                .to_yaml()
+                .contains(&format!("enabled: \"{}\"", DEFAULT_COLLECTORS.join(",")))
+        );
+    }
+
+    #[test]
+    fn test_where_option() {
+        assert_eq!(where_option("service"), Some("services-where"));
+        assert_eq!(where_option("process"), None);
+    }
+}
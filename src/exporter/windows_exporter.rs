@@ -1,9 +1,21 @@
-use std::fs;
-use std::io::Write;
+use crate::arch::Arch;
+use crate::config::WindowsExporterSettings;
+use crate::exporter::downloader::ConfigWrite;
+use crate::exporter::manifest::{ArtifactSource, ComponentRecord, ServiceRecord};
+use crate::exporter::preflight::Requirements;
+use crate::exporter::recorder::{self, progress};
+use crate::exporter::web_config::WebConfig;
+use crate::exporter::windows_config::WindowsExporterConfig;
+use crate::exporter::{Component, ComponentStatus, downloader, listen, service};
+use std::net::IpAddr;
+use std::path::Path;
 use std::process::Command;
 
 const WINDOWS_EXPORTER_VERSION: &str = "0.25.1";
-const WINDOWS_EXPORTER_PORT: u16 = 31415;
+pub const WINDOWS_EXPORTER_PORT: u16 = 31415;
+const DEFAULT_INSTALL_PATH: &str = "C:\\Program Files\\prometheus";
+const UNINSTALL_REGISTRY_KEY: &str =
+    "HKLM\\SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Uninstall";
 
 #[cfg(target_os = "windows")]
 const EMBEDDED_WINDOWS_EXPORTER: Option<&[u8]> = Some(include_bytes!(concat!(
@@ -16,96 +28,327 @@ const EMBEDDED_WINDOWS_EXPORTER: Option<&[u8]> = None;
 pub struct WindowsExporterSetup {
     version: String,
     install_path: String,
+    config: WindowsExporterConfig,
+    arch: Option<&'static str>,
+    web_config: Option<WebConfig>,
 }
 
 impl WindowsExporterSetup {
     pub fn new() -> Self {
         Self {
             version: WINDOWS_EXPORTER_VERSION.to_string(),
-            install_path: "C:\\Program Files\\prometheus".to_string(),
+            install_path: DEFAULT_INSTALL_PATH.to_string(),
+            config: WindowsExporterConfig::new(
+                WINDOWS_EXPORTER_PORT,
+                default_textfile_dir(DEFAULT_INSTALL_PATH),
+            ),
+            arch: get_windows_exporter_arch(),
+            web_config: None,
         }
     }
 
+    /// Override the defaults with values from the installer config
+    pub fn with_settings(mut self, settings: &WindowsExporterSettings) -> Self {
+        if let Some(version) = &settings.version {
+            self.version = version.clone();
+        }
+        if let Some(install_path) = &settings.install_path {
+            self.install_path = install_path.clone();
+        }
+        self.config.textfile_dir = match &settings.textfile_directory {
+            Some(dir) => dir.clone(),
+            None => default_textfile_dir(&self.install_path),
+        };
+        if let Some(port) = settings.port {
+            self.config.port = port;
+        }
+        if let Some(collectors) = &settings.collectors {
+            self.config.collectors = collectors.clone();
+        }
+        if let Some(where_clauses) = &settings.where_clauses {
+            self.config.where_clauses = where_clauses.clone();
+        }
+        self
+    }
+
+    /// Listen on `port` instead, such as a free port replacing a taken one
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.config.port = port;
+        self
+    }
+
+    pub fn port(&self) -> u16 {
+        self.config.port
+    }
+
+    /// Listen on `ip` only, or on every interface with `None`
+    pub fn with_listen_address(mut self, ip: Option<IpAddr>) -> Self {
+        self.config.listen_address = ip;
+        self
+    }
+
+    pub fn listen_address(&self) -> Option<IpAddr> {
+        self.config.listen_address
+    }
+
+    /// Protect the metrics endpoint with basic auth and TLS
+    pub fn with_web_config(mut self, web_config: Option<WebConfig>) -> Self {
+        self.web_config = web_config;
+        self
+    }
+
     pub fn download_url(&self, arch: &str) -> String {
-        let arch_suffix = if arch == "x86_64" { "amd64" } else { "386" };
+        format!("{}/{}", self.release_base_url(), self.installer_name(arch))
+    }
+
+    /// URL of the upstream `sha256sums.txt` for this release
+    pub fn checksums_url(&self) -> String {
+        format!("{}/sha256sums.txt", self.release_base_url())
+    }
+
+    fn installer_name(&self, arch: &str) -> String {
+        format!("windows_exporter-{}-{arch}.msi", self.version)
+    }
+
+    fn arch(&self) -> Result<&'static str, String> {
+        self.arch.ok_or_else(|| {
+            format!(
+                "windows_exporter is not published for the {} architecture",
+                crate::os_detector::get_arch()
+            )
+        })
+    }
+
+    fn release_base_url(&self) -> String {
         format!(
-            "https://github.com/prometheus-community/windows_exporter/releases/download/v{}/windows_exporter-{}-{}.msi",
-            self.version, self.version, arch_suffix
+            "https://github.com/prometheus-community/windows_exporter/releases/download/v{}",
+            self.version
         )
     }
 
-    pub fn setup(&self) -> Result<(), Box<dyn std::error::Error>> {
-        println!("Setting up Windows Exporter v{}", self.version);
+    /// Install or repair Windows Exporter. The configuration file is written
+    /// first, since the service reads it on start. The MSI is only re-run
+    /// when the installer or its properties changed since the `previous`
+    /// install, or the service is missing.
+    /// Returns `true` if anything on the system was changed.
+    pub fn setup(
+        &self,
+        previous: Option<&ComponentRecord>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        progress!("Setting up Windows Exporter v{}", self.version);
 
-        let arch = crate::os_detector::get_arch();
+        let arch = self.arch()?;
 
         self.create_directories()?;
-        self.download_installer(arch)?;
-        self.install_msi()?;
-        self.configure_service()?;
+        let config_changed = self.create_config_file(previous)?;
+        let installer_changed = self.download_installer(arch, previous)?;
+        let web_changed = match &self.web_config {
+            Some(web_config) => web_config.write(&self.install_path, None)?,
+            None => false,
+        };
 
-        Ok(())
+        let properties = self.msi_properties();
+        let reinstall = installer_changed
+            || previous.and_then(|r| r.msi_properties()) != Some(properties.as_slice())
+            || service::windows_service_installed("windows_exporter") != Some(true);
+        if reinstall {
+            self.install_msi(&properties)?;
+        } else {
+            progress!("Windows Exporter package is up to date");
+        }
+        // exporter-toolkit rereads the web configuration on every request
+        let started = self.configure_service(reinstall, config_changed)?;
+
+        Ok(installer_changed || config_changed || reinstall || web_changed || started)
+    }
+
+    /// Where the installer comes from
+    pub fn source(&self) -> ArtifactSource {
+        if self.version == WINDOWS_EXPORTER_VERSION && EMBEDDED_WINDOWS_EXPORTER.is_some() {
+            ArtifactSource::Embedded
+        } else {
+            let url = match self.arch() {
+                Ok(arch) => self.download_url(arch),
+                Err(_) => self.release_base_url(),
+            };
+            ArtifactSource::Remote { url }
+        }
+    }
+
+    /// Describe what `setup` installed, for the install manifest
+    pub fn manifest_record(&self) -> Result<ComponentRecord, Box<dyn std::error::Error>> {
+        let mut record = ComponentRecord::new(
+            Component::WindowsExporter,
+            Some(self.version.clone()),
+            self.source(),
+        );
+        record.add_file(&self.installer_path())?;
+        record.add_config_file(&self.config_path(), self.config.to_yaml().as_bytes());
+        record.add_service(ServiceRecord::MsiPackage {
+            product_code: query_product_code(),
+            installer_path: self.installer_path(),
+            properties: self.msi_properties(),
+        });
+        record.add_service(ServiceRecord::WindowsService {
+            name: "windows_exporter".to_string(),
+        });
+        Ok(record)
+    }
+
+    /// What `setup` needs from the host, for the preflight check
+    pub fn requirements(&self) -> Requirements {
+        let mut paths = vec![self.install_path.clone()];
+        paths.extend(self.config.textfile_dir().map(str::to_string));
+        Requirements::privileged(paths, vec!["msiexec", "sc"])
+    }
+
+    pub fn status(&self) -> ComponentStatus {
+        ComponentStatus {
+            component: Component::WindowsExporter,
+            installed: service::windows_service_installed("windows_exporter") == Some(true),
+            running: service::windows_service_running("windows_exporter"),
+        }
+    }
+
+    /// Uninstall the MSI package and remove the files created by `setup`.
+    /// The recorded product code is used when the installer file is gone.
+    /// Returns a description of everything that was removed.
+    pub fn uninstall(
+        &self,
+        product_code: Option<&str>,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut removed = Vec::new();
+
+        if service::windows_service_installed("windows_exporter") == Some(true) {
+            let installer_path = self.installer_path();
+            let package = if Path::new(&installer_path).exists() {
+                installer_path
+            } else if let Some(code) = product_code {
+                code.to_string()
+            } else {
+                return Err(format!(
+                    "Installer not found at {installer_path} and no product code recorded; cannot run msiexec /x"
+                )
+                .into());
+            };
+
+            let output =
+                recorder::run_command("msiexec", &["/x", &package, "/quiet", "/norestart"])?;
+            if !output.status.success() {
+                let error = String::from_utf8_lossy(&output.stderr);
+                return Err(format!("Uninstallation failed: {error}").into());
+            }
+            removed.push("windows_exporter MSI package and service".to_string());
+        }
+
+        removed.extend(self.remove_files()?);
+        Ok(removed)
+    }
+
+    fn remove_files(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut removed = Vec::new();
+        let config_path = self.config_path();
+        let new_config_path = format!("{config_path}.new");
+        let mut paths = vec![self.installer_path(), config_path, new_config_path];
+        paths.extend(WebConfig::generated_files(&self.install_path));
+        for path in paths {
+            if downloader::remove_path(&path)? {
+                removed.push(path);
+            }
+        }
+        // Other jobs may still write metrics there
+        if downloader::remove_dir_if_empty(&self.config.textfile_dir) {
+            removed.push(self.config.textfile_dir.clone());
+        }
+        if downloader::remove_dir_if_empty(&self.install_path) {
+            removed.push(self.install_path.clone());
+        }
+        Ok(removed)
+    }
+
+    fn installer_path(&self) -> String {
+        self.file_path("windows_exporter.msi")
+    }
+
+    fn config_path(&self) -> String {
+        self.file_path("windows_exporter.yml")
+    }
+
+    fn file_path(&self, file_name: &str) -> String {
+        join_path(&self.install_path, file_name)
     }
 
     fn create_directories(&self) -> Result<(), Box<dyn std::error::Error>> {
-        fs::create_dir_all(&self.install_path)?;
+        downloader::ensure_directory_exists(&self.install_path)?;
+        if let Some(dir) = self.config.textfile_dir() {
+            downloader::ensure_directory_exists(dir)?;
+        }
         Ok(())
     }
 
-    fn download_installer(&self, arch: &str) -> Result<(), Box<dyn std::error::Error>> {
+    /// Returns `true` when the installer file was replaced
+    fn download_installer(
+        &self,
+        arch: &str,
+        previous: Option<&ComponentRecord>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
         if self.version == WINDOWS_EXPORTER_VERSION
             && let Some(bytes) = EMBEDDED_WINDOWS_EXPORTER
         {
-            self.write_installer(bytes)?;
-            return Ok(());
+            return self.write_installer(bytes);
+        }
+
+        let installer_path = self.installer_path();
+        if previous.is_some_and(|r| {
+            r.version.as_deref() == Some(self.version.as_str())
+                && r.file_unmodified(&installer_path)
+        }) {
+            progress!("Windows Exporter installer is up to date: {installer_path}");
+            return Ok(false);
         }
 
         let url = self.download_url(arch);
-        println!("Downloading from: {url}");
+        progress!("Downloading from: {url}");
 
-        let response = reqwest::blocking::get(&url)?;
+        let expected =
+            downloader::fetch_sha256_from_sums(&self.checksums_url(), &self.installer_name(arch))?;
+        let bytes = downloader::download_content_verified(&url, &expected)?;
+        self.write_installer(&bytes)
+    }
 
-        if !response.status().is_success() {
-            return Err(format!("Failed to download: HTTP {}", response.status()).into());
+    fn write_installer(&self, bytes: &[u8]) -> Result<bool, Box<dyn std::error::Error>> {
+        let installer_path = self.installer_path();
+        let written = downloader::write_file_if_changed(&installer_path, bytes)?;
+        if written {
+            progress!("Windows Exporter installer prepared at: {installer_path}");
         }
-
-        let bytes = response.bytes()?;
-        self.write_installer(&bytes)?;
-        Ok(())
+        Ok(written)
     }
 
-    fn write_installer(&self, bytes: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
-        let installer_path = format!("{}\\windows_exporter.msi", self.install_path);
-        let mut file = fs::File::create(&installer_path)?;
-        file.write_all(bytes)?;
-        println!("Windows Exporter installer prepared at: {installer_path}");
-        Ok(())
+    /// Properties passed to the MSI; also recorded to detect changes on re-runs
+    fn msi_properties(&self) -> Vec<String> {
+        let mut properties = self.config.msi_properties(&self.config_path());
+        if self.web_config.is_some() {
+            // The MSI appends EXTRA_FLAGS to the service command line
+            properties.push(format!(
+                "EXTRA_FLAGS=--web.config.file={}",
+                WebConfig::path(&self.install_path)
+            ));
+        }
+        properties
     }
 
-    fn install_msi(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let installer_path = format!("{}\\windows_exporter.msi", self.install_path);
+    fn install_msi(&self, properties: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+        let installer_path = self.installer_path();
 
-        println!("Installing Windows Exporter...");
+        progress!("Installing Windows Exporter...");
 
-        // Follow upstream MSI semantics: properties are passed as
-        //   ENABLED_COLLECTORS=...
-        //   LISTEN_PORT=...
-        // without extra quoting, matching README examples.
-        let collectors_arg = "ENABLED_COLLECTORS=cpu,cs,logical_disk,net,os,service,system,textfile,process,memory,thermalzone";
-
-        let output = Command::new("msiexec")
-            .args([
-                "/i",
-                &installer_path,
-                "/quiet",
-                "/norestart",
-                &format!("LISTEN_PORT={}", WINDOWS_EXPORTER_PORT),
-                collectors_arg,
-            ])
-            .output()?;
+        let mut args = vec!["/i", installer_path.as_str(), "/quiet", "/norestart"];
+        args.extend(properties.iter().map(String::as_str));
+        let output = recorder::run_command("msiexec", &args)?;
 
         if output.status.success() {
-            println!("Windows Exporter installed successfully");
+            progress!("Windows Exporter installed successfully");
         } else {
             let error = String::from_utf8_lossy(&output.stderr);
             return Err(format!("Installation failed: {error}").into());
@@ -114,74 +357,129 @@ impl WindowsExporterSetup {
         Ok(())
     }
 
-    fn configure_service(&self) -> Result<(), Box<dyn std::error::Error>> {
-        println!("Configuring Windows Exporter service...");
+    /// Returns `true` when the service was (re)configured and started
+    fn configure_service(
+        &self,
+        reinstalled: bool,
+        config_changed: bool,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        if !reinstalled && service::windows_service_running("windows_exporter") == Some(true) {
+            if !config_changed {
+                return Ok(false);
+            }
+            // The configuration file is only read on start
+            progress!("Restarting Windows Exporter to apply its configuration...");
+            // `net stop` waits for the service to stop, unlike `sc stop`
+            recorder::run_command("net", &["stop", "windows_exporter"])?;
+        }
+
+        progress!("Configuring Windows Exporter service...");
 
-        Command::new("sc")
-            .args(["config", "windows_exporter", "start=auto"])
-            .output()?;
+        recorder::run_command("sc", &["config", "windows_exporter", "start=auto"])?;
 
-        let output = Command::new("sc")
-            .args(["start", "windows_exporter"])
-            .output()?;
+        let output = recorder::run_command("sc", &["start", "windows_exporter"])?;
 
         if output.status.success() {
-            println!("Windows Exporter service started successfully");
-            println!("Metrics available at: http://localhost:{WINDOWS_EXPORTER_PORT}/metrics");
+            progress!("Windows Exporter service started successfully");
+            progress!(
+                "Metrics available at: http://{}/metrics",
+                listen::local_endpoint(self.config.listen_address, self.config.port)
+            );
         } else {
-            println!("Please start the service manually: sc.exe start windows_exporter");
+            progress!("Please start the service manually: sc.exe start windows_exporter");
         }
 
-        Ok(())
+        Ok(true)
     }
 
-    pub fn create_config_file(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let config_content = r#"# Windows Exporter Configuration
-# Collectors to enable
-collectors:
-  enabled:
-    - cpu
-    - cs
-    - logical_disk
-    - net
-    - os
-    - service
-    - system
-    - textfile
-    - process
-    - memory
-    - thermalzone
-    - tcp
-    - iis
-
-# Collector-specific configuration
-collector:
-  service:
-    services-where: "Name='windows_exporter' OR Name='prometheus'"
-  process:
-    processes-where: "Name LIKE 'chrome%' OR Name = 'firefox'"
-"#;
-
-        // Use proper path separator based on OS
-        let config_path = if cfg!(windows) {
-            format!("{}\\windows_exporter.yml", self.install_path)
-        } else {
-            format!("{}/windows_exporter.yml", self.install_path)
-        };
+    /// Write the YAML configuration, keeping local edits made since the
+    /// `previous` install. Returns `true` when the active configuration changed.
+    pub fn create_config_file(
+        &self,
+        previous: Option<&ComponentRecord>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let config_content = self.config.to_yaml();
+        let config_path = self.config_path();
+
+        let shipped = previous.and_then(|record| record.file_sha256(&config_path));
+        match downloader::write_config_file(&config_path, config_content.as_bytes(), shipped)? {
+            ConfigWrite::Unchanged => Ok(false),
+            ConfigWrite::Written => {
+                progress!("Configuration file created at: {config_path}");
+                Ok(true)
+            }
+            ConfigWrite::Preserved { new_path } => {
+                progress!(
+                    "Keeping locally edited {config_path}; new defaults written to {new_path}"
+                );
+                Ok(false)
+            }
+        }
+    }
+}
 
-        let mut file = fs::File::create(&config_path)?;
-        file.write_all(config_content.as_bytes())?;
+/// Where the textfile collector reads `.prom` files from unless configured
+fn default_textfile_dir(install_path: &str) -> String {
+    join_path(install_path, "textfile_inputs")
+}
 
-        println!("Configuration file created at: {config_path}");
-        Ok(())
+fn join_path(dir: &str, file_name: &str) -> String {
+    // Use proper path separator based on OS
+    if cfg!(windows) {
+        format!("{dir}\\{file_name}")
+    } else {
+        format!("{dir}/{file_name}")
     }
 }
 
+/// Get architecture string for Windows Exporter, `None` if upstream has no MSI for it
+pub fn get_windows_exporter_arch() -> Option<&'static str> {
+    crate::os_detector::detect_arch().and_then(Arch::windows_exporter_name)
+}
+
+/// Look up the MSI product code of the installed windows_exporter package
+fn query_product_code() -> Option<String> {
+    let output = Command::new("reg")
+        .args([
+            "query",
+            UNINSTALL_REGISTRY_KEY,
+            "/s",
+            "/f",
+            "windows_exporter",
+            "/d",
+        ])
+        .output()
+        .ok()?;
+    parse_product_code(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Extract the `{GUID}` key name from `reg query` output of the Uninstall key
+fn parse_product_code(output: &str) -> Option<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with("HKEY_"))
+        .filter_map(|line| line.rsplit('\\').next())
+        .find(|key| key.starts_with('{') && key.ends_with('}'))
+        .map(str::to_string)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::WebSettings;
+    use crate::exporter::windows_config::DEFAULT_COLLECTORS;
+    use std::fs;
     use tempfile::TempDir;
 
+    /// Setup installing into `path`, with the textfile directory under it
+    fn setup_in(path: &Path) -> WindowsExporterSetup {
+        WindowsExporterSetup::new().with_settings(&WindowsExporterSettings {
+            install_path: Some(path.to_str().unwrap().to_string()),
+            ..Default::default()
+        })
+    }
+
     #[test]
     fn test_windows_exporter_creation() {
         let setup = WindowsExporterSetup::new();
@@ -193,27 +491,50 @@ mod tests {
     fn test_download_url_generation() {
         let setup = WindowsExporterSetup::new();
 
-        let url_amd64 = setup.download_url("x86_64");
+        let url_amd64 = setup.download_url("amd64");
         assert!(url_amd64.contains("windows_exporter-0.25.1-amd64.msi"));
         assert!(url_amd64.starts_with(
             "https://github.com/prometheus-community/windows_exporter/releases/download/"
         ));
 
-        let url_386 = setup.download_url("x86");
+        let url_386 = setup.download_url("386");
         assert!(url_386.contains("windows_exporter-0.25.1-386.msi"));
+
+        let url_arm64 = setup.download_url("arm64");
+        assert!(url_arm64.contains("windows_exporter-0.25.1-arm64.msi"));
     }
 
     #[test]
-    fn test_arch_mapping() {
+    fn test_checksums_url() {
         let setup = WindowsExporterSetup::new();
+        assert_eq!(
+            setup.checksums_url(),
+            "https://github.com/prometheus-community/windows_exporter/releases/download/v0.25.1/sha256sums.txt"
+        );
+        assert_eq!(
+            setup.installer_name("amd64"),
+            "windows_exporter-0.25.1-amd64.msi"
+        );
+    }
 
-        // Test x86_64 maps to amd64
-        let url = setup.download_url("x86_64");
-        assert!(url.contains("amd64"));
+    #[test]
+    fn test_arch_mapping() {
+        match std::env::consts::ARCH {
+            "x86_64" => assert_eq!(get_windows_exporter_arch(), Some("amd64")),
+            "x86" => assert_eq!(get_windows_exporter_arch(), Some("386")),
+            "aarch64" => assert_eq!(get_windows_exporter_arch(), Some("arm64")),
+            _ => assert_eq!(get_windows_exporter_arch(), None),
+        }
 
-        // Test other architectures map to 386
-        let url = setup.download_url("x86");
-        assert!(url.contains("386"));
+        let mut setup = WindowsExporterSetup::new();
+        setup.arch = None;
+        assert!(
+            setup
+                .setup(None)
+                .unwrap_err()
+                .to_string()
+                .contains("not published")
+        );
     }
 
     #[test]
@@ -221,12 +542,12 @@ mod tests {
         let temp_dir = TempDir::new().unwrap();
         let test_path = temp_dir.path().join("test_prometheus");
 
-        let mut setup = WindowsExporterSetup::new();
-        setup.install_path = test_path.to_str().unwrap().to_string();
+        let setup = setup_in(&test_path);
 
         let result = setup.create_directories();
         assert!(result.is_ok());
         assert!(test_path.exists());
+        assert!(test_path.join("textfile_inputs").is_dir());
     }
 
     #[test]
@@ -245,10 +566,9 @@ mod tests {
         let test_path = temp_dir.path().join("test_prometheus");
         fs::create_dir_all(&test_path).unwrap();
 
-        let mut setup = WindowsExporterSetup::new();
-        setup.install_path = test_path.to_str().unwrap().to_string();
+        let setup = setup_in(&test_path);
 
-        let result = setup.create_config_file();
+        let result = setup.create_config_file(None);
         assert!(result.is_ok());
 
         // Check file exists with proper path separator
@@ -256,11 +576,154 @@ mod tests {
         assert!(config_path.exists());
 
         let content = fs::read_to_string(&config_path).unwrap();
-        assert!(content.contains("Windows Exporter Configuration"));
-        assert!(content.contains("collectors:"));
-        assert!(content.contains("cpu"));
-        assert!(content.contains("memory"));
-        assert!(content.contains("logical_disk"));
+        assert_eq!(content, setup.config.to_yaml());
+        assert!(content.contains("collectors:\n  enabled: \"cpu,cs,logical_disk,"));
+        assert!(!content.contains("chrome"));
+    }
+
+    #[test]
+    fn test_with_settings() {
+        let settings = WindowsExporterSettings {
+            port: Some(9182),
+            collectors: Some(vec![
+                "cpu".to_string(),
+                "iis".to_string(),
+                "textfile".to_string(),
+            ]),
+            ..Default::default()
+        };
+        let setup = WindowsExporterSetup::new().with_settings(&settings);
+        assert_eq!(setup.port(), 9182);
+        assert_eq!(setup.version, WINDOWS_EXPORTER_VERSION);
+        assert_eq!(setup.install_path, "C:\\Program Files\\prometheus");
+
+        let content = setup.config.to_yaml();
+        assert!(content.contains("  enabled: \"cpu,iis,textfile\"\n"));
+        assert!(content.contains("listen-address: \":9182\"\n"));
+        // The default service filter only applies with the service collector
+        assert!(!content.contains("services-where"));
+    }
+
+    #[test]
+    fn test_with_settings_textfile_and_where_clauses() {
+        let settings = WindowsExporterSettings {
+            install_path: Some("D:\\prometheus".to_string()),
+            where_clauses: Some([("service".to_string(), "StartMode='Auto'".to_string())].into()),
+            ..Default::default()
+        };
+        let setup = WindowsExporterSetup::new().with_settings(&settings);
+        assert_eq!(
+            setup.config.textfile_dir(),
+            Some(join_path("D:\\prometheus", "textfile_inputs").as_str())
+        );
+        assert!(
+            setup
+                .requirements()
+                .writable_paths
+                .contains(&setup.config.textfile_dir)
+        );
+        let content = setup.config.to_yaml();
+        assert!(content.contains("    services-where: \"StartMode='Auto'\"\n"));
+
+        let setup = setup.with_settings(&WindowsExporterSettings {
+            textfile_directory: Some("E:\\metrics".to_string()),
+            ..Default::default()
+        });
+        assert!(
+            setup
+                .msi_properties()
+                .contains(&"TEXTFILE_DIRS=E:\\metrics".to_string())
+        );
+    }
+
+    #[test]
+    fn test_msi_properties_with_web_config() {
+        let setup = WindowsExporterSetup::new();
+        assert_eq!(setup.msi_properties().len(), 4);
+
+        let web = WebSettings {
+            self_signed_cert: Some(true),
+            ..Default::default()
+        };
+        let setup = setup.with_web_config(WebConfig::from_settings(&web));
+        let properties = setup.msi_properties();
+        assert!(properties[4].starts_with("EXTRA_FLAGS=--web.config.file=C:\\Program Files"));
+        assert!(properties[4].ends_with("web-config.yml"));
+    }
+
+    #[test]
+    fn test_msi_properties_with_listen_address() {
+        let setup =
+            WindowsExporterSetup::new().with_listen_address(Some("10.0.0.5".parse().unwrap()));
+        assert_eq!(
+            setup.msi_properties(),
+            vec![
+                "LISTEN_PORT=31415".to_string(),
+                format!("ENABLED_COLLECTORS={}", DEFAULT_COLLECTORS.join(",")),
+                "LISTEN_ADDR=10.0.0.5".to_string(),
+                format!("TEXTFILE_DIRS={}", setup.config.textfile_dir),
+                format!("CONFIG_FILE={}", setup.config_path()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_remove_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let test_path = temp_dir.path().join("test_prometheus");
+        fs::create_dir_all(&test_path).unwrap();
+
+        let setup = setup_in(&test_path);
+        setup.create_directories().unwrap();
+        setup.write_installer(b"msi").unwrap();
+        setup.create_config_file(None).unwrap();
+
+        let removed = setup.remove_files().unwrap();
+        assert_eq!(removed.len(), 4);
+        assert!(removed[0].ends_with("windows_exporter.msi"));
+        assert!(removed[1].ends_with("windows_exporter.yml"));
+        assert!(removed[2].ends_with("textfile_inputs"));
+        assert!(!test_path.exists());
+    }
+
+    #[test]
+    fn test_parse_product_code() {
+        let output = r#"
+HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft\Windows\CurrentVersion\Uninstall\{8C3D1A2B-0000-4C5D-9E8F-123456789ABC}
+    DisplayName    REG_SZ    windows_exporter
+
+End of search: 1 match(es) found.
+"#;
+        assert_eq!(
+            parse_product_code(output),
+            Some("{8C3D1A2B-0000-4C5D-9E8F-123456789ABC}".to_string())
+        );
+        assert_eq!(
+            parse_product_code("End of search: 0 match(es) found."),
+            None
+        );
+    }
+
+    #[test]
+    fn test_manifest_record() {
+        let temp_dir = TempDir::new().unwrap();
+        let test_path = temp_dir.path().join("test_prometheus");
+        fs::create_dir_all(&test_path).unwrap();
+
+        let setup = setup_in(&test_path);
+        setup.write_installer(b"msi").unwrap();
+        setup.create_config_file(None).unwrap();
+
+        let record = setup.manifest_record().unwrap();
+        assert_eq!(record.component, Component::WindowsExporter);
+        assert_eq!(record.version.as_deref(), Some("0.25.1"));
+        assert_eq!(record.files.len(), 2);
+        assert!(
+            record
+                .services
+                .iter()
+                .any(|s| matches!(s, ServiceRecord::MsiPackage { .. }))
+        );
     }
 
     #[test]
@@ -278,12 +741,11 @@ mod tests {
         let test_path = temp_dir.path().join("test_prometheus");
         fs::create_dir_all(&test_path).unwrap();
 
-        let mut setup = WindowsExporterSetup::new();
-        setup.install_path = test_path.to_str().unwrap().to_string();
+        let mut setup = setup_in(&test_path);
         // Use an invalid version that will cause 404
         setup.version = "99.99.99".to_string();
 
-        let result = setup.download_installer("x86_64");
+        let result = setup.download_installer("amd64", None);
         // GitHub will return 404 for non-existent version
         assert!(result.is_err());
     }
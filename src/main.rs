@@ -1,122 +1,77 @@
+mod arch;
+mod cli;
+mod commands;
+mod config;
 mod exporter;
 mod os_detector;
 
-use exporter::node_exporter::NodeExporterSetup;
-use exporter::process_exporter::ProcessCpuAgentSetup;
-use exporter::windows_exporter::WindowsExporterSetup;
-
-use os_detector::{OsType, detect_os};
-use std::env;
+use clap::Parser;
+use cli::{Cli, Command, PlanFormat};
+use config::InstallerConfig;
+use os_detector::detect_os;
 
 fn main() {
-    println!("Prometheus Exporters Setup Tool");
-    println!("================================\n");
-
-    // Get Process CPU Agent download URL from environment variable or command line argument
-    let process_cpu_agent_url = env::var("PROCESS_CPU_AGENT_URL")
-        .ok()
-        .or_else(|| env::args().nth(1));
-
-    if process_cpu_agent_url.is_some() {
-        println!(
-            "Using custom Process CPU Agent URL: {}",
-            process_cpu_agent_url.as_ref().unwrap()
-        );
+    let cli = Cli::parse();
+    let config_path = cli.config.clone();
+    let command = match cli.into_command() {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("✗ {e}");
+            std::process::exit(1);
+        }
+    };
+    // Started by the service control manager, which has no console to print to
+    if let Command::ServiceHost(args) = &command {
+        if let Err(e) = exporter::service_host::run(&args.name, &args.working_dir, &args.command) {
+            eprintln!("✗ {e}");
+            std::process::exit(1);
+        }
+        return;
     }
-
     let os_type = detect_os();
-    let arch = os_detector::get_arch();
-
-    println!("Detected OS: {os_type:?}");
-    println!("Architecture: {arch}");
-    println!("64-bit: {}\n", os_detector::is_64bit());
-
-    let process_agent_setup = ProcessCpuAgentSetup::new(process_cpu_agent_url);
 
-    let result = match os_type {
-        OsType::Linux => {
-            println!("Setting up exporters for Linux...\n");
-
-            println!("1. Setting up Node Exporter...");
-            let node_setup = NodeExporterSetup::new();
-            if let Err(e) = node_setup.setup() {
-                eprintln!("Node Exporter setup failed: {e}");
-            }
-
-            println!("\n2. Setting up Process CPU Agent...");
-            process_agent_setup.setup()
+    let config = match config_path.as_deref().map(InstallerConfig::load) {
+        Some(Ok(config)) => config,
+        Some(Err(e)) => {
+            eprintln!("✗ {e}");
+            std::process::exit(1);
         }
-        OsType::Windows => {
-            println!("Setting up exporters for Windows...\n");
-
-            println!("1. Setting up Windows Exporter...");
-            let windows_setup = WindowsExporterSetup::new();
-            if let Err(e) = windows_setup.setup() {
-                eprintln!("Windows Exporter setup failed: {e}");
-            }
-            windows_setup.create_config_file().ok();
+        None => InstallerConfig::default(),
+    };
 
-            println!("\n2. Setting up Process CPU Agent...");
-            process_agent_setup.setup()
-        }
-        OsType::MacOs => {
-            println!("Setting up exporters for macOS...\n");
-            println!("Note: macOS support uses Node Exporter with limited collectors");
+    // Keep JSON plans machine readable
+    if command.plan_format() != Some(PlanFormat::Json) {
+        println!("Prometheus Exporters Setup Tool");
+        println!("================================\n");
 
-            println!("1. Setting up Node Exporter...");
-            let node_setup = NodeExporterSetup::new();
-            if let Err(e) = node_setup.setup() {
-                eprintln!("Node Exporter setup failed: {e}");
-            }
+        println!("Detected OS: {os_type:?}");
+        println!("Architecture: {}", os_detector::get_arch());
+        println!("64-bit: {}\n", os_detector::is_64bit());
+    }
 
-            println!("\n2. Setting up Process CPU Agent...");
-            process_agent_setup.setup()
-        }
-        OsType::Unknown => Err("Unsupported operating system".into()),
+    let (result, failure_label) = match &command {
+        Command::Install(args) => (commands::install(&os_type, args, &config), "Setup failed"),
+        Command::Uninstall(args) => (
+            commands::uninstall(&os_type, args, &config),
+            "Uninstall failed",
+        ),
+        Command::Upgrade(args) => (commands::upgrade(&os_type, args, &config), "Upgrade failed"),
+        Command::Status(selection) => (
+            commands::status(&os_type, selection, &config),
+            "Status check failed",
+        ),
+        Command::Plan(args) => (commands::plan(&os_type, args, &config), "Planning failed"),
+        Command::ServiceHost(_) => unreachable!("the service host returns early"),
     };
 
-    match result {
-        Ok(_) => {
-            println!("\n✓ Exporter setup completed successfully!");
-            println!("Next steps:");
-            match os_type {
-                OsType::Linux => {
-                    println!("1. Start Node Exporter: sudo systemctl enable --now node_exporter");
-                    println!(
-                        "2. Start Process CPU Agent: sudo systemctl enable --now process-cpu-agent"
-                    );
-                    println!("3. Check Node Exporter metrics: http://localhost:31415/metrics");
-                    println!("4. Check Process CPU Agent metrics: http://localhost:31416/metrics");
-                }
-                OsType::Windows => {
-                    println!("1. Check Windows Exporter: sc.exe query windows_exporter");
-                    println!("2. Process CPU Agent is registered as a scheduled task:");
-                    println!("   - Check task: schtasks /Query /TN ProcessCpuAgent");
-                    println!("   - Run once now: schtasks /Run /TN ProcessCpuAgent");
-                    println!("3. Check Windows Exporter metrics: http://localhost:31415/metrics");
-                    println!("4. Check Process CPU Agent metrics: http://localhost:31416/metrics");
-                }
-                OsType::MacOs => {
-                    println!("1. Start Node Exporter manually from /opt/prometheus/node_exporter");
-                    println!("2. Start Process CPU Agent from /opt/prometheus/process-cpu-agent");
-                    println!("3. Check Node Exporter metrics: http://localhost:31415/metrics");
-                    println!("4. Check Process CPU Agent metrics: http://localhost:31416/metrics");
-                }
-                _ => {}
-            }
-            println!("5. Configure Prometheus to scrape these exporters");
-
-            println!("\n📌 Custom Download URLs:");
-            println!("   You can specify a custom Process CPU Agent download URL:");
-            println!(
-                "   - Via environment variable: PROCESS_CPU_AGENT_URL=<url> ./prometheus-agents-setup"
-            );
-            println!("   - Via command line argument: ./prometheus-agents-setup <url>");
-        }
-        Err(e) => {
-            eprintln!("\n✗ Setup failed: {e}");
+    if let Err(e) = result {
+        eprintln!("\n✗ {failure_label}: {e}");
+        if matches!(
+            command,
+            Command::Install(_) | Command::Upgrade(_) | Command::Uninstall(_)
+        ) {
             eprintln!("Please check permissions and try again");
-            std::process::exit(1);
         }
+        std::process::exit(1);
     }
 }
@@ -1,4 +1,15 @@
+use crate::arch::Arch;
 use std::env;
+use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// An IP address assigned to a network interface
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterfaceAddress {
+    pub interface: String,
+    pub ip: IpAddr,
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum OsType {
@@ -22,7 +33,170 @@ pub fn get_arch() -> &'static str {
 }
 
 pub fn is_64bit() -> bool {
-    env::consts::ARCH == "x86_64" || env::consts::ARCH == "aarch64"
+    cfg!(target_pointer_width = "64")
+}
+
+/// Release architecture of the target this binary was built for.
+/// `None` when upstream exporters are not published for it.
+pub fn detect_arch() -> Option<Arch> {
+    Arch::from_triple(env!("PROMETHEUS_AGENTS_TARGET"))
+}
+
+/// Locate an executable in the directories listed in `PATH`
+pub fn find_program(name: &str) -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+    let file_name = if cfg!(windows) && Path::new(name).extension().is_none() {
+        format!("{name}.exe")
+    } else {
+        name.to_string()
+    };
+    env::split_paths(&path)
+        .map(|dir| dir.join(&file_name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Whether this process runs as root or, on Windows, from an elevated prompt
+pub fn is_privileged() -> bool {
+    if cfg!(windows) {
+        // Listing server sessions is only allowed for elevated Administrators
+        Command::new("net")
+            .arg("session")
+            .output()
+            .is_ok_and(|output| output.status.success())
+    } else {
+        effective_uid() == Some(0)
+    }
+}
+
+/// Name of this host, `None` when it cannot be determined
+pub fn hostname() -> Option<String> {
+    let output = Command::new("hostname").output().ok()?;
+    let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!name.is_empty()).then_some(name)
+}
+
+/// Address of the interface holding the default route, `None` without one
+pub fn primary_ip() -> Option<IpAddr> {
+    // Connecting a UDP socket only selects a route; nothing is sent
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect((Ipv4Addr::new(192, 0, 2, 1), 9)).ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    (!ip.is_unspecified() && !ip.is_loopback()).then_some(ip)
+}
+
+/// Addresses of this host's network interfaces, empty when they cannot be listed
+pub fn interface_addresses() -> Vec<InterfaceAddress> {
+    let output = if cfg!(windows) {
+        Command::new("powershell")
+            .args([
+                "-NoProfile",
+                "-Command",
+                "Get-NetIPAddress | ForEach-Object { $_.InterfaceAlias + '|' + $_.IPAddress }",
+            ])
+            .output()
+    } else if cfg!(target_os = "macos") {
+        Command::new("ifconfig").output()
+    } else {
+        Command::new("ip").args(["-o", "addr", "show"]).output()
+    };
+    let Ok(output) = output else {
+        return Vec::new();
+    };
+    let output = String::from_utf8_lossy(&output.stdout);
+    if cfg!(windows) {
+        parse_get_net_ip_address(&output)
+    } else if cfg!(target_os = "macos") {
+        parse_ifconfig(&output)
+    } else {
+        parse_ip_addr(&output)
+    }
+}
+
+/// `ip -o addr show`: one address per line, after the index and interface name
+fn parse_ip_addr(output: &str) -> Vec<InterfaceAddress> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [_, interface, "inet" | "inet6", cidr, ..] => Some(InterfaceAddress {
+                    interface: interface.split('@').next()?.to_string(),
+                    ip: cidr.split('/').next()?.parse().ok()?,
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// `ifconfig`: an unindented `name: flags=...` line starts each interface
+fn parse_ifconfig(output: &str) -> Vec<InterfaceAddress> {
+    let mut interface = "";
+    let mut addresses = Vec::new();
+    for line in output.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            interface = line.split(':').next().unwrap_or_default();
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        if let (Some("inet" | "inet6"), Some(ip)) = (fields.next(), fields.next())
+            && let Ok(ip) = ip.split('%').next().unwrap_or_default().parse()
+        {
+            addresses.push(InterfaceAddress {
+                interface: interface.to_string(),
+                ip,
+            });
+        }
+    }
+    addresses
+}
+
+/// `alias|address` lines printed for each `Get-NetIPAddress` result
+fn parse_get_net_ip_address(output: &str) -> Vec<InterfaceAddress> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (interface, ip) = line.trim().rsplit_once('|')?;
+            Some(InterfaceAddress {
+                interface: interface.to_string(),
+                // Link-local IPv6 addresses carry a zone index
+                ip: ip.split('%').next()?.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Per-user data directory, `$XDG_DATA_HOME` or `~/.local/share`
+pub fn user_data_dir() -> String {
+    xdg_dir(
+        env::var("XDG_DATA_HOME").ok(),
+        env::var("HOME").ok(),
+        ".local/share",
+    )
+}
+
+/// Per-user configuration directory, `$XDG_CONFIG_HOME` or `~/.config`
+pub fn user_config_dir() -> String {
+    xdg_dir(
+        env::var("XDG_CONFIG_HOME").ok(),
+        env::var("HOME").ok(),
+        ".config",
+    )
+}
+
+fn xdg_dir(value: Option<String>, home: Option<String>, default: &str) -> String {
+    match (value, home) {
+        // Relative values are invalid and must be ignored
+        (Some(dir), _) if dir.starts_with('/') => dir,
+        (_, Some(home)) => format!("{}/{default}", home.trim_end_matches('/')),
+        _ => format!("~/{default}"),
+    }
+}
+
+/// Effective user ID of this process, from `id -u`
+pub fn effective_uid() -> Option<u32> {
+    let output = Command::new("id").arg("-u").output().ok()?;
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
 }
 
 #[cfg(test)]
@@ -53,14 +227,18 @@ mod tests {
 
     #[test]
     fn test_is_64bit() {
-        let is_64 = is_64bit();
-        let arch = get_arch();
+        assert_eq!(is_64bit(), std::mem::size_of::<usize>() == 8);
+    }
 
-        if arch == "x86_64" || arch == "aarch64" {
-            assert!(is_64);
-        } else {
-            assert!(!is_64);
-        }
+    #[test]
+    fn test_detect_arch_matches_build_target() {
+        let expected = match get_arch() {
+            "x86_64" => Some(Arch::Amd64),
+            "x86" => Some(Arch::I386),
+            "aarch64" => Some(Arch::Arm64),
+            _ => return,
+        };
+        assert_eq!(detect_arch(), expected);
     }
 
     #[test]
@@ -94,4 +272,81 @@ mod tests {
         let arch2 = get_arch();
         assert_eq!(arch1, arch2);
     }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_effective_uid() {
+        assert!(effective_uid().is_some());
+        assert_eq!(is_privileged(), effective_uid() == Some(0));
+    }
+
+    #[test]
+    fn test_xdg_dir() {
+        let home = Some("/home/dev/".to_string());
+        assert_eq!(
+            xdg_dir(None, home.clone(), ".local/share"),
+            "/home/dev/.local/share"
+        );
+        assert_eq!(
+            xdg_dir(Some("/data".to_string()), home.clone(), ".local/share"),
+            "/data"
+        );
+        assert_eq!(
+            xdg_dir(Some("data".to_string()), home, ".config"),
+            "/home/dev/.config"
+        );
+        assert_eq!(xdg_dir(None, None, ".config"), "~/.config");
+    }
+
+    #[test]
+    fn test_parse_ip_addr() {
+        let output = "1: lo    inet 127.0.0.1/8 scope host lo\\       valid_lft forever preferred_lft forever\n\
+                      4: eth0    inet 10.0.0.5/24 brd 10.0.0.255 scope global eth0\\       valid_lft forever\n\
+                      4: eth0    inet6 fe80::fc:ff:fe00:1/64 scope link \\       valid_lft forever\n";
+        let addresses = parse_ip_addr(output);
+        assert_eq!(addresses.len(), 3);
+        assert_eq!(
+            addresses[1],
+            InterfaceAddress {
+                interface: "eth0".to_string(),
+                ip: "10.0.0.5".parse().unwrap(),
+            }
+        );
+        assert_eq!(
+            addresses[2].ip,
+            "fe80::fc:ff:fe00:1".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_ifconfig() {
+        let output = "lo0: flags=8049<UP,LOOPBACK,RUNNING,MULTICAST> mtu 16384\n\
+                      \tinet 127.0.0.1 netmask 0xff000000\n\
+                      \tinet6 fe80::1%lo0 prefixlen 64 scopeid 0x1\n\
+                      en0: flags=8863<UP,BROADCAST,SMART,RUNNING,SIMPLEX,MULTICAST> mtu 1500\n\
+                      \tether 3c:22:fb:00:00:01\n\
+                      \tinet 192.168.1.20 netmask 0xffffff00 broadcast 192.168.1.255\n";
+        let addresses = parse_ifconfig(output);
+        assert_eq!(addresses.len(), 3);
+        assert_eq!(addresses[1].interface, "lo0");
+        assert_eq!(addresses[1].ip, "fe80::1".parse::<IpAddr>().unwrap());
+        assert_eq!(addresses[2].interface, "en0");
+        assert_eq!(addresses[2].ip, "192.168.1.20".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn test_parse_get_net_ip_address() {
+        let output = "Ethernet 2|10.0.0.5\r\nLoopback Pseudo-Interface 1|::1\r\n\
+                      Ethernet 2|fe80::5%12\r\n\r\n";
+        let addresses = parse_get_net_ip_address(output);
+        assert_eq!(addresses.len(), 3);
+        assert_eq!(addresses[0].interface, "Ethernet 2");
+        assert_eq!(addresses[2].ip, "fe80::5".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn test_find_program() {
+        assert!(find_program("sh").is_some());
+        assert!(find_program("prometheus-agents-setup-nonexistent").is_none());
+    }
 }